@@ -1,6 +1,7 @@
 #![no_std]
 #![no_main]
-use core::{arch::asm, panic::PanicInfo};
+use core::{arch::asm, fmt::{self, Write}, hint::black_box, panic::PanicInfo};
+use tethys_lib::{Descriptor, namespace, thread};
 #[panic_handler]
 fn panic(_info: &PanicInfo) -> ! {
     loop {
@@ -10,11 +11,175 @@ fn panic(_info: &PanicInfo) -> ! {
     }
 }
 static DEBUGCON_PORT: u16 = 0xe9;
-pub extern "C" fn _start() -> ! {
-    for byte in "\nhello from kickstart process!".as_bytes() {
+const NAMESPACE: &str = include_str!("../namespace");
+// more names than procfs has files, so kickstart's own directory lists all of them
+const PROC_LISTED: usize = 16;
+const PROC_FILE: &[u8] = b"threads";
+// enough threads to keep four processors busy, each spinning for long enough to be preempted
+// and migrated many times over
+const SPREAD_THREADS: usize = 4;
+const SPREAD_ITERATIONS: usize = 200_000_000;
+// more threads than processors, so that threads using the vector registers share processors
+// and are preempted in the middle of their loops
+const SIMD_THREADS: usize = 8;
+const SIMD_ITERATIONS: usize = 50_000_000;
+struct Debugcon;
+impl Write for Debugcon {
+    fn write_str(&mut self, string: &str) -> fmt::Result {
+        for byte in string.as_bytes() {
             unsafe {
                 asm!("out dx, al", in("dx") DEBUGCON_PORT, in("al") *byte, options(nomem, nostack, preserves_flags));
             }
+        }
+        Ok(())
+    }
+}
+fn spin(iterations: usize) -> usize {
+    let mut count = 0;
+    while count < iterations {
+        count = black_box(count + 1);
+    }
+    0
+}
+// fills xmm0 to xmm3 with a pattern made from the seed, then keeps checking them against it,
+// counting every time one has changed. the target is soft-float, so the compiler never touches
+// the vector registers and they are not declared to the asm block, which could not name them
+fn simd(seed: usize) -> usize {
+    let mismatches: usize;
+    unsafe {
+        asm!(
+            "movq xmm0, {seed}",
+            "punpcklqdq xmm0, xmm0",
+            "movdqa xmm1, xmm0",
+            "movdqa xmm2, xmm0",
+            "movdqa xmm3, xmm0",
+            "2:",
+            "movq xmm4, {seed}",
+            "punpcklqdq xmm4, xmm4",
+            "movdqa xmm5, xmm4",
+            "pcmpeqd xmm5, xmm0",
+            "movdqa xmm6, xmm4",
+            "pcmpeqd xmm6, xmm1",
+            "pand xmm5, xmm6",
+            "movdqa xmm6, xmm4",
+            "pcmpeqd xmm6, xmm2",
+            "pand xmm5, xmm6",
+            "pcmpeqd xmm4, xmm3",
+            "pand xmm5, xmm4",
+            "pmovmskb {mask:e}, xmm5",
+            "cmp {mask:e}, 0xffff",
+            "je 3f",
+            "inc {mismatches}",
+            "3:",
+            "dec {count}",
+            "jnz 2b",
+            seed = in(reg) seed,
+            count = inout(reg) SIMD_ITERATIONS => _,
+            mask = out(reg) _,
+            mismatches = inout(reg) 0usize => mismatches,
+            options(nomem, nostack),
+        );
+    }
+    mismatches
+}
+// threads with different patterns in the same vector registers. any mismatch means a thread
+// was resumed with vector state that was not its own
+fn check_simd() -> bool {
+    let mut handles = [const { None }; SIMD_THREADS];
+    for (index, handle) in handles.iter_mut().enumerate() {
+        let seed = 0x0101_0101_0101_0101 * (index + 1);
+        match thread::spawn(simd, seed) {
+            Ok(spawned) => *handle = Some(spawned),
+            Err(_) => return false,
+        }
+    }
+    handles
+        .into_iter()
+        .flatten()
+        .all(|handle| handle.join() == Ok(0))
+}
+// cpu-bound threads that never block, so only preemption and rebalancing can spread them out.
+// the kernel reports how busy each processor was once kickstart exits
+fn check_spread() -> bool {
+    let mut handles = [const { None }; SPREAD_THREADS];
+    for handle in handles.iter_mut() {
+        match thread::spawn(spin, SPREAD_ITERATIONS) {
+            Ok(spawned) => *handle = Some(spawned),
+            Err(_) => return false,
+        }
+    }
+    handles
+        .into_iter()
+        .flatten()
+        .all(|handle| handle.join() == Ok(0))
+}
+// makes every binding in kickstart's namespace file through the root, reporting each line that
+// is wrong or could not be bound and carrying on with the rest
+fn build_namespace(root: &mut Descriptor) -> bool {
+    let mut built = true;
+    for binding in namespace::parse(NAMESPACE) {
+        let binding = match binding {
+            Ok(binding) => binding,
+            Err(error) => {
+                let _ = write!(Debugcon, "\nbad kickstart namespace file, {}!", error);
+                built = false;
+                continue;
+            }
         };
-    panic!()
+        let bound = root
+            .walk(binding.from)
+            .and_then(|from| root.bind(&from, binding.mask, binding.order, binding.to));
+        if let Err(error) = bound {
+            let _ = write!(
+                Debugcon,
+                "\nfailed to bind {} onto {}, {:?}!",
+                binding.from, binding.to, error
+            );
+            built = false;
+        }
+    }
+    built
+}
+// a thread can be kept to the first processor, which is always online, but not to none at all
+fn check_affinity() -> bool {
+    let Ok(handle) = thread::spawn(spin, SPREAD_ITERATIONS / 100) else {
+        return false;
+    };
+    let refused = handle.set_affinity(0).is_err();
+    let pinned = handle.set_affinity(1).is_ok();
+    handle.join() == Ok(0) && refused && pinned
+}
+// kickstart's bindings are made over the kernel's, so they must leave procfs reachable at /proc
+fn check_proc(root: &mut Descriptor) -> bool {
+    let Ok(mut proc) = root.walk("/proc") else {
+        return false;
+    };
+    proc.list(PROC_LISTED)
+        .is_ok_and(|listing| listing.names().iter().any(|name| name == PROC_FILE))
+}
+// kickstart is started with the index of its workspace descriptor, on the root
+#[unsafe(no_mangle)]
+pub extern "C" fn _start(workspace: usize) -> ! {
+    let _ = write!(Debugcon, "\nhello from kickstart process!");
+    let mut root = Descriptor::from_index(workspace);
+    if !build_namespace(&mut root) {
+        thread::exit(1);
+    }
+    if !check_proc(&mut root) {
+        let _ = write!(Debugcon, "\nprocfs is no longer bound onto /proc!");
+        thread::exit(1);
+    }
+    if !check_spread() {
+        let _ = write!(Debugcon, "\nspread check threads did not all finish!");
+        thread::exit(1);
+    }
+    if !check_affinity() {
+        let _ = write!(Debugcon, "\naffinity check thread was not kept to the first processor!");
+        thread::exit(1);
+    }
+    if !check_simd() {
+        let _ = write!(Debugcon, "\nsimd check threads saw vector registers change under them!");
+        thread::exit(1);
+    }
+    thread::exit(0)
 }
@@ -1,8 +1,42 @@
-use crate::{mapping::physical_to_virtual_address, port, println};
-use core::ptr::NonNull;
+use crate::{
+    mapping::physical_to_virtual_address,
+    pci::{self, PCI_DEVICES},
+    port, println, time,
+};
+use acpi::aml::{
+    AmlError, Interpreter,
+    namespace::{AmlName, NamespaceLevelKind},
+    object::{Object, WrappedObject},
+    pci_routing::{PciRoutingTable, Pin},
+};
+use alloc::{vec, vec::Vec};
+use acpi::{PciAddress, platform::ProcessorState};
+use core::{arch::x86_64::__cpuid, ptr::NonNull, str::FromStr};
 use spinning_top::{RwSpinlock, Spinlock};
+const AML_TIMEOUT_FOREVER: u16 = 0xffff;
+// \_PIC argument selecting the apic interrupt model over the legacy 8259 pic
+const PIC_MODE_APIC: u64 = 1;
+const PCI_HOST_BRIDGE_HIDS: [&str; 2] = ["PNP0A03", "PNP0A08"];
+// 33db4d5b-1ff7-401c-9657-7441c03dd766 in the mixed-endian byte order _OSC expects
+const PCI_OSC_UUID: [u8; 16] = [
+    0x5b, 0x4d, 0xdb, 0x33, 0xf7, 0x1f, 0x1c, 0x40, 0x96, 0x57, 0x74, 0x41, 0xc0, 0x3d, 0xd7, 0x66,
+];
+const PCI_OSC_REVISION: u64 = 1;
+// extended configuration space, segment groups and msi are supported, and no control is
+// requested over hotplug, power management or error reporting
+const PCI_OSC_SUPPORT: u32 = (1 << 0) | (1 << 3) | (1 << 4);
+const PCI_OSC_CONTROL: u32 = 0;
+struct AmlMutex {
+    owner: Option<u32>,
+    depth: u32,
+}
+static AML_MUTEXES: Spinlock<Vec<AmlMutex>> = Spinlock::new(Vec::new());
+// the initial local apic id identifies the acquiring processor, as AML mutexes are reentrant
+fn processor_id() -> u32 {
+    __cpuid(1).ebx >> 24
+}
 #[derive(Clone)]
-struct SystemAcpiHandler {}
+pub struct SystemAcpiHandler {}
 impl acpi::Handler for SystemAcpiHandler {
     unsafe fn map_physical_region<T>(
         &self,
@@ -83,56 +117,104 @@ impl acpi::Handler for SystemAcpiHandler {
     }
 
     fn read_pci_u8(&self, address: acpi::PciAddress, offset: u16) -> u8 {
-        todo!()
+        pci::read_u8(address, offset)
     }
 
     fn read_pci_u16(&self, address: acpi::PciAddress, offset: u16) -> u16 {
-        todo!()
+        pci::read_u16(address, offset)
     }
 
     fn read_pci_u32(&self, address: acpi::PciAddress, offset: u16) -> u32 {
-        todo!()
+        pci::read_u32(address, offset)
     }
 
     fn write_pci_u8(&self, address: acpi::PciAddress, offset: u16, value: u8) {
-        todo!()
+        pci::write_u8(address, offset, value)
     }
 
     fn write_pci_u16(&self, address: acpi::PciAddress, offset: u16, value: u16) {
-        todo!()
+        pci::write_u16(address, offset, value)
     }
 
     fn write_pci_u32(&self, address: acpi::PciAddress, offset: u16, value: u32) {
-        todo!()
+        pci::write_u32(address, offset, value)
     }
 
     fn nanos_since_boot(&self) -> u64 {
-        todo!()
+        time::nanos_since_boot()
     }
 
     fn stall(&self, microseconds: u64) {
-        todo!()
+        time::stall(microseconds)
     }
 
     fn sleep(&self, milliseconds: u64) {
-        todo!()
+        time::sleep(milliseconds)
     }
 
     fn create_mutex(&self) -> acpi::Handle {
-        todo!()
+        let mut mutexes = AML_MUTEXES.lock();
+        mutexes.push(AmlMutex {
+            owner: None,
+            depth: 0,
+        });
+        acpi::Handle((mutexes.len() - 1) as u32)
     }
 
-    fn acquire(&self, mutex: acpi::Handle, timeout: u16) -> Result<(), acpi::aml::AmlError> {
-        todo!()
+    fn acquire(&self, mutex: acpi::Handle, timeout: u16) -> Result<(), AmlError> {
+        let owner = processor_id();
+        let deadline = (timeout != AML_TIMEOUT_FOREVER)
+            .then(|| time::nanos_since_boot() + timeout as u64 * 1_000_000);
+        loop {
+            {
+                let mut mutexes = AML_MUTEXES.lock();
+                let Some(mutex) = mutexes.get_mut(mutex.0 as usize) else {
+                    println!("AML attempted to acquire a mutex that was never created!");
+                    return Err(AmlError::LibUnimplemented);
+                };
+                match mutex.owner {
+                    None => {
+                        mutex.owner = Some(owner);
+                        mutex.depth = 1;
+                        return Ok(());
+                    }
+                    Some(current_owner) if current_owner == owner => {
+                        mutex.depth += 1;
+                        return Ok(());
+                    }
+                    Some(..) => {}
+                }
+            }
+            if deadline.is_some_and(|deadline| time::nanos_since_boot() >= deadline) {
+                return Err(AmlError::MutexAcquireTimeout);
+            }
+            core::hint::spin_loop();
+        }
     }
 
+    // a release the firmware gets wrong is reported and ignored, as nothing it does should be
+    // able to bring the kernel down
     fn release(&self, mutex: acpi::Handle) {
-        todo!()
+        let mut mutexes = AML_MUTEXES.lock();
+        let Some(mutex) = mutexes.get_mut(mutex.0 as usize) else {
+            println!("AML attempted to release a mutex that was never created!");
+            return;
+        };
+        if mutex.owner != Some(processor_id()) {
+            println!("AML attempted to release a mutex it does not own!");
+            return;
+        }
+        mutex.depth -= 1;
+        if mutex.depth == 0 {
+            mutex.owner = None;
+        }
     }
 }
 static SYSTEM_ACPI_HANDLER: SystemAcpiHandler = SystemAcpiHandler {};
 pub static ACPI_PLATFORM: RwSpinlock<Option<acpi::platform::AcpiPlatform<SystemAcpiHandler>>> =
     RwSpinlock::new(None);
+pub static AML_INTERPRETER: RwSpinlock<Option<Interpreter<SystemAcpiHandler>>> =
+    RwSpinlock::new(None);
 pub static PROCESSOR_COUNT: RwSpinlock<Option<usize>> = RwSpinlock::new(None);
 pub fn bootstrap_initialise(boot_info: &mut bootloader_api::BootInfo) {
     println!(
@@ -174,7 +256,9 @@ pub fn bootstrap_initialise(boot_info: &mut bootloader_api::BootInfo) {
             .as_ref()
             .expect("ACPI platform does not contain processor info!")
             .application_processors
-            .len()
+            .iter()
+            .filter(|processor| processor.state != ProcessorState::Disabled)
+            .count()
             + 1,
     );
     println!(
@@ -182,3 +266,199 @@ pub fn bootstrap_initialise(boot_info: &mut bootloader_api::BootInfo) {
         PROCESSOR_COUNT.read().unwrap()
     );
 }
+fn eisa_id(id: &str) -> u64 {
+    let bytes = id.as_bytes();
+    let compressed = ((bytes[0] - 0x40) as u16) << 10
+        | ((bytes[1] - 0x40) as u16) << 5
+        | (bytes[2] - 0x40) as u16;
+    let product = u16::from_str_radix(&id[3..7], 16).unwrap_or(0);
+    (compressed.swap_bytes() as u32 | (product.swap_bytes() as u32) << 16) as u64
+}
+fn is_pci_host_bridge(interpreter: &Interpreter<SystemAcpiHandler>, device: &AmlName) -> bool {
+    ["_HID", "_CID"].into_iter().any(|name| {
+        let Ok(path) = AmlName::from_str(name).and_then(|name| name.resolve(device)) else {
+            return false;
+        };
+        match interpreter.evaluate_if_present(path, vec![]) {
+            Ok(Some(id)) => match &*id {
+                Object::Integer(id) => PCI_HOST_BRIDGE_HIDS
+                    .into_iter()
+                    .any(|hid| eisa_id(hid) == *id),
+                Object::String(id) => PCI_HOST_BRIDGE_HIDS.contains(&id.as_str()),
+                _ => false,
+            },
+            _ => false,
+        }
+    })
+}
+fn evaluate_integer(
+    interpreter: &Interpreter<SystemAcpiHandler>,
+    device: &AmlName,
+    name: &str,
+) -> Option<u64> {
+    let path = AmlName::from_str(name).ok()?.resolve(device).ok()?;
+    match &*interpreter.evaluate_if_present(path, vec![]).ok()?? {
+        Object::Integer(value) => Some(*value),
+        _ => None,
+    }
+}
+fn negotiate_pci_osc(interpreter: &Interpreter<SystemAcpiHandler>, bridge: &AmlName) {
+    let Ok(path) = AmlName::from_str("_OSC").and_then(|name| name.resolve(bridge)) else {
+        return;
+    };
+    let capabilities = [0, PCI_OSC_SUPPORT, PCI_OSC_CONTROL]
+        .into_iter()
+        .flat_map(u32::to_le_bytes)
+        .collect::<Vec<u8>>();
+    match interpreter.evaluate_if_present(
+        path,
+        vec![
+            WrappedObject::new(Object::Buffer(PCI_OSC_UUID.to_vec())),
+            WrappedObject::new(Object::Integer(PCI_OSC_REVISION)),
+            WrappedObject::new(Object::Integer(3)),
+            WrappedObject::new(Object::Buffer(capabilities)),
+        ],
+    ) {
+        Ok(Some(result)) => match &*result {
+            Object::Buffer(result) if result.len() >= 12 => println!(
+                "negotiated _OSC for {}, status 0x{:x}, granted control 0x{:x}...",
+                bridge,
+                u32::from_le_bytes(result[0..4].try_into().unwrap()),
+                u32::from_le_bytes(result[8..12].try_into().unwrap())
+            ),
+            _ => println!("_OSC for {} returned a malformed buffer!", bridge),
+        },
+        Ok(None) => println!(
+            "{} has no _OSC, assuming firmware-default pci control...",
+            bridge
+        ),
+        Err(error) => println!("failed to evaluate _OSC for {}: {:?}!", bridge, error),
+    }
+}
+// a device behind pci-to-pci bridges raises its interrupt through the pin of the bridge on the
+// host bridge's bus, each bridge rotating the pin by the device number below it. devices that
+// never reach the host bridge's bus belong to another host bridge and are left to it
+fn swizzle(
+    bridges: &[(u8, PciAddress)],
+    root_bus: u8,
+    mut address: PciAddress,
+    mut pin: u8,
+) -> Option<(PciAddress, Pin)> {
+    for _ in 0..=u8::MAX {
+        if address.bus() == root_bus {
+            return Some((
+                address,
+                match pin {
+                    0 => Pin::IntA,
+                    1 => Pin::IntB,
+                    2 => Pin::IntC,
+                    _ => Pin::IntD,
+                },
+            ));
+        }
+        let (_, bridge) = bridges
+            .iter()
+            .find(|(secondary_bus, _)| *secondary_bus == address.bus())?;
+        pin = (pin + address.device()) % 4;
+        address = *bridge;
+    }
+    None
+}
+fn route_pci_interrupts(interpreter: &Interpreter<SystemAcpiHandler>, bridge: &AmlName) {
+    let Ok(prt_path) = AmlName::from_str("_PRT").and_then(|name| name.resolve(bridge)) else {
+        return;
+    };
+    let routing_table = match PciRoutingTable::from_prt_path(prt_path, interpreter) {
+        Ok(routing_table) => routing_table,
+        Err(error) => {
+            println!("failed to decode _PRT for {}: {:?}!", bridge, error);
+            return;
+        }
+    };
+    let segment = evaluate_integer(interpreter, bridge, "_SEG").unwrap_or(0) as u16;
+    let bus = evaluate_integer(interpreter, bridge, "_BBN").unwrap_or(0) as u8;
+    let bridges = PCI_DEVICES
+        .read()
+        .iter()
+        .filter_map(|device| Some((device.secondary_bus?, device.address)))
+        .filter(|(_, address)| address.segment() == segment)
+        .collect::<Vec<(u8, PciAddress)>>();
+    for device in PCI_DEVICES
+        .write()
+        .iter_mut()
+        .filter(|device| device.address.segment() == segment)
+    {
+        if !(1..=4).contains(&device.interrupt_pin) {
+            continue;
+        }
+        let Some((slot, pin)) = swizzle(&bridges, bus, device.address, device.interrupt_pin - 1)
+        else {
+            continue;
+        };
+        match routing_table.route(
+            slot.device() as u16,
+            slot.function() as u16,
+            pin,
+            interpreter,
+        ) {
+            Ok(descriptor) => {
+                device.gsi = Some(descriptor.irq);
+                println!(
+                    "routed pci device {} pin {:?} through {} to gsi {}...",
+                    device.address, pin, slot, descriptor.irq
+                );
+            }
+            Err(error) => println!(
+                "failed to route pci device {} pin {:?}: {:?}!",
+                device.address, pin, error
+            ),
+        }
+    }
+}
+pub fn initialise(_boot_info: &mut bootloader_api::BootInfo) {
+    // without an interpreter the kernel carries on, with interrupts left unrouted and only the
+    // fallback shutdown and reboot mechanisms
+    let interpreter = match Interpreter::new_from_platform(
+        ACPI_PLATFORM
+            .read()
+            .as_ref()
+            .expect("ACPI platform not initialised before AML initialisation!"),
+    ) {
+        Ok(interpreter) => interpreter,
+        Err(error) => {
+            println!("failed to load DSDT/SSDT AML tables: {:?}!", error);
+            return;
+        }
+    };
+    println!("loaded DSDT and SSDT namespace...");
+    interpreter.initialize_namespace();
+    println!("initialised AML namespace devices...");
+    match interpreter.evaluate_if_present(
+        AmlName::from_str("\\_PIC").unwrap(),
+        vec![WrappedObject::new(Object::Integer(PIC_MODE_APIC))],
+    ) {
+        Ok(Some(..)) => println!("informed firmware of apic interrupt model via \\_PIC..."),
+        Ok(None) => println!("firmware has no \\_PIC method, assuming apic interrupt model..."),
+        Err(error) => println!("failed to evaluate \\_PIC: {:?}!", error),
+    }
+    let mut devices = Vec::new();
+    let _ = interpreter
+        .namespace
+        .lock()
+        .clone()
+        .traverse(|path, level| {
+            if level.kind == NamespaceLevelKind::Device {
+                devices.push(path.clone());
+            }
+            Ok(true)
+        });
+    for bridge in devices
+        .iter()
+        .filter(|device| is_pci_host_bridge(&interpreter, device))
+    {
+        println!("found pci host bridge {}...", bridge);
+        negotiate_pci_osc(&interpreter, bridge);
+        route_pci_interrupts(&interpreter, bridge);
+    }
+    let _ = AML_INTERPRETER.write().insert(interpreter);
+}
@@ -1,18 +1,91 @@
 use crate::{
     acpi::PROCESSOR_COUNT,
     gdt::{self, Selectors},
+    mapping::HIGHER_HALF,
     println,
-    proc::{Process, Thread}, scheduler::ProcessorScheduler,
+    proc::Thread,
+    scheduler::ProcessorScheduler,
+};
+use alloc::{boxed::Box, vec::Vec};
+use core::{
+    arch::asm,
+    ptr,
+    sync::atomic::{AtomicPtr, AtomicU64},
 };
-use alloc::{boxed::Box, collections::{binary_heap::BinaryHeap, vec_deque::VecDeque}, sync::{Arc, Weak}, vec::Vec};
 use spinning_top::RwSpinlock;
-use x86_64::structures::gdt::GlobalDescriptorTable;
+use x86_64::{
+    PrivilegeLevel, VirtAddr,
+    registers::model_specific::{GsBase, KernelGsBase},
+    structures::{gdt::GlobalDescriptorTable, idt::InterruptStackFrame, tss::TaskStateSegment},
+};
 pub struct ProcessorData {
     pub gdt_selectors: (&'static GlobalDescriptorTable, Selectors),
+    pub task_state: &'static mut TaskStateSegment,
     pub scheduler: ProcessorScheduler,
+    pub online: bool,
 }
-pub static PROCESSOR_DATA_VEC: RwSpinlock<Vec<&'static mut RwSpinlock<ProcessorData>>> =
+pub static PROCESSOR_DATA_VEC: RwSpinlock<Vec<&'static RwSpinlock<ProcessorData>>> =
     RwSpinlock::new(Vec::new());
+// the block gs points at while in the kernel, the syscall entry path addresses the stack fields
+// by offset before it has a stack, so the layout is fixed
+#[repr(C)]
+pub struct ProcessorLocal {
+    this: *const ProcessorLocal,
+    pub processor: usize,
+    pub syscall_stack_top: AtomicU64,
+    pub user_stack: AtomicU64,
+    // the idle loop's stack pointer while a thread is running on the processor
+    pub scheduler_context: AtomicU64,
+    pub current_thread: AtomicPtr<RwSpinlock<Thread>>,
+    pub data: &'static RwSpinlock<ProcessorData>,
+}
+pub fn local() -> &'static ProcessorLocal {
+    let this: *const ProcessorLocal;
+    unsafe {
+        asm!("mov {}, gs:[0]", out(reg) this, options(nostack, preserves_flags, readonly));
+        &*this
+    }
+}
+// must run after the processor's gdt is loaded, as loading the gs selector clears the gs base
+pub fn install_local(processor: usize) {
+    let data = PROCESSOR_DATA_VEC.read()[processor];
+    let local = Box::leak(Box::new(ProcessorLocal {
+        this: ptr::null(),
+        processor,
+        syscall_stack_top: AtomicU64::new(0),
+        user_stack: AtomicU64::new(0),
+        scheduler_context: AtomicU64::new(0),
+        current_thread: AtomicPtr::new(ptr::null_mut()),
+        data,
+    }));
+    local.this = local;
+    data.write().online = true;
+    GsBase::write(VirtAddr::from_ptr(local));
+    KernelGsBase::write(VirtAddr::zero());
+}
+// swaps in the kernel gs base for the lifetime of an interrupt handler if the interrupted code
+// had the user's installed. code interrupted in ring 3 always has, while in ring 0 only the
+// syscall entry and exit paths outside their swapgs run with the user's base. user code cannot
+// write its gs base directly, as cr4.fsgsbase is left clear, and a selector load only gives it a
+// base from the gdt, so the user's base never points into the higher half
+pub struct KernelGs(bool);
+impl KernelGs {
+    pub fn enter(stack_frame: &InterruptStackFrame) -> KernelGs {
+        let swapped = stack_frame.code_segment.rpl() == PrivilegeLevel::Ring3
+            || GsBase::read().as_u64() < HIGHER_HALF;
+        if swapped {
+            unsafe { asm!("swapgs", options(nostack, preserves_flags)) };
+        }
+        KernelGs(swapped)
+    }
+}
+impl Drop for KernelGs {
+    fn drop(&mut self) {
+        if self.0 {
+            unsafe { asm!("swapgs", options(nostack, preserves_flags)) };
+        }
+    }
+}
 pub fn initialise(_boot_info: &mut bootloader_api::BootInfo) {
     let mut processor_data = PROCESSOR_DATA_VEC.write();
     processor_data.append(
@@ -20,18 +93,22 @@ pub fn initialise(_boot_info: &mut bootloader_api::BootInfo) {
             .read()
             .expect("processors not counted before per-processor initialisation!"))
             .map(|index| {
-                let gdt_selectors = gdt::new(index);
-                Box::leak(Box::new(RwSpinlock::new(ProcessorData {
-                    gdt_selectors: gdt_selectors,
+                let (gdt, selectors, task_state) = gdt::new(index);
+                &*Box::leak(Box::new(RwSpinlock::new(ProcessorData {
+                    gdt_selectors: (gdt, selectors),
+                    task_state,
                     scheduler: ProcessorScheduler::new(),
+                    online: false,
                 })))
             })
-            .collect::<Vec<&'static mut RwSpinlock<ProcessorData>>>(),
+            .collect::<Vec<&'static RwSpinlock<ProcessorData>>>(),
     );
     println!(
         "initialised processor data for {} processors...",
         processor_data.len()
     );
-    let bootstrap_data = processor_data.get(0).expect("bootstrap processor could not find processor data!").read();
-    unsafe {gdt::load(&bootstrap_data.gdt_selectors)};
+    unsafe { gdt::load(&processor_data[0].read().gdt_selectors) };
+    drop(processor_data);
+    install_local(0);
+    println!("installed processor-local block for bootstrap processor...");
 }
@@ -3,7 +3,7 @@ use alloc::fmt;
 use spinning_top::Spinlock;
 use crate::port;
 struct DebugconWriter;
-static DEBUGCON_PORT: u16 = 0xe9;
+pub static DEBUGCON_PORT: u16 = 0xe9;
 static DEBUGCON_WRITER: Spinlock<DebugconWriter> = Spinlock::new(DebugconWriter);
 impl fmt::Write for DebugconWriter {
     fn write_str(&mut self, s: &str) -> core::fmt::Result {
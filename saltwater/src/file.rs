@@ -0,0 +1,127 @@
+use crate::{
+    println,
+    proc::{KernelServer, Server, ServerKind, State},
+};
+use alloc::{boxed::Box, sync::Arc, vec::Vec};
+use spinning_top::RwSpinlock;
+pub use tethys_wire::FileError;
+#[derive(Clone, Copy, Debug)]
+pub enum Seek {
+    Forward(u64),
+    Backward(u64),
+    Start(u64),
+    End(u64),
+}
+impl Seek {
+    pub fn apply(self, position: u64, length: u64) -> Result<u64, FileError> {
+        match self {
+            Seek::Forward(offset) => position.checked_add(offset),
+            Seek::Backward(offset) => position.checked_sub(offset),
+            Seek::Start(offset) => Some(offset),
+            Seek::End(offset) => length.checked_sub(offset),
+        }
+        .filter(|new_position| *new_position <= length)
+        .ok_or(FileError::Invalid)
+    }
+    pub fn permitted(self, state: State) -> bool {
+        match self {
+            Seek::Forward(..) => state.seek_forward,
+            Seek::Backward(..) => state.seek_backward,
+            Seek::Start(..) => state.seek_start,
+            Seek::End(..) => state.seek_end,
+        }
+    }
+}
+// a kernel-served file is the server-side half of a descriptor, every method defaults to
+// unsupported so that servers only implement what their files actually do
+pub trait KernelFile: Send + Sync {
+    fn state(&self) -> State;
+    fn walk(&mut self, _name: &[u8]) -> Result<Box<dyn KernelFile>, FileError> {
+        Err(FileError::Unsupported)
+    }
+    fn list(&mut self, _count: usize, _advance: bool) -> Result<Vec<Box<[u8]>>, FileError> {
+        Err(FileError::Unsupported)
+    }
+    fn list_seek(&mut self, _seek: Seek) -> Result<u64, FileError> {
+        Err(FileError::Unsupported)
+    }
+    fn list_tell(&mut self) -> Result<u64, FileError> {
+        Err(FileError::Unsupported)
+    }
+    fn make(&mut self, _state: State, _name: &[u8]) -> Result<Box<dyn KernelFile>, FileError> {
+        Err(FileError::Unsupported)
+    }
+    fn remove(&mut self, _name: &[u8]) -> Result<(), FileError> {
+        Err(FileError::Unsupported)
+    }
+    fn rename(&mut self, _new_name: &[u8]) -> Result<(), FileError> {
+        Err(FileError::Unsupported)
+    }
+    fn read(&mut self, _length: usize, _advance: bool) -> Result<Vec<u8>, FileError> {
+        Err(FileError::Unsupported)
+    }
+    fn insert(&mut self, _content: &[u8]) -> Result<usize, FileError> {
+        Err(FileError::Unsupported)
+    }
+    fn overwrite(&mut self, _content: &[u8]) -> Result<usize, FileError> {
+        Err(FileError::Unsupported)
+    }
+    fn truncate(&mut self, _length: usize) -> Result<usize, FileError> {
+        Err(FileError::Unsupported)
+    }
+    fn seek(&mut self, _seek: Seek) -> Result<u64, FileError> {
+        Err(FileError::Unsupported)
+    }
+    fn tell(&mut self) -> Result<u64, FileError> {
+        Err(FileError::Unsupported)
+    }
+    fn lock(&mut self) -> Result<(), FileError> {
+        Err(FileError::Unsupported)
+    }
+}
+pub trait KernelFilesystem: Send + Sync {
+    fn attach(&self) -> Box<dyn KernelFile>;
+}
+// a directory whose entries are known up front, tracking its own list head
+#[derive(Default)]
+pub struct Listing {
+    pub position: u64,
+}
+impl Listing {
+    pub const fn new() -> Listing {
+        Listing { position: 0 }
+    }
+    pub fn list(
+        &mut self,
+        entries: Vec<Box<[u8]>>,
+        count: usize,
+        advance: bool,
+    ) -> Result<Vec<Box<[u8]>>, FileError> {
+        let listed = entries
+            .into_iter()
+            .skip(self.position as usize)
+            .take(count)
+            .collect::<Vec<Box<[u8]>>>();
+        if advance {
+            self.position += listed.len() as u64;
+        }
+        Ok(listed)
+    }
+    pub fn seek(&mut self, seek: Seek, length: usize) -> Result<u64, FileError> {
+        self.position = seek.apply(self.position, length as u64)?;
+        Ok(self.position)
+    }
+}
+pub static KERNEL_SERVERS: RwSpinlock<Vec<(&'static [u8], Arc<Server>)>> =
+    RwSpinlock::new(Vec::new());
+pub fn register(name: &'static [u8], filesystem: &'static dyn KernelFilesystem) -> Arc<Server> {
+    let server = Arc::new(Server {
+        kind: ServerKind::Kernel(KernelServer { filesystem }),
+    });
+    KERNEL_SERVERS.write().push((name, server.clone()));
+    println!(
+        "registered kernel server \"{}\"...",
+        core::str::from_utf8(name).unwrap_or("?")
+    );
+    server
+}
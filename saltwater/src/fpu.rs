@@ -0,0 +1,211 @@
+use crate::{
+    core::{KernelGs, local},
+    println,
+};
+use alloc::{
+    alloc::{alloc_zeroed, dealloc, handle_alloc_error},
+    sync::Arc,
+};
+use core::{
+    alloc::Layout,
+    arch::{asm, x86_64::__cpuid_count},
+    ptr::NonNull,
+    sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering},
+};
+use x86_64::{
+    registers::{
+        control::{Cr0, Cr0Flags, Cr4, Cr4Flags},
+        xcontrol::{XCr0, XCr0Flags},
+    },
+    structures::idt::InterruptStackFrame,
+};
+const CPUID_FEATURE_LEAF: u32 = 0x01;
+const CPUID_XSAVE: u32 = 1 << 26;
+const CPUID_EXTENDED_STATE_LEAF: u32 = 0x0d;
+const FXSAVE_SIZE: usize = 512;
+const STATE_ALIGNMENT: usize = 64;
+const FCW_OFFSET: usize = 0;
+const MXCSR_OFFSET: usize = 24;
+// all x87 exceptions masked, double extended precision, round to nearest
+const FCW_DEFAULT: u16 = 0x037f;
+// all simd exceptions masked, round to nearest
+const MXCSR_DEFAULT: u32 = 0x1f80;
+// only user-visible register state is managed here, supervisor and bounds state is left disabled
+const MANAGED_COMPONENTS: XCr0Flags = XCr0Flags::X87
+    .union(XCr0Flags::SSE)
+    .union(XCr0Flags::AVX)
+    .union(XCr0Flags::OPMASK)
+    .union(XCr0Flags::ZMM_HI256)
+    .union(XCr0Flags::HI16_ZMM);
+static XSAVE: AtomicBool = AtomicBool::new(false);
+static COMPONENTS: AtomicU64 = AtomicU64::new(0);
+static STATE_SIZE: AtomicUsize = AtomicUsize::new(0);
+// a thread's x87, sse and avx registers while they are not loaded, in xsave format when the
+// processor supports it and fxsave format otherwise
+pub struct ExtendedState(NonNull<u8>);
+unsafe impl Send for ExtendedState {}
+unsafe impl Sync for ExtendedState {}
+fn state_layout() -> Layout {
+    let size = STATE_SIZE.load(Ordering::Relaxed);
+    if size == 0 {
+        panic!("extended state allocated before fpu initialisation!");
+    }
+    Layout::from_size_align(size, STATE_ALIGNMENT).expect("extended state layout is invalid!")
+}
+impl ExtendedState {
+    // a zeroed xsave header marks every component as being in its initial configuration, only
+    // the control words in the legacy area need sensible values for fxrstor and xrstor alike
+    pub fn new() -> ExtendedState {
+        let layout = state_layout();
+        let pointer = NonNull::new(unsafe { alloc_zeroed(layout) })
+            .unwrap_or_else(|| handle_alloc_error(layout));
+        unsafe {
+            (pointer.as_ptr().add(FCW_OFFSET) as *mut u16).write(FCW_DEFAULT);
+            (pointer.as_ptr().add(MXCSR_OFFSET) as *mut u32).write(MXCSR_DEFAULT);
+        }
+        ExtendedState(pointer)
+    }
+    pub fn save(&mut self) {
+        let components = COMPONENTS.load(Ordering::Relaxed);
+        unsafe {
+            if XSAVE.load(Ordering::Relaxed) {
+                asm!(
+                    "xsave64 [{}]",
+                    in(reg) self.0.as_ptr(),
+                    in("eax") components as u32,
+                    in("edx") (components >> 32) as u32,
+                    options(nostack, preserves_flags),
+                );
+            } else {
+                asm!("fxsave64 [{}]", in(reg) self.0.as_ptr(), options(nostack, preserves_flags));
+            }
+        }
+    }
+    pub fn restore(&self) {
+        let components = COMPONENTS.load(Ordering::Relaxed);
+        unsafe {
+            if XSAVE.load(Ordering::Relaxed) {
+                asm!(
+                    "xrstor64 [{}]",
+                    in(reg) self.0.as_ptr(),
+                    in("eax") components as u32,
+                    in("edx") (components >> 32) as u32,
+                    options(nostack, preserves_flags, readonly),
+                );
+            } else {
+                asm!(
+                    "fxrstor64 [{}]",
+                    in(reg) self.0.as_ptr(),
+                    options(nostack, preserves_flags, readonly),
+                );
+            }
+        }
+    }
+}
+impl Default for ExtendedState {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+impl Drop for ExtendedState {
+    fn drop(&mut self) {
+        unsafe { dealloc(self.0.as_ptr(), state_layout()) };
+    }
+}
+// called on every context switch, the next instruction to touch extended state raises #nm and
+// only then is the outgoing owner's state saved and the incoming thread's restored
+pub fn defer() {
+    unsafe { Cr0::update(|flags| flags.insert(Cr0Flags::TASK_SWITCHED)) };
+}
+// switching back to the thread whose state is still loaded needs no trap at all
+pub fn resume() {
+    unsafe { asm!("clts", options(nostack, preserves_flags)) };
+}
+pub extern "x86-interrupt" fn device_not_available_handler(stack_frame: InterruptStackFrame) {
+    let _kernel_gs = KernelGs::enter(&stack_frame);
+    resume();
+    let mut data_write = local().data.write();
+    let scheduler = &mut data_write.scheduler;
+    let Some(current) = scheduler.current_thread.clone() else {
+        panic!("extended state used with no thread running!");
+    };
+    if let Some(owner) = scheduler
+        .fpu_owner
+        .as_ref()
+        .and_then(|owner| owner.upgrade())
+    {
+        if Arc::ptr_eq(&owner, &current) {
+            return;
+        }
+        owner.write().extended_state.save();
+    }
+    current.read().extended_state.restore();
+    scheduler.fpu_owner = Some(Arc::downgrade(&current));
+}
+// every processor has to be configured the same way, so the bootstrap processor's choice of
+// components is applied as-is on application processors
+pub fn enable() {
+    unsafe {
+        Cr0::update(|flags| {
+            flags.remove(Cr0Flags::EMULATE_COPROCESSOR | Cr0Flags::TASK_SWITCHED);
+            flags.insert(Cr0Flags::MONITOR_COPROCESSOR | Cr0Flags::NUMERIC_ERROR);
+        });
+        Cr4::update(|flags| flags.insert(Cr4Flags::OSFXSR | Cr4Flags::OSXMMEXCPT_ENABLE));
+        if XSAVE.load(Ordering::Relaxed) {
+            Cr4::update(|flags| flags.insert(Cr4Flags::OSXSAVE));
+            XCr0::write(XCr0Flags::from_bits_truncate(
+                COMPONENTS.load(Ordering::Relaxed),
+            ));
+        }
+        asm!("fninit", options(nostack, preserves_flags));
+    }
+}
+// round-trips two register images through two state areas to check that saving one context
+// cannot clobber another before any thread relies on it
+fn self_check() -> bool {
+    let patterns = [[0x5555_5555_5555_5555u64; 2], [0xaaaa_aaaa_aaaa_aaaa; 2]];
+    let mut states = [ExtendedState::new(), ExtendedState::new()];
+    for (pattern, state) in patterns.iter().zip(states.iter_mut()) {
+        unsafe { asm!("movdqu xmm0, [{}]", in(reg) pattern.as_ptr(), options(nostack, readonly)) };
+        state.save();
+    }
+    patterns.iter().zip(states.iter()).all(|(pattern, state)| {
+        let mut loaded = [0u64; 2];
+        state.restore();
+        unsafe { asm!("movdqu [{}], xmm0", in(reg) loaded.as_mut_ptr(), options(nostack)) };
+        loaded == *pattern
+    })
+}
+pub fn initialise(_boot_info: &mut bootloader_api::BootInfo) {
+    let xsave = __cpuid_count(CPUID_FEATURE_LEAF, 0).ecx & CPUID_XSAVE != 0;
+    XSAVE.store(xsave, Ordering::Relaxed);
+    if xsave {
+        let supported = __cpuid_count(CPUID_EXTENDED_STATE_LEAF, 0);
+        let supported =
+            XCr0Flags::from_bits_truncate(supported.eax as u64 | (supported.edx as u64) << 32);
+        let mut components = supported & MANAGED_COMPONENTS;
+        // avx-512 state can only be enabled as a whole
+        if !components.contains(XCr0Flags::OPMASK | XCr0Flags::ZMM_HI256 | XCr0Flags::HI16_ZMM) {
+            components.remove(XCr0Flags::OPMASK | XCr0Flags::ZMM_HI256 | XCr0Flags::HI16_ZMM);
+        }
+        COMPONENTS.store(components.bits(), Ordering::Relaxed);
+    }
+    enable();
+    let size = if xsave {
+        // ebx reports the size needed for the components currently enabled in xcr0
+        __cpuid_count(CPUID_EXTENDED_STATE_LEAF, 0).ebx as usize
+    } else {
+        FXSAVE_SIZE
+    };
+    STATE_SIZE.store(size, Ordering::Relaxed);
+    println!(
+        "enabled extended state management via {} with 0x{:x}-byte state areas and components 0x{:x}...",
+        if xsave { "xsave" } else { "fxsave" },
+        size,
+        COMPONENTS.load(Ordering::Relaxed)
+    );
+    if !self_check() {
+        panic!("extended state did not survive a save and restore round trip!");
+    }
+    println!("verified extended state save and restore...");
+}
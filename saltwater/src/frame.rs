@@ -101,6 +101,26 @@ pub fn initialise(boot_info: &mut bootloader_api::BootInfo) {
         free_page_count * PAGE_SIZE
     );
 }
+impl BitmapPageFrameAllocator {
+    pub fn free_frames(&self) -> usize {
+        (0..self.total_frames)
+            .filter(|frame_index| self.bitmap[frame_index / 8] & (1 << (frame_index % 8)) == 0)
+            .count()
+    }
+    // real-mode code such as the ap trampoline must live in conventional memory, and frame 0
+    // holds the real-mode interrupt vector table so is never handed out here
+    pub fn allocate_frame_below(&mut self, limit: u64) -> Option<PhysFrame> {
+        let frame_index = (1..((limit / PAGE_SIZE) as usize).min(self.total_frames))
+            .find(|frame_index| self.bitmap[frame_index / 8] & (1 << (frame_index % 8)) == 0)?;
+        self.bitmap[frame_index / 8] |= 1 << (frame_index % 8);
+        let frame_address = frame_index as u64 * PAGE_SIZE;
+        unsafe {
+            (physical_to_virtual_address(frame_address) as *mut u8)
+                .write_bytes(0, PAGE_SIZE as usize)
+        };
+        Some(PhysFrame::containing_address(PhysAddr::new(frame_address)))
+    }
+}
 unsafe impl FrameAllocator<Size4KiB> for BitmapPageFrameAllocator {
     fn allocate_frame(&mut self) -> Option<PhysFrame> {
         for frame_index in self.last_allocated_frame_index..self.total_frames {
@@ -1,5 +1,6 @@
-use crate::{idt::{CRITICAL_IST_INDEX, DOUBLE_FAULT_IST_INDEX, INTERRUPT_IST_INDEX, SYSCALL_IST_INDEX}, mapping};
+use crate::{debugcon::DEBUGCON_PORT, idt::{CRITICAL_IST_INDEX, DOUBLE_FAULT_IST_INDEX, INTERRUPT_IST_INDEX, SYSCALL_IST_INDEX}, mapping};
 use alloc::boxed::Box;
+use core::mem::size_of;
 use x86_64::{
     instructions::tables::load_tss,
     registers::segmentation::{self, Segment},
@@ -8,6 +9,16 @@ use x86_64::{
         tss::TaskStateSegment,
     },
 };
+// one bit per port with a set bit denying access, and a trailing byte of all ones that the
+// processor may read past the last port
+const IO_PERMISSIONS_SIZE: usize = 0x2000 + 1;
+// user code may only write the debug console, so that programs can report how they are doing
+// before any console server exists
+#[repr(C)]
+struct TaskState {
+    segment: TaskStateSegment,
+    io_permissions: [u8; IO_PERMISSIONS_SIZE],
+}
 #[derive(Clone)]
 pub struct Selectors {
     pub kernel_code: SegmentSelector,
@@ -16,26 +27,43 @@ pub struct Selectors {
     pub user_data: SegmentSelector,
     pub task_state: SegmentSelector,
 }
-pub fn new(processor: usize) -> (&'static GlobalDescriptorTable, Selectors) {
+// the task state segment is handed back so the scheduler can point its privilege stack at the
+// kernel stack of whichever thread is running
+pub fn new(processor: usize) -> (&'static GlobalDescriptorTable, Selectors, &'static mut TaskStateSegment) {
     let mut gdt = GlobalDescriptorTable::new();
-    let mut tss = Box::new(TaskStateSegment::new());
+    let task_state = Box::leak(Box::new(TaskState {
+        segment: TaskStateSegment::new(),
+        io_permissions: [0xff; IO_PERMISSIONS_SIZE],
+    }));
+    task_state.io_permissions[DEBUGCON_PORT as usize / 8] &= !(1 << (DEBUGCON_PORT % 8));
+    task_state.segment.iomap_base = size_of::<TaskStateSegment>() as u16;
+    // the descriptor's limit has to cover the permission bitmap as well as the segment itself
+    let Descriptor::SystemSegment(low, high) =
+        (unsafe { Descriptor::tss_segment_unchecked(&task_state.segment) })
+    else {
+        unreachable!("task state segment descriptor is not a system segment!");
+    };
+    let task_state_descriptor =
+        Descriptor::SystemSegment((low & !0xffff) | (size_of::<TaskStateSegment>() + IO_PERMISSIONS_SIZE - 1) as u64, high);
+    let tss = &mut task_state.segment;
     tss.privilege_stack_table[0] = x86_64::VirtAddr::new(mapping::syscall_stack_address(processor));
     tss.interrupt_stack_table[SYSCALL_IST_INDEX] =
         x86_64::VirtAddr::new(mapping::syscall_stack_address(processor));
     tss.interrupt_stack_table[INTERRUPT_IST_INDEX] =
-        x86_64::VirtAddr::new(mapping::interrupt_stack_address(processor));
+        x86_64::VirtAddr::new(mapping::interrupt_stack_address(processor) + mapping::INTERRUPT_STACK_SIZE);
     tss.interrupt_stack_table[DOUBLE_FAULT_IST_INDEX] =
-        x86_64::VirtAddr::new(mapping::double_fault_stack_address(processor));
+        x86_64::VirtAddr::new(mapping::double_fault_stack_address(processor) + mapping::INTERRUPT_STACK_SIZE);
     tss.interrupt_stack_table[CRITICAL_IST_INDEX] =
-        x86_64::VirtAddr::new(mapping::critical_stack_address(processor));
+        x86_64::VirtAddr::new(mapping::critical_stack_address(processor) + mapping::INTERRUPT_STACK_SIZE);
     let selectors = Selectors {
         kernel_code: gdt.append(Descriptor::kernel_code_segment()),
         kernel_data: gdt.append(Descriptor::kernel_data_segment()),
-        user_code: gdt.append(Descriptor::user_code_segment()),
+        // sysret derives the user selectors from one base, expecting data before code
         user_data: gdt.append(Descriptor::user_data_segment()),
-        task_state: gdt.append(Descriptor::tss_segment(Box::leak(tss))),
+        user_code: gdt.append(Descriptor::user_code_segment()),
+        task_state: gdt.append(task_state_descriptor),
     };
-    (Box::leak(Box::new(gdt)), selectors)
+    (Box::leak(Box::new(gdt)), selectors, tss)
 }
 pub unsafe fn load(gdt_selectors: &(&'static GlobalDescriptorTable, Selectors)) {
     unsafe {
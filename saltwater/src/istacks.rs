@@ -1,8 +1,8 @@
 use crate::{
     frame::PAGE_FRAME_ALLOCATOR,
     mapping::{
-        INTERRUPT_STACK_SIZE, PAGE_SIZE, critical_stack_address, double_fault_stack_address,
-        interrupt_stack_address,
+        INTERRUPT_STACK_SIZE, PAGE_SIZE, bootstrap_stack_address, critical_stack_address,
+        double_fault_stack_address, interrupt_stack_address,
     },
     page::{KERNEL_PAGE_FLAGS, get_current_pml4, get_offset_table},
     println,
@@ -27,7 +27,10 @@ pub fn initialise(_boot_info: &mut bootloader_api::BootInfo) {
             ("interrupt", interrupt_stack_address(processor)),
             ("double fault", double_fault_stack_address(processor)),
             ("critical", critical_stack_address(processor)),
-        ] {
+        ]
+        .into_iter()
+        .chain((processor != 0).then(|| ("bootstrap", bootstrap_stack_address(processor))))
+        {
             for page in (0..(INTERRUPT_STACK_SIZE / PAGE_SIZE)).map(|x| {
                 Page::<Size4KiB>::containing_address(VirtAddr::new(stack_address + x * PAGE_SIZE))
             }) {
@@ -1,16 +1,15 @@
-use core::{sync::atomic::AtomicU64, u64};
-use alloc::{collections::vec_deque::VecDeque, sync::Arc, vec::{self, Vec}};
+use core::sync::atomic::{AtomicBool, Ordering};
+use alloc::sync::Arc;
 use elf::{ElfBytes, endian::AnyEndian};
 use spinning_top::RwSpinlock;
-use x86_64::structures::paging::PageTable;
-use crate::{println, proc::Process};
+use crate::{println, proc::{Process, ProcessStatus, REGISTER_RDI, ROOT_PRIORITY}, qemu, rootfs, scheduler};
 const KICKSTART_BYTES: &[u8] = if cfg!(debug_assertions) {
     include_bytes!("../../target/x86_64-unknown-none/debug/kickstart")
 } else {
     include_bytes!("../../target/x86_64-unknown-none/release/kickstart")
 };
-pub static mut KICKSTART_PAGE_TABLE: RwSpinlock<PageTable> = RwSpinlock::new(PageTable::new());
-pub static mut KICKSTART_ARC: RwSpinlock<Option<Arc<Process>>> = RwSpinlock::new(None);
+pub static KICKSTART_ARC: RwSpinlock<Option<Arc<Process>>> = RwSpinlock::new(None);
+static FINISHED: AtomicBool = AtomicBool::new(false);
 pub fn initialise(_boot_info: &mut bootloader_api::BootInfo) {
     println!("loading kickstart process from embedded elf...");
     let elf_bytes = ElfBytes::<AnyEndian>::minimal_parse(KICKSTART_BYTES)
@@ -21,17 +20,45 @@ pub fn initialise(_boot_info: &mut bootloader_api::BootInfo) {
     if elf_bytes.ehdr.class != elf::file::Class::ELF64 {
         println!("incorrect kickstart elf class! expected ELF64, received: ELF32!");
     }
-    /*let kickstart_proc = Arc::new(Process {
-            set_priority: AtomicU64::new(u64::MAX),
-            propagated_priority: u64::MAX,
-            parent: None,
-            pages: RwSpinlock::new(unsafe {&mut KICKSTART_PAGE_TABLE as *mut PageTable}),
-            threads: RwSpinlock::new(Vec::new()),
-            children: RwSpinlock::new(Vec::new()),
-            requests: RwSpinlock::new(Vec::new()),
-            responses: RwSpinlock::new(VecDeque::new()),
-            servers: RwSpinlock::new(Vec::new()),
-            descriptors: RwSpinlock::new(Vec::new()),
-    });
-    let thread_arc = kickstart_proc.add_thread();*/
+    let kickstart_process = Process::from_elf(None, KICKSTART_BYTES)
+        .unwrap_or_else(|error| panic!("failed to load kickstart elf: {:?}!", error));
+    kickstart_process.set_priority.store(u64::MAX, Ordering::Relaxed);
+    kickstart_process.propagated_priority.store(ROOT_PRIORITY, Ordering::Relaxed);
+    kickstart_process.propagate_priorities();
+    if let Some(template) = kickstart_process.tls_template.read().as_ref() {
+        println!("kickstart declares a 0x{:x}-byte thread-local block aligned to 0x{:x}...", template.memory_size, template.alignment);
+    }
+    let thread = kickstart_process.threads.read()[0].clone();
+    println!(
+        "created kickstart thread with entry point 0x{:x} and thread pointer 0x{:x}...",
+        thread.read().user_context.instruction_pointer,
+        thread.read().user_context.fs_base
+    );
+    #[cfg(feature = "teardown_check")]
+    crate::teardown::check(&kickstart_process);
+    let _ = KICKSTART_ARC.write().insert(kickstart_process);
+}
+// kickstart starts with a descriptor on the root, where it finds every kernel server, and builds
+// the namespace it launches processes into from there
+pub fn start() {
+    let kickstart_process = KICKSTART_ARC.read().clone().expect("kickstart started before it was loaded!");
+    let workspace = rootfs::workspace(&kickstart_process)
+        .unwrap_or_else(|error| panic!("failed to give kickstart a workspace on the root: {:?}!", error));
+    kickstart_process.threads.read()[0].write().user_context.registers[REGISTER_RDI] = workspace;
+    kickstart_process.start();
+    println!("started kickstart process with workspace descriptor {}...", workspace);
+}
+// kickstart exiting is the end of a run. idle processors look for it, and the first to see it
+// reports how the processors shared its work before leaving qemu
+pub fn check_finished() {
+    let status = match KICKSTART_ARC.read().as_ref().map(|kickstart| kickstart.lifecycle.read().status) {
+        Some(ProcessStatus::Exited(status)) => status,
+        _ => return,
+    };
+    if FINISHED.swap(true, Ordering::SeqCst) {
+        return;
+    }
+    println!("kickstart process exited with status {}...", status);
+    scheduler::report();
+    qemu::exit(if status == 0 { qemu::ExitCode::Success } else { qemu::ExitCode::Failure });
 }
@@ -0,0 +1,125 @@
+use crate::{
+    acpi::ACPI_PLATFORM, page::map_device_region, port, println, scheduler::TICK_NANOS, time,
+};
+use acpi::platform::interrupt::InterruptModel;
+use core::sync::atomic::{AtomicU32, Ordering};
+use spinning_top::RwSpinlock;
+use x86_64::structures::idt::InterruptStackFrame;
+const LAPIC_REGION_SIZE: u64 = 0x1000;
+const ID_OFFSET: u64 = 0x020;
+const EOI_OFFSET: u64 = 0x0b0;
+const SPURIOUS_VECTOR_OFFSET: u64 = 0x0f0;
+const TIMER_VECTOR_OFFSET: u64 = 0x320;
+const TIMER_INITIAL_COUNT_OFFSET: u64 = 0x380;
+const TIMER_CURRENT_COUNT_OFFSET: u64 = 0x390;
+const TIMER_DIVIDE_OFFSET: u64 = 0x3e0;
+const TIMER_DIVIDE_BY_16: u32 = 0b0011;
+const TIMER_PERIODIC: u32 = 1 << 17;
+const TIMER_CALIBRATION_MICROSECONDS: u64 = 10_000;
+pub const TIMER_VECTOR: u8 = 0x20;
+const PIC_MASTER_DATA_PORT: u16 = 0x21;
+const PIC_SLAVE_DATA_PORT: u16 = 0xa1;
+const ICR_LOW_OFFSET: u64 = 0x300;
+const ICR_HIGH_OFFSET: u64 = 0x310;
+const SOFTWARE_ENABLE: u32 = 1 << 8;
+pub const SPURIOUS_VECTOR: u8 = 0xff;
+const ICR_DELIVERY_PENDING: u32 = 1 << 12;
+const ICR_LEVEL_ASSERT: u32 = 1 << 14;
+const ICR_INIT: u32 = 0b101 << 8;
+const ICR_STARTUP: u32 = 0b110 << 8;
+static LAPIC_ADDRESS: RwSpinlock<Option<u64>> = RwSpinlock::new(None);
+// the bootstrap processor's timer count for one tick, every processor's timer is assumed to
+// run at the same rate
+static TIMER_COUNT: AtomicU32 = AtomicU32::new(0);
+fn register(offset: u64) -> *mut u32 {
+    (LAPIC_ADDRESS
+        .read()
+        .expect("local apic accessed before initialisation!")
+        + offset) as *mut u32
+}
+fn read(offset: u64) -> u32 {
+    unsafe { register(offset).read_volatile() }
+}
+fn write(offset: u64, value: u32) {
+    unsafe { register(offset).write_volatile(value) }
+}
+pub fn id() -> u32 {
+    read(ID_OFFSET) >> 24
+}
+// every processor has to software-enable its own local apic before it accepts interrupts, and
+// starts its own timer ticking along with it
+pub fn enable() {
+    write(
+        SPURIOUS_VECTOR_OFFSET,
+        read(SPURIOUS_VECTOR_OFFSET) | SOFTWARE_ENABLE | SPURIOUS_VECTOR as u32,
+    );
+    let count = TIMER_COUNT.load(Ordering::Relaxed);
+    if count != 0 {
+        write(TIMER_DIVIDE_OFFSET, TIMER_DIVIDE_BY_16);
+        write(TIMER_VECTOR_OFFSET, TIMER_PERIODIC | TIMER_VECTOR as u32);
+        write(TIMER_INITIAL_COUNT_OFFSET, count);
+    }
+}
+pub fn end_of_interrupt() {
+    write(EOI_OFFSET, 0);
+}
+// a spurious interrupt is never in service, so it is not acknowledged
+pub extern "x86-interrupt" fn spurious_handler(_stack_frame: InterruptStackFrame) {}
+// counts down a one-shot timer against the monotonic clock, with the timer masked so that it
+// never fires while doing so
+fn calibrate_timer() -> u32 {
+    write(TIMER_DIVIDE_OFFSET, TIMER_DIVIDE_BY_16);
+    write(TIMER_VECTOR_OFFSET, 1 << 16 | TIMER_VECTOR as u32);
+    write(TIMER_INITIAL_COUNT_OFFSET, u32::MAX);
+    time::stall(TIMER_CALIBRATION_MICROSECONDS);
+    let elapsed = u32::MAX - read(TIMER_CURRENT_COUNT_OFFSET);
+    write(TIMER_INITIAL_COUNT_OFFSET, 0);
+    (elapsed as u64 * TICK_NANOS / (TIMER_CALIBRATION_MICROSECONDS * 1_000)).clamp(1, u32::MAX as u64)
+        as u32
+}
+fn send_ipi(apic_id: u32, command: u32) {
+    write(ICR_HIGH_OFFSET, apic_id << 24);
+    write(ICR_LOW_OFFSET, command);
+    while read(ICR_LOW_OFFSET) & ICR_DELIVERY_PENDING != 0 {
+        core::hint::spin_loop();
+    }
+}
+pub fn send_init(apic_id: u32) {
+    send_ipi(apic_id, ICR_INIT | ICR_LEVEL_ASSERT);
+}
+// the startup vector is the page number of the real-mode entry point, so it must be below 1MiB
+pub fn send_startup(apic_id: u32, page: u8) {
+    send_ipi(apic_id, ICR_STARTUP | ICR_LEVEL_ASSERT | page as u32);
+}
+pub fn initialise(_boot_info: &mut bootloader_api::BootInfo) {
+    let physical_address = match &ACPI_PLATFORM
+        .read()
+        .as_ref()
+        .expect("ACPI platform not initialised before local apic initialisation!")
+        .interrupt_model
+    {
+        InterruptModel::Apic(apic) => apic.local_apic_address,
+        _ => panic!("ACPI does not describe an apic interrupt model!"),
+    };
+    let _ = LAPIC_ADDRESS
+        .write()
+        .insert(map_device_region(physical_address, LAPIC_REGION_SIZE));
+    // the legacy pics would deliver their irqs on top of the exception vectors, so every line is
+    // masked and the local apic's timer is the only interrupt source
+    unsafe {
+        port::write_u8(PIC_MASTER_DATA_PORT, 0xff);
+        port::write_u8(PIC_SLAVE_DATA_PORT, 0xff);
+    }
+    let count = calibrate_timer();
+    TIMER_COUNT.store(count, Ordering::Relaxed);
+    println!(
+        "calibrated local apic timer to {} counts per {}ns tick...",
+        count, TICK_NANOS
+    );
+    enable();
+    println!(
+        "enabled local apic at address 0x{:x} for bootstrap processor with apic id {}...",
+        physical_address,
+        id()
+    );
+}
@@ -0,0 +1,106 @@
+use crate::{
+    mapping::{PAGE_SIZE, USER_IMAGE},
+    page::{ManagedPageTable, USER_PAGE_FLAGS},
+};
+use elf::{
+    ElfBytes, ParseError,
+    abi::{EM_X86_64, ET_DYN, PF_W, PF_X, PT_LOAD, PT_TLS, R_X86_64_RELATIVE, SHT_RELA},
+    endian::AnyEndian,
+    file::Class,
+};
+use x86_64::structures::paging::PageTableFlags;
+// where the initial image of a thread-local block lives in the loaded program, every new thread
+// copies it into its own block
+#[derive(Clone, Copy, Debug)]
+pub struct TlsTemplate {
+    pub address: u64,
+    pub file_size: u64,
+    pub memory_size: u64,
+    pub alignment: u64,
+}
+#[derive(Debug)]
+pub struct LoadedImage {
+    pub base: u64,
+    pub entry: u64,
+    pub tls_template: Option<TlsTemplate>,
+}
+#[derive(Debug)]
+pub enum LoadError {
+    Parse(ParseError),
+    Unsupported(&'static str),
+    OutOfMemory,
+}
+impl From<ParseError> for LoadError {
+    fn from(error: ParseError) -> Self {
+        LoadError::Parse(error)
+    }
+}
+// maps every loadable segment of an elf into the given address space. position-independent
+// executables are placed at a fixed base and their relative relocations applied, as no dynamic
+// linker exists to do it for them
+pub fn load(pages: &mut ManagedPageTable, bytes: &[u8]) -> Result<LoadedImage, LoadError> {
+    let elf_bytes = ElfBytes::<AnyEndian>::minimal_parse(bytes)?;
+    if elf_bytes.ehdr.class != Class::ELF64 || elf_bytes.ehdr.e_machine != EM_X86_64 {
+        return Err(LoadError::Unsupported("not an x86_64 elf"));
+    }
+    let base = if elf_bytes.ehdr.e_type == ET_DYN {
+        USER_IMAGE
+    } else {
+        0
+    };
+    let segments = elf_bytes
+        .segments()
+        .ok_or(LoadError::Unsupported("no program headers"))?;
+    let mut tls_template = None;
+    for segment in segments.iter() {
+        match segment.p_type {
+            PT_LOAD => {
+                let start = (base + segment.p_vaddr) & !(PAGE_SIZE - 1);
+                let end = (base + segment.p_vaddr + segment.p_memsz).div_ceil(PAGE_SIZE) * PAGE_SIZE;
+                let mut flags = *USER_PAGE_FLAGS;
+                if segment.p_flags & PF_W == 0 {
+                    flags.remove(PageTableFlags::WRITABLE);
+                }
+                if segment.p_flags & PF_X == 0 {
+                    flags.insert(PageTableFlags::NO_EXECUTE);
+                }
+                pages
+                    .map(start, (end - start) / PAGE_SIZE, flags)
+                    .ok_or(LoadError::OutOfMemory)?;
+                // the frames come zeroed, so only the file-backed part needs copying
+                pages
+                    .write(base + segment.p_vaddr, elf_bytes.segment_data(&segment)?)
+                    .ok_or(LoadError::OutOfMemory)?;
+            }
+            PT_TLS => {
+                let _ = tls_template.insert(TlsTemplate {
+                    address: base + segment.p_vaddr,
+                    file_size: segment.p_filesz,
+                    memory_size: segment.p_memsz,
+                    alignment: segment.p_align.max(1),
+                });
+            }
+            _ => {}
+        }
+    }
+    if let Some(section_headers) = elf_bytes.section_headers() {
+        for section_header in section_headers.iter().filter(|header| header.sh_type == SHT_RELA) {
+            for rela in elf_bytes.section_data_as_relas(&section_header)? {
+                if rela.r_type != R_X86_64_RELATIVE {
+                    return Err(LoadError::Unsupported("relocation other than relative"));
+                }
+                pages
+                    .write(
+                        base + rela.r_offset,
+                        &base.wrapping_add_signed(rela.r_addend).to_le_bytes(),
+                    )
+                    .ok_or(LoadError::Unsupported("relocation outside of loaded segments"))?;
+            }
+        }
+    }
+    Ok(LoadedImage {
+        base,
+        entry: base + elf_bytes.ehdr.e_entry,
+        tls_template,
+    })
+}
@@ -0,0 +1,152 @@
+use crate::{
+    file::FileError,
+    page,
+    proc::{Descriptor, Handle, Message, Server},
+    wire,
+};
+use alloc::{
+    boxed::Box,
+    collections::vec_deque::VecDeque,
+    sync::{Arc, Weak},
+    vec,
+    vec::Vec,
+};
+use spinning_top::RwSpinlock;
+// a lock request waits for its response until the lock is handed to it, holding on to the
+// message so that it is still there to respond to
+struct Waiter {
+    process: u64,
+    descriptor: Weak<Descriptor>,
+    message: Arc<Message>,
+}
+// a file is identified by the server owning it and its path there, and is locked by a single
+// descriptor at a time
+struct Lock {
+    server: Weak<Server>,
+    path: Box<[u8]>,
+    process: u64,
+    holder: Weak<Descriptor>,
+    waiters: VecDeque<Waiter>,
+}
+static LOCKS: RwSpinlock<Vec<Lock>> = RwSpinlock::new(Vec::new());
+pub enum Acquired {
+    Now,
+    Queued,
+}
+// whether a process waiting on the locks held by another would end up waiting on itself. waits
+// are tracked per process, as a lock request is sent without blocking any particular thread
+fn waits_on(locks: &[Lock], from: u64, to: u64) -> bool {
+    let mut visited = Vec::new();
+    let mut pending = vec![from];
+    while let Some(process) = pending.pop() {
+        if process == to {
+            return true;
+        }
+        if visited.contains(&process) {
+            continue;
+        }
+        visited.push(process);
+        for lock in locks {
+            if lock.waiters.iter().any(|waiter| {
+                waiter.process == process && !waiter.message.cancelled()
+            }) {
+                pending.push(lock.process);
+            }
+        }
+    }
+    false
+}
+// takes the lock on a file for a descriptor, or queues the request until the lock is handed over.
+// a request that would complete a cycle of processes waiting on each other fails instead, as
+// does one from a process already holding the lock through another descriptor
+pub fn acquire(
+    server: &Arc<Server>,
+    path: &[u8],
+    process: u64,
+    descriptor: &Arc<Descriptor>,
+    message: &Arc<Message>,
+) -> Result<Acquired, FileError> {
+    let mut locks_write = LOCKS.write();
+    let Some(index) = locks_write.iter().position(|lock| {
+        Weak::as_ptr(&lock.server) == Arc::as_ptr(server) && *lock.path == *path
+    }) else {
+        locks_write.push(Lock {
+            server: Arc::downgrade(server),
+            path: Box::from(path),
+            process,
+            holder: Arc::downgrade(descriptor),
+            waiters: VecDeque::new(),
+        });
+        return Ok(Acquired::Now);
+    };
+    if Weak::as_ptr(&locks_write[index].holder) == Arc::as_ptr(descriptor) {
+        return Ok(Acquired::Now);
+    }
+    if waits_on(&locks_write, locks_write[index].process, process) {
+        return Err(FileError::Deadlock);
+    }
+    locks_write[index].waiters.push_back(Waiter {
+        process,
+        descriptor: Arc::downgrade(descriptor),
+        message: message.clone(),
+    });
+    Ok(Acquired::Queued)
+}
+// gives up every lock a descriptor holds or is waiting on, handing each lock to the next waiter
+// that has not given up on its request. requests still queued for the descriptor are dropped,
+// which fails them for their clients
+pub fn release(descriptor: *const Descriptor) {
+    let mut granted = Vec::new();
+    let mut abandoned = Vec::new();
+    {
+        let mut locks_write = LOCKS.write();
+        for lock in locks_write.iter_mut() {
+            let waiters = lock.waiters.drain(..).collect::<Vec<_>>();
+            for waiter in waiters {
+                if Weak::as_ptr(&waiter.descriptor) == descriptor {
+                    abandoned.push(waiter.message);
+                } else {
+                    lock.waiters.push_back(waiter);
+                }
+            }
+            if Weak::as_ptr(&lock.holder) != descriptor {
+                continue;
+            }
+            lock.holder = Weak::new();
+            while let Some(waiter) = lock.waiters.pop_front() {
+                let holder = waiter.descriptor.upgrade();
+                let Some(holder) = holder.filter(|_| !waiter.message.cancelled()) else {
+                    abandoned.push(waiter.message);
+                    continue;
+                };
+                lock.process = waiter.process;
+                lock.holder = waiter.descriptor;
+                granted.push((holder, waiter.message));
+                break;
+            }
+        }
+        locks_write.retain(|lock| lock.holder.strong_count() != 0 || !lock.waiters.is_empty());
+    }
+    // responding and dropping messages wakes their clients, which is kept out from under the lock
+    drop(abandoned);
+    for (holder, message) in granted {
+        respond(message, claim(&holder).map(|_| Vec::new()));
+    }
+}
+// a kernel-served file is told once its descriptor holds the lock, so that it can tie whatever
+// the lock stands for, such as a claimed device, to its handle
+pub fn claim(descriptor: &Descriptor) -> Result<(), FileError> {
+    match &mut *descriptor.handle.write() {
+        Handle::Kernel(file) => match file.lock() {
+            Err(FileError::Unsupported) => Ok(()),
+            result => result,
+        },
+        Handle::User { .. } => Ok(()),
+    }
+}
+pub fn respond(message: Arc<Message>, result: Result<Vec<u8>, FileError>) {
+    match page::frames_from_bytes(&wire::response(result)) {
+        Some(frames) => Message::respond(message, frames),
+        None => drop(message),
+    }
+}
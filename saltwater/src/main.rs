@@ -7,30 +7,59 @@ pub mod allocator;
 pub mod config;
 pub mod core;
 pub mod debugcon;
+pub mod file;
+pub mod fpu;
 pub mod frame;
 pub mod gdt;
 pub mod hcf;
 pub mod idt;
 pub mod istacks;
 pub mod kickstart;
+pub mod lapic;
+pub mod loader;
+pub mod locks;
 pub mod mapping;
+pub mod namespace;
 pub mod page;
 pub mod panic;
+pub mod pci;
+pub mod pcifs;
 pub mod port;
+pub mod power;
 pub mod proc;
+pub mod procfs;
 pub mod qemu;
+pub mod rootfs;
 pub mod sstacks;
 pub mod scheduler;
-use crate::hcf::hcf;
-const INITIALISERS: [fn(&mut bootloader_api::BootInfo); 8] = [
+pub mod smp;
+pub mod syscall;
+#[cfg(feature = "teardown_check")]
+pub mod teardown;
+pub mod time;
+pub mod tls;
+pub mod wire;
+use crate::scheduler::ProcessorScheduler;
+const INITIALISERS: [fn(&mut bootloader_api::BootInfo); 19] = [
     mapping::initialise,
     allocator::bootstrap_initialise,
     acpi::bootstrap_initialise,
     frame::initialise,
+    time::initialise,
+    pci::initialise,
+    acpi::initialise,
+    pcifs::initialise,
+    power::initialise,
     istacks::initialise,
     core::initialise,
     idt::initialise,
+    syscall::initialise,
+    fpu::initialise,
+    lapic::initialise,
+    smp::initialise,
     kickstart::initialise,
+    procfs::initialise,
+    rootfs::initialise,
 ];
 bootloader_api::entry_point!(main, config = &config::BOOTLOADER_CONFIG);
 pub fn main(boot_info: &'static mut bootloader_api::BootInfo) -> ! {
@@ -52,19 +81,7 @@ pub fn main(boot_info: &'static mut bootloader_api::BootInfo) -> ! {
     println!(
         "successfully initialised saltwater tethys kernel! exiting initialisation procedure to scheduler & kickstart process..."
     );
-    // scheduler::enter();
-    println!("successfully executed tethys operating system!");
-    qemu::exit(qemu::ExitCode::Success);
-    println!("exited qemu with success code...");
-    match &mut boot_info.framebuffer {
-        bootloader_api::info::Optional::Some(framebuffer) => framebuffer
-            .buffer_mut()
-            .iter_mut()
-            .skip(1)
-            .step_by(4)
-            .for_each(|x| *x = 255),
-        bootloader_api::info::Optional::None => panic!("no framebuffer!"),
-    }
-    println!("wrote success graphic to framebuffer...");
-    hcf();
+    // the run ends when kickstart exits, with its exit status deciding the qemu exit code
+    kickstart::start();
+    ProcessorScheduler::enter();
 }
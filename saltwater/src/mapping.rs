@@ -1,10 +1,15 @@
 use crate::println;
 const ONE_MEGABYTE: u64 = 0x0000_0000_0010_0000;
+const FOUR_MEGABYTES: u64 = 0x0000_0000_0040_0000;
 const SIXTEEN_MEGABYTES: u64 = 0x0000_0000_0100_0000;
 const ONE_TERABYTE: u64 = 0x0000_0100_0000_0000;
 const TWELVE_TERABYTES: u64 = 0x0000_0c00_0000_0000;
 const SIXTEEN_TERABYTES: u64 = 0x0000_1000_0000_0000;
 pub const PAGE_SIZE: u64 = 4096;
+pub const USER_IMAGE: u64 = FOUR_MEGABYTES;
+pub const USER_THREAD_AREAS: u64 = TWELVE_TERABYTES;
+// the end of the canonical lower half, user addresses are checked against it before being used
+pub const USER_LIMIT: u64 = 0x0000_8000_0000_0000;
 pub const HIGHER_HALF: u64 = 0xffff_8000_0000_0000;
 pub const KERNEL_CODE: u64 = HIGHER_HALF;
 pub const KERNEL_HEAP: u64 = KERNEL_CODE + SIXTEEN_TERABYTES;
@@ -17,6 +22,12 @@ pub const CRITICAL_STACKS: u64 = DOUBLE_FAULT_STACKS + ONE_TERABYTE;
 pub const DIRECT_PHYSICAL: u64 = CRITICAL_STACKS + ONE_TERABYTE;
 pub const SYSCALL_STACK_SIZE: u64 = SIXTEEN_MEGABYTES;
 pub const INTERRUPT_STACK_SIZE: u64 = ONE_MEGABYTE;
+pub const USER_THREAD_AREA_SIZE: u64 = SIXTEEN_MEGABYTES;
+// processor 0 runs on the stack the bootloader set up, application processors are given
+// smaller stacks at the same stride for the time before they enter their schedulers
+pub const fn bootstrap_stack_address(index: usize) -> u64 {
+    BOOTSTRAP_STACK + SYSCALL_STACK_SIZE * index as u64
+}
 pub const fn syscall_stack_address(index: usize) -> u64 {
     SYSCALL_STACKS + SYSCALL_STACK_SIZE * index as u64
 }
@@ -29,6 +40,10 @@ pub const fn double_fault_stack_address(index: usize) -> u64 {
 pub const fn critical_stack_address(index: usize) -> u64 {
     CRITICAL_STACKS + INTERRUPT_STACK_SIZE * index as u64
 }
+// every thread of a process gets its own area in the lower half holding its thread-local block
+pub const fn user_thread_area_address(index: u64) -> u64 {
+    USER_THREAD_AREAS + USER_THREAD_AREA_SIZE * index
+}
 pub fn physical_to_virtual_address(physical: u64) -> u64 {
     DIRECT_PHYSICAL + physical
 }
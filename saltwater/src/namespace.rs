@@ -0,0 +1,811 @@
+use crate::{
+    file::{FileError, KernelFile, Listing, Seek},
+    locks::{self, Acquired},
+    page,
+    proc::{
+        Binding, Descriptor, Grant, Handle, Message, MessageStatus, Namespace, Order, Process,
+        ROOT_HANDLE, Server, ServerKind, State, UserServer,
+    },
+    scheduler::{self, Source, current_thread},
+    time,
+    wire::{self, Negotiated, Protocol, REQUEST_HEADER_SIZE, Request, Selector, WIRE_MAJOR, WIRE_VERSION},
+};
+use alloc::{
+    boxed::Box,
+    sync::{Arc, Weak},
+    vec,
+    vec::Vec,
+};
+use core::{mem, ptr, sync::atomic::Ordering};
+use spinning_top::RwSpinlock;
+// bindings can bind into each other, so they are only expanded to a fixed depth rather than
+// followed round a cycle
+const BIND_DEPTH: usize = 8;
+const LIST_CHUNK: u64 = 0x100;
+const READ_CHUNK: u64 = 0x10000;
+const TRANSACT_TIMEOUT_NANOS: u64 = 5_000_000_000;
+// unmapping with no descriptor to unmap removes every binding at the bindpoint
+const ANY_DESCRIPTOR: u64 = u64::MAX;
+// a point in the namespace, along with the mask every binding on the way there has applied and
+// the bindings themselves
+#[derive(Clone)]
+pub struct Location {
+    pub server: Arc<Server>,
+    pub path: Box<[u8]>,
+    pub state_mask: State,
+    pub through: Vec<u64>,
+}
+impl Location {
+    pub fn of(descriptor: &Descriptor) -> Result<Location, FileError> {
+        Ok(Location {
+            server: descriptor.server.upgrade().ok_or(FileError::NotFound)?,
+            path: descriptor.path.clone(),
+            state_mask: descriptor.state_mask,
+            through: descriptor.through.to_vec(),
+        })
+    }
+    fn child(&self, name: &[u8]) -> Location {
+        let mut path = self.path.to_vec();
+        if !path.is_empty() {
+            path.push(b'/');
+        }
+        path.extend_from_slice(name);
+        Location {
+            server: self.server.clone(),
+            path: path.into_boxed_slice(),
+            state_mask: self.state_mask,
+            through: self.through.clone(),
+        }
+    }
+}
+// empty components are skipped, so "a//b/" and "a/b" are the same path
+pub fn components(path: &[u8]) -> impl Iterator<Item = &[u8]> {
+    path.split(|byte| *byte == b'/')
+        .filter(|name| !name.is_empty())
+}
+pub fn normalise(path: &[u8]) -> Box<[u8]> {
+    components(path)
+        .collect::<Vec<_>>()
+        .join(&b'/')
+        .into_boxed_slice()
+}
+// the union at a location in the order it is searched, which starts out as the location itself
+// and has each binding the namespace makes onto it applied in the order they were made
+pub fn members(namespace: &Namespace, location: &Location) -> Vec<Location> {
+    expand(namespace, location, 0)
+}
+fn expand(namespace: &Namespace, location: &Location, depth: usize) -> Vec<Location> {
+    let mut members = vec![location.clone()];
+    if depth == BIND_DEPTH {
+        return members;
+    }
+    let bindings = namespace
+        .bindings
+        .read()
+        .iter()
+        .filter(|binding| {
+            same_server(&binding.to_server, &location.server) && *binding.to_path == *location.path
+        })
+        .cloned()
+        .collect::<Vec<_>>();
+    for binding in bindings {
+        let Some(server) = binding.from_server.upgrade().filter(|_| !binding.grant.revoked()) else {
+            continue;
+        };
+        let mut through = location.through.clone();
+        through.push(binding.id);
+        let bound = Location {
+            server,
+            path: binding.from_path,
+            state_mask: location.state_mask & binding.state_mask,
+            through,
+        };
+        let bound = expand(namespace, &bound, depth + 1);
+        match binding.order {
+            Order::Replace => members = bound,
+            Order::Before => {
+                members.splice(0..0, bound);
+            }
+            Order::After => members.extend(bound),
+        }
+    }
+    members
+}
+fn same_server(weak: &Weak<Server>, server: &Arc<Server>) -> bool {
+    ptr::eq(weak.as_ptr(), Arc::as_ptr(server))
+}
+// a descriptor walked through a binding that has since been unmapped no longer refers to anything
+fn stale(namespace: &Namespace, descriptor: &Descriptor) -> bool {
+    descriptor
+        .through
+        .iter()
+        .any(|id| !namespace.contains(*id))
+}
+fn revoked(namespace: &Namespace, descriptor: &Descriptor) -> bool {
+    descriptor.grant.revoked() || descriptor.through.iter().any(|id| namespace.revoked(*id))
+}
+// a descriptor that has been revoked, or walked through a binding that has, answers nothing but
+// the revoked error, and one whose bindings are gone goes stale
+pub fn usable(namespace: &Namespace, descriptor: &Descriptor) -> Result<(), FileError> {
+    if revoked(namespace, descriptor) {
+        return Err(FileError::Revoked);
+    }
+    if stale(namespace, descriptor) {
+        return Err(FileError::NotFound);
+    }
+    Ok(())
+}
+// a union with more than one member has its listing merged by the kernel
+fn union_members(namespace: &Namespace, descriptor: &Descriptor) -> Option<Vec<Location>> {
+    let members = members(namespace, &Location::of(descriptor).ok()?);
+    (members.len() > 1).then_some(members)
+}
+fn primary(namespace: &Namespace, location: &Location) -> Result<Location, FileError> {
+    members(namespace, location)
+        .into_iter()
+        .next()
+        .ok_or(FileError::NotFound)
+}
+// sends a request to a user server on the current thread's behalf and blocks until it has been
+// answered, returning the whole response. only a weak reference is kept while waiting, so a
+// server that goes away unanswered still wakes the thread, and a server that never answers is
+// given up on as busy once the timeout passes
+fn exchange(server: &Server, request: &[u8]) -> Result<Vec<u8>, FileError> {
+    let ServerKind::User(user_server) = &server.kind else {
+        return Err(FileError::Unsupported);
+    };
+    let process = current_thread()
+        .read()
+        .process
+        .upgrade()
+        .ok_or(FileError::Busy)?;
+    let frames = page::frames_from_bytes(request).ok_or(FileError::Busy)?;
+    let tag = process.next_tag.fetch_add(1, Ordering::Relaxed);
+    let message = Arc::new(Message::new(tag, Arc::downgrade(&process), frames));
+    let waited = Arc::downgrade(&message);
+    user_server.read().enqueue(message);
+    let deadline = time::nanos_since_boot().saturating_add(TRANSACT_TIMEOUT_NANOS);
+    let woken = scheduler::wait_any(&[Source::Response(waited.clone())], Some(deadline));
+    let Some(message) = waited.upgrade() else {
+        return Err(FileError::NotFound);
+    };
+    process
+        .responses
+        .write()
+        .retain(|other| !Arc::ptr_eq(other, &message));
+    if !matches!(woken, Ok(Some(..))) {
+        message.cancel();
+        return Err(FileError::Busy);
+    }
+    let MessageStatus::Responded(frames) =
+        mem::replace(&mut *message.status.write(), MessageStatus::Received)
+    else {
+        return Err(FileError::Invalid);
+    };
+    let bytes = page::frames_to_bytes(&frames);
+    page::release_frames(frames);
+    Ok(bytes)
+}
+// returns the body of the response, or the error it carries
+fn transact(server: &Server, request: &[u8]) -> Result<Vec<u8>, FileError> {
+    let bytes = exchange(server, request)?;
+    wire::parse_response(&bytes).map(<[u8]>::to_vec)
+}
+// the kernel asks a user server what it speaks the first time it opens a file there or forwards
+// a message to it, and remembers the answer. only a server answering the way servers from before
+// version messages do is taken to speak 1.0, and any other error is incompatible. a server that
+// goes away or times out is asked again next time, rather than being remembered as anything
+fn negotiate(server: &Server) -> Result<Protocol, FileError> {
+    let ServerKind::User(user_server) = &server.kind else {
+        return Ok(Protocol::current());
+    };
+    let known = *user_server.read().protocol.read();
+    let protocol = match known {
+        Some(negotiated) => negotiated.protocol,
+        None => {
+            let request = wire::request(
+                ROOT_HANDLE,
+                Selector::Version,
+                &wire::word_bytes(WIRE_VERSION),
+            );
+            let response = exchange(server, &request)?;
+            let predates_version = wire::predates_version(&response);
+            let protocol = if predates_version {
+                Protocol::legacy()
+            } else {
+                wire::parse_response(&response)
+                    .and_then(Protocol::parse)
+                    .map_err(|_| FileError::Incompatible)?
+            };
+            *user_server.read().protocol.write() = Some(Negotiated {
+                protocol,
+                predates_version,
+            });
+            protocol
+        }
+    };
+    if !protocol.compatible() {
+        return Err(FileError::Incompatible);
+    }
+    Ok(protocol)
+}
+// a message is only forwarded to a server that has said it takes the selector, and at that size
+fn accepts(server: &Server, request: &Request) -> Result<(), FileError> {
+    let protocol = negotiate(server)?;
+    if !protocol.supports(request.selector) {
+        return Err(FileError::Unsupported);
+    }
+    if (REQUEST_HEADER_SIZE + request.body.len()) as u64 > protocol.message_limit {
+        return Err(FileError::Incompatible);
+    }
+    Ok(())
+}
+// a version message is answered with what the server behind the descriptor speaks, once the
+// sender is known to speak a compatible version itself
+fn version(descriptor: &Descriptor, request: &Request) -> Result<Vec<u8>, FileError> {
+    if wire::major(wire::word(request.body, 0)?) != WIRE_MAJOR {
+        return Err(FileError::Incompatible);
+    }
+    let user_handle = user_handle(&descriptor.handle.read());
+    let protocol = match user_handle {
+        Some((server, _)) => {
+            let server = server.upgrade().ok_or(FileError::NotFound)?;
+            negotiate(&server)?
+        }
+        None => Protocol::current(),
+    };
+    Ok(protocol.encode().to_vec())
+}
+fn user_handle(handle: &Handle) -> Option<(Weak<Server>, u64)> {
+    match handle {
+        Handle::User { server, handle } => Some((server.clone(), *handle)),
+        Handle::Kernel(..) => None,
+    }
+}
+fn transact_handle(
+    server: &Weak<Server>,
+    handle: u64,
+    selector: Selector,
+    body: &[u8],
+) -> Result<Vec<u8>, FileError> {
+    let server = server.upgrade().ok_or(FileError::NotFound)?;
+    transact(&server, &wire::request(handle, selector, body))
+}
+// opens the file at a location on the server that owns it, without looking at any bindings
+pub fn open(location: &Location) -> Result<Handle, FileError> {
+    negotiate(&location.server)?;
+    match &location.server.kind {
+        ServerKind::Kernel(kernel_server) => {
+            let mut file = kernel_server.filesystem.attach();
+            for name in components(&location.path) {
+                file = file.walk(name)?;
+            }
+            Ok(Handle::Kernel(file))
+        }
+        ServerKind::User(..) if location.path.is_empty() => Ok(Handle::User {
+            server: Arc::downgrade(&location.server),
+            handle: ROOT_HANDLE,
+        }),
+        ServerKind::User(..) => {
+            let response = transact(
+                &location.server,
+                &wire::request(ROOT_HANDLE, Selector::Walk, &location.path),
+            )?;
+            Ok(Handle::User {
+                server: Arc::downgrade(&location.server),
+                handle: wire::word(&response, 0)?,
+            })
+        }
+    }
+}
+fn new_descriptor(
+    location: Location,
+    handle: Handle,
+    primary: &Location,
+    grant: Arc<Grant>,
+) -> Descriptor {
+    Descriptor {
+        server: Arc::downgrade(&location.server),
+        path: location.path,
+        state_mask: location.state_mask,
+        handle: RwSpinlock::new(handle),
+        handle_mask: primary.state_mask,
+        through: primary.through.clone().into_boxed_slice(),
+        listing: RwSpinlock::new(Listing::new()),
+        grant,
+    }
+}
+// a descriptor sits at the location it was walked to, so that walking on from it sees the whole
+// union there, while its messages go to the union's first member
+pub fn descriptor(
+    namespace: &Namespace,
+    location: Location,
+    grant: Arc<Grant>,
+) -> Result<Descriptor, FileError> {
+    let primary = primary(namespace, &location)?;
+    let handle = open(&primary)?;
+    Ok(new_descriptor(location, handle, &primary, grant))
+}
+// resolves a path one component at a time. a component is looked for in each member of the
+// union it is walked from in turn, skipping members masked from being walked, and only a union
+// with a single member is walked through without checking the component exists
+pub fn walk(
+    namespace: &Namespace,
+    descriptor: &Descriptor,
+    path: &[u8],
+) -> Result<Descriptor, FileError> {
+    let mut location = Location::of(descriptor)?;
+    let mut probed = None;
+    for name in components(path) {
+        let members = members(namespace, &location)
+            .into_iter()
+            .filter(|member| member.state_mask.walk)
+            .collect::<Vec<_>>();
+        (location, probed) = match members.as_slice() {
+            [] => return Err(FileError::Denied),
+            [member] => (member.child(name), None),
+            _ => {
+                let (child, handle) = probe(&members, name)?;
+                (child, Some(handle))
+            }
+        };
+    }
+    let grant = Grant::derive(&descriptor.grant);
+    let primary = primary(namespace, &location)?;
+    // the handle the last component was found with is kept, unless something is bound over it
+    match probed {
+        Some(handle)
+            if Arc::ptr_eq(&primary.server, &location.server) && primary.path == location.path =>
+        {
+            Ok(new_descriptor(location, handle, &primary, grant))
+        }
+        _ => self::descriptor(namespace, location, grant),
+    }
+}
+// only a member without the component is passed over, so a busy server fails the walk rather
+// than costing every member after it the timeout as well
+fn probe(members: &[Location], name: &[u8]) -> Result<(Location, Handle), FileError> {
+    for member in members {
+        let child = member.child(name);
+        match open(&child) {
+            Ok(handle) => return Ok((child, handle)),
+            Err(FileError::NotFound | FileError::Unsupported) => {}
+            Err(error) => return Err(error),
+        }
+    }
+    Err(FileError::NotFound)
+}
+// a new file is made in the first member of a union, which is where its descriptor sits, and
+// asks for no more than the descriptor's mask allows
+fn make(
+    process: &Process,
+    namespace: &Namespace,
+    descriptor: &Descriptor,
+    body: &[u8],
+) -> Result<Vec<u8>, FileError> {
+    let state = wire::state_from_word(wire::word(body, 0)?) & descriptor.handle_mask;
+    let name = &body[size_of::<u64>()..];
+    let primary = primary(namespace, &Location::of(descriptor)?)?;
+    let user_handle = user_handle(&descriptor.handle.read());
+    let handle = match user_handle {
+        Some((server, handle)) => {
+            let mut masked = wire::word_bytes(wire::state_word(state));
+            masked.extend_from_slice(name);
+            let response = transact_handle(&server, handle, Selector::Make, &masked)?;
+            Handle::User {
+                server,
+                handle: wire::word(&response, 0)?,
+            }
+        }
+        None => {
+            let Handle::Kernel(file) = &mut *descriptor.handle.write() else {
+                return Err(FileError::Invalid);
+            };
+            Handle::Kernel(file.make(state, name)?)
+        }
+    };
+    let made = primary.child(name);
+    let made = new_descriptor(made.clone(), handle, &made, Grant::derive(&descriptor.grant));
+    Ok(wire::word_bytes(process.add_descriptor(made)))
+}
+fn list_all(location: &Location) -> Result<Vec<Box<[u8]>>, FileError> {
+    let mut handle = open(location)?;
+    let mut names = Vec::new();
+    loop {
+        let listed = match &mut handle {
+            Handle::Kernel(file) => file.list(LIST_CHUNK as usize, true)?,
+            Handle::User { server, handle } => wire::parse_names(&transact_handle(
+                server,
+                *handle,
+                Selector::List,
+                &LIST_CHUNK.to_le_bytes(),
+            )?)?,
+        };
+        if listed.is_empty() {
+            return Ok(names);
+        }
+        names.extend(listed);
+    }
+}
+// a binding under a name goes onto that name in the first member of the union at the descriptor,
+// which is where a walk to the name arrives unless another member has it first. a binding with
+// no name goes onto the descriptor's own location
+fn bindpoint(
+    namespace: &Namespace,
+    descriptor: &Descriptor,
+    name: &[u8],
+) -> Result<Location, FileError> {
+    let location = Location::of(descriptor)?;
+    let name = normalise(name);
+    if name.is_empty() {
+        return Ok(location);
+    }
+    Ok(primary(namespace, &location)?.child(&name))
+}
+// the binding keeps the location the bound descriptor was walked to rather than the descriptor
+// itself, and is opened afresh by every walk through it. it is masked by the bound descriptor
+// as well as the state asked for, so binding never grants more than the binder had
+fn bind(
+    process: &Process,
+    namespace: &Namespace,
+    descriptor: &Descriptor,
+    body: &[u8],
+) -> Result<Vec<u8>, FileError> {
+    let from = process
+        .descriptor(wire::word(body, 0)?)
+        .ok_or(FileError::NotFound)?;
+    usable(namespace, &from)?;
+    let state = wire::state_from_word(wire::word(body, 1)?);
+    let order = match wire::word(body, 2)? {
+        wire::ORDER_REPLACE => Order::Replace,
+        wire::ORDER_BEFORE => Order::Before,
+        wire::ORDER_AFTER => Order::After,
+        _ => return Err(FileError::Invalid),
+    };
+    let name = body
+        .get(3 * size_of::<u64>()..)
+        .ok_or(FileError::Invalid)?;
+    let grant = Grant::derive(&from.grant);
+    let from = Location::of(&from)?;
+    let to = bindpoint(namespace, descriptor, name)?;
+    namespace.bindings.write().push(Binding {
+        id: Namespace::next_binding_id(),
+        from_server: Arc::downgrade(&from.server),
+        from_path: from.path,
+        to_server: Arc::downgrade(&to.server),
+        to_path: to.path,
+        state_mask: state & from.state_mask,
+        order,
+        grant,
+    });
+    Ok(Vec::new())
+}
+// removes every binding at the bindpoint, or only those of the location a given descriptor sits
+// at. descriptors walked through a removed binding go stale
+fn unmap(
+    process: &Process,
+    namespace: &Namespace,
+    descriptor: &Descriptor,
+    body: &[u8],
+) -> Result<Vec<u8>, FileError> {
+    let from = match wire::word(body, 0)? {
+        ANY_DESCRIPTOR => None,
+        index => {
+            let from = process.descriptor(index).ok_or(FileError::NotFound)?;
+            Some(Location::of(&from)?)
+        }
+    };
+    let name = body.get(size_of::<u64>()..).ok_or(FileError::Invalid)?;
+    let to = bindpoint(namespace, descriptor, name)?;
+    let mut bindings_write = namespace.bindings.write();
+    let count = bindings_write.len();
+    bindings_write.retain(|binding| {
+        !(same_server(&binding.to_server, &to.server)
+            && binding.to_path == to.path
+            && from.as_ref().is_none_or(|from| {
+                same_server(&binding.from_server, &from.server) && binding.from_path == from.path
+            }))
+    });
+    if bindings_write.len() == count {
+        return Err(FileError::NotFound);
+    }
+    Ok(Vec::new())
+}
+// a union lists every member in order, leaving out names an earlier member already listed and
+// members that are masked from being walked or cannot be listed at all
+fn list_union(
+    descriptor: &Descriptor,
+    members: &[Location],
+    request: &Request,
+) -> Result<Vec<u8>, FileError> {
+    let mut entries: Vec<Box<[u8]>> = Vec::new();
+    for member in members.iter().filter(|member| member.state_mask.walk) {
+        for name in list_all(member).unwrap_or_default() {
+            if !entries.contains(&name) {
+                entries.push(name);
+            }
+        }
+    }
+    let mut listing = descriptor.listing.write();
+    match request.selector {
+        Selector::List | Selector::ListPeek => Ok(wire::names(&listing.list(
+            entries,
+            wire::word(request.body, 0)? as usize,
+            request.selector == Selector::List,
+        )?)),
+        Selector::ListTell => Ok(wire::word_bytes(listing.position)),
+        _ => Ok(wire::word_bytes(
+            listing.seek(seek(request)?, entries.len())?,
+        )),
+    }
+}
+// reads a whole file from the start through a handle of its own, leaving the descriptor's head
+// where it was. a file that grows past the limit is refused rather than read on without end
+pub fn read_all(
+    namespace: &Namespace,
+    descriptor: &Descriptor,
+    limit: usize,
+) -> Result<Vec<u8>, FileError> {
+    if !descriptor.handle_mask.read {
+        return Err(FileError::Denied);
+    }
+    let mut handle = open(&primary(namespace, &Location::of(descriptor)?)?)?;
+    let mut content = Vec::new();
+    loop {
+        let chunk = match &mut handle {
+            Handle::Kernel(file) => file.read(READ_CHUNK as usize, true)?,
+            Handle::User { server, handle } => transact_handle(
+                server,
+                *handle,
+                Selector::Read,
+                &READ_CHUNK.to_le_bytes(),
+            )?,
+        };
+        if chunk.is_empty() {
+            return Ok(content);
+        }
+        if content.len() + chunk.len() > limit {
+            return Err(FileError::Invalid);
+        }
+        content.extend_from_slice(&chunk);
+    }
+}
+fn seek(request: &Request) -> Result<Seek, FileError> {
+    wire::seek(request.selector, wire::word(request.body, 0)?).ok_or(FileError::Invalid)
+}
+// carries out a request on a kernel-served file, encoding its result as a user server would
+fn execute(file: &mut dyn KernelFile, mask: State, request: &Request) -> Result<Vec<u8>, FileError> {
+    let argument = || wire::word(request.body, 0);
+    Ok(match request.selector {
+        Selector::ReadState => wire::word_bytes(wire::state_word(file.state() & mask)),
+        Selector::List | Selector::ListPeek => wire::names(&file.list(
+            argument()? as usize,
+            request.selector == Selector::List,
+        )?),
+        Selector::ListSeekForward
+        | Selector::ListSeekBackward
+        | Selector::ListSeekStart
+        | Selector::ListSeekEnd => wire::word_bytes(file.list_seek(seek(request)?)?),
+        Selector::ListTell => wire::word_bytes(file.list_tell()?),
+        Selector::Remove => {
+            file.remove(request.body)?;
+            Vec::new()
+        }
+        Selector::Rename => {
+            file.rename(request.body)?;
+            Vec::new()
+        }
+        Selector::Read | Selector::Peek => {
+            file.read(argument()? as usize, request.selector == Selector::Read)?
+        }
+        Selector::Insert => wire::word_bytes(file.insert(request.body)? as u64),
+        Selector::Overwrite => wire::word_bytes(file.overwrite(request.body)? as u64),
+        Selector::Truncate => wire::word_bytes(file.truncate(argument()? as usize)? as u64),
+        Selector::SeekForward
+        | Selector::SeekBackward
+        | Selector::SeekStart
+        | Selector::SeekEnd => wire::word_bytes(file.seek(seek(request)?)?),
+        Selector::Tell => wire::word_bytes(file.tell()?),
+        _ => return Err(FileError::Unsupported),
+    })
+}
+// the bits of a state a message needs on the descriptor it is sent through, with none needed by
+// the messages that are always allowed
+pub fn required(selector: Selector) -> State {
+    wire::state_from_word(selector.required_bit().map_or(0, |bit| 1 << bit))
+}
+pub fn permitted(selector: Selector, mask: State) -> bool {
+    selector.permitted(wire::state_word(mask))
+}
+// states are masked on their way to and from a user server, so a descriptor never shows or takes
+// on more than its mask allows
+fn state(descriptor: &Descriptor, request: &Request) -> Result<Vec<u8>, FileError> {
+    let mask = descriptor.handle_mask;
+    let user_handle = user_handle(&descriptor.handle.read());
+    let Some((server, handle)) = user_handle else {
+        let Handle::Kernel(file) = &mut *descriptor.handle.write() else {
+            return Err(FileError::Invalid);
+        };
+        return execute(&mut **file, mask, request);
+    };
+    match request.selector {
+        Selector::ReadState => {
+            let response = transact_handle(&server, handle, Selector::ReadState, &[])?;
+            let state = wire::state_from_word(wire::word(&response, 0)?) & mask;
+            Ok(wire::word_bytes(wire::state_word(state)))
+        }
+        _ => {
+            let state = wire::state_from_word(wire::word(request.body, 0)?) & mask;
+            let body = wire::word_bytes(wire::state_word(state));
+            transact_handle(&server, handle, Selector::WriteState, &body)
+        }
+    }
+}
+enum Route {
+    Answer(Result<Vec<u8>, FileError>),
+    Forward(Arc<RwSpinlock<UserServer>>, u64),
+    Lock(Location),
+}
+fn listing(selector: Selector) -> bool {
+    matches!(
+        selector,
+        Selector::List
+            | Selector::ListPeek
+            | Selector::ListSeekForward
+            | Selector::ListSeekBackward
+            | Selector::ListSeekStart
+            | Selector::ListSeekEnd
+            | Selector::ListTell
+    )
+}
+// messages that create or destroy descriptors, and listings of unions, are answered by the
+// kernel. anything else goes to the file's own server
+fn route(
+    process: &Process,
+    namespace: &Namespace,
+    descriptor: &Descriptor,
+    request: &Request,
+) -> Route {
+    let union = listing(request.selector)
+        .then(|| union_members(namespace, descriptor))
+        .flatten();
+    // walks and union listings start from the whole union, everything else goes to its first
+    // member alone
+    let mask = if request.selector == Selector::Walk || union.is_some() {
+        descriptor.state_mask
+    } else {
+        descriptor.handle_mask
+    };
+    if !permitted(request.selector, mask) {
+        return Route::Answer(Err(FileError::Denied));
+    }
+    if request.selector != Selector::Drop
+        && let Err(error) = usable(namespace, descriptor)
+    {
+        return Route::Answer(Err(error));
+    }
+    match request.selector {
+        Selector::Walk => {
+            return Route::Answer(
+                walk(namespace, descriptor, request.body)
+                    .map(|walked| wire::word_bytes(process.add_descriptor(walked))),
+            );
+        }
+        Selector::Make => return Route::Answer(make(process, namespace, descriptor, request.body)),
+        Selector::Drop => {
+            return Route::Answer(
+                process
+                    .remove_descriptor(request.descriptor)
+                    .map(|_| Vec::new())
+                    .ok_or(FileError::NotFound),
+            );
+        }
+        Selector::ReadState | Selector::WriteState => {
+            return Route::Answer(state(descriptor, request));
+        }
+        // locks are kept by the kernel, so servers never see a lock message
+        Selector::Lock => {
+            return match Location::of(descriptor).and_then(|location| primary(namespace, &location)) {
+                Ok(primary) => Route::Lock(primary),
+                Err(error) => Route::Answer(Err(error)),
+            };
+        }
+        Selector::Version => return Route::Answer(version(descriptor, request)),
+        Selector::Bind => return Route::Answer(bind(process, namespace, descriptor, request.body)),
+        Selector::Unmap => {
+            return Route::Answer(unmap(process, namespace, descriptor, request.body));
+        }
+        _ => {}
+    }
+    if let Some(members) = union {
+        return Route::Answer(list_union(descriptor, &members, request));
+    }
+    let mut handle_write = descriptor.handle.write();
+    let (server, handle) = match &mut *handle_write {
+        Handle::Kernel(file) => {
+            return Route::Answer(execute(&mut **file, descriptor.handle_mask, request));
+        }
+        Handle::User { server, handle } => (server.upgrade(), *handle),
+    };
+    // asking the server what it speaks may block, which is done without the handle held
+    drop(handle_write);
+    let Some(server) = server else {
+        return Route::Answer(Err(FileError::NotFound));
+    };
+    let ServerKind::User(user_server) = &server.kind else {
+        return Route::Answer(Err(FileError::NotFound));
+    };
+    match accepts(&server, request) {
+        Ok(()) => Route::Forward(user_server.clone(), handle),
+        Err(error) => Route::Answer(Err(error)),
+    }
+}
+// a descriptor handed to another process is opened afresh on the file it refers to, masked by
+// the state it is passed with. it no longer goes through any binding, so it stays valid in
+// whichever namespace it ends up in
+pub fn pass(process: &Process, passed: &[(u64, State)]) -> Result<Vec<Descriptor>, FileError> {
+    let namespace = process.namespace.read().clone();
+    passed
+        .iter()
+        .map(|&(index, mask)| {
+            let descriptor = process.descriptor(index).ok_or(FileError::NotFound)?;
+            usable(&namespace, &descriptor)?;
+            let mut file = primary(&namespace, &Location::of(&descriptor)?)?;
+            file.state_mask = file.state_mask & mask;
+            file.through = Vec::new();
+            let handle = open(&file)?;
+            Ok(new_descriptor(file.clone(), handle, &file, Grant::derive(&descriptor.grant)))
+        })
+        .collect()
+}
+// delivers a request sent through one of a process's descriptors under the client's tag, resolved
+// in the process's own namespace. a request forwarded to a user server has its descriptor
+// replaced by the server's own handle, and one the kernel answers has its response ready
+// straight away, dropping any descriptors passed along with it. fails only when out of memory
+pub fn send(
+    process: &Arc<Process>,
+    tag: u64,
+    mut bytes: Vec<u8>,
+    passed: Vec<Descriptor>,
+) -> Option<()> {
+    let namespace = process.namespace.read().clone();
+    let request = wire::parse_request(&bytes);
+    let descriptor = request
+        .as_ref()
+        .ok()
+        .and_then(|request| process.descriptor(request.descriptor));
+    let route = match (&request, &descriptor) {
+        (Ok(request), Some(descriptor)) => route(process, &namespace, descriptor, request),
+        (Ok(..), None) => Route::Answer(Err(FileError::NotFound)),
+        (Err(error), _) => Route::Answer(Err(*error)),
+    };
+    match route {
+        Route::Forward(user_server, handle) => {
+            wire::set_request_descriptor(&mut bytes, handle);
+            let frames = page::frames_from_bytes(&bytes)?;
+            let message = Arc::new(Message::new(tag, Arc::downgrade(process), frames));
+            *message.passed.write() = passed;
+            process.add_sent(&message);
+            user_server.read().enqueue(message);
+        }
+        Route::Answer(result) => {
+            let frames = page::frames_from_bytes(&wire::response(result))?;
+            let message = Arc::new(Message::new(tag, Arc::downgrade(process), Vec::new()));
+            process.add_sent(&message);
+            Message::respond(message, frames);
+        }
+        Route::Lock(file) => {
+            let descriptor = descriptor?;
+            let message = Arc::new(Message::new(tag, Arc::downgrade(process), Vec::new()));
+            process.add_sent(&message);
+            match locks::acquire(&file.server, &file.path, process.id, &descriptor, &message) {
+                Ok(Acquired::Now) => {
+                    locks::respond(message, locks::claim(&descriptor).map(|_| Vec::new()))
+                }
+                Ok(Acquired::Queued) => {}
+                Err(error) => locks::respond(message, Err(error)),
+            }
+        }
+    }
+    Some(())
+}
@@ -2,13 +2,15 @@ use crate::{
     frame::PAGE_FRAME_ALLOCATOR,
     mapping::{self, physical_to_virtual_address},
 };
+use alloc::vec::Vec;
+use core::slice;
 use lazy_static::lazy_static;
 use x86_64::{
-    PhysAddr,
+    PhysAddr, VirtAddr,
     registers::control::Cr3,
     structures::paging::{
-        FrameAllocator, FrameDeallocator, OffsetPageTable, PageTable, PageTableFlags,
-        PhysFrame, Size4KiB,
+        FrameAllocator, FrameDeallocator, Mapper, OffsetPageTable, Page, PageTable,
+        PageTableFlags, PhysFrame, Size4KiB, Translate, mapper::TranslateResult,
     },
 };
 lazy_static! {
@@ -21,26 +23,285 @@ lazy_static! {
         | PageTableFlags::USER_ACCESSIBLE
         | PageTableFlags::WRITABLE
         | PageTableFlags::PRESENT;
+    pub static ref DEVICE_PAGE_FLAGS: PageTableFlags = PageTableFlags::GLOBAL
+        | PageTableFlags::NO_CACHE
+        | PageTableFlags::WRITE_THROUGH
+        | PageTableFlags::WRITABLE
+        | PageTableFlags::PRESENT
+        | PageTableFlags::NO_EXECUTE;
+    // device memory mapped into a process is borrowed, never owned, so the frames behind it are
+    // left alone when the pages are unmapped or the table is cleared
+    pub static ref USER_DEVICE_PAGE_FLAGS: PageTableFlags = PageTableFlags::USER_ACCESSIBLE
+        | PageTableFlags::NO_CACHE
+        | PageTableFlags::WRITE_THROUGH
+        | PageTableFlags::WRITABLE
+        | PageTableFlags::PRESENT
+        | PageTableFlags::NO_EXECUTE
+        | BORROWED_PAGE_FLAG;
 }
+const BORROWED_PAGE_FLAG: PageTableFlags = PageTableFlags::BIT_9;
 pub fn get_current_pml4<'a>() -> *mut PageTable {
     physical_to_virtual_address(Cr3::read().0.start_address().as_u64()) as *mut PageTable
 }
 pub fn get_offset_table<'a>(table: &'a mut PageTable) -> OffsetPageTable<'a> {
     unsafe { OffsetPageTable::new(table, x86_64::VirtAddr::new(mapping::DIRECT_PHYSICAL)) }
 }
+// the bootloader only maps physical memory described by the memory map, so device memory (ecam,
+// hpet, lapic, bars) is mapped into the direct physical region on demand
+pub fn map_device_region(physical_address: u64, size: u64) -> u64 {
+    let mut table = get_offset_table(unsafe { &mut *get_current_pml4() });
+    let mut pfa_guard = PAGE_FRAME_ALLOCATOR.lock();
+    let pfa = pfa_guard
+        .as_mut()
+        .expect("page frame allocator not initialised before mapping device region!");
+    for frame_address in (physical_address & !(mapping::PAGE_SIZE - 1)..physical_address + size.max(1))
+        .step_by(mapping::PAGE_SIZE as usize)
+    {
+        let virtual_address = VirtAddr::new(physical_to_virtual_address(frame_address));
+        if table.translate_addr(virtual_address).is_some() {
+            continue;
+        }
+        unsafe {
+            table.map_to(
+                Page::<Size4KiB>::containing_address(virtual_address),
+                PhysFrame::containing_address(PhysAddr::new(frame_address)),
+                *DEVICE_PAGE_FLAGS,
+                pfa,
+            )
+        }
+        .expect("failed to map page during device region mapping!")
+        .flush();
+    }
+    physical_to_virtual_address(physical_address)
+}
+// frames that belong to no address space, such as those carried by a message, are given back
+// through here
+pub fn release_frames(frames: impl IntoIterator<Item = PhysFrame>) {
+    let mut pfa_guard = PAGE_FRAME_ALLOCATOR.lock();
+    let pfa = pfa_guard
+        .as_mut()
+        .expect("page frame allocator not initialised before frame releasing!");
+    for frame in frames {
+        unsafe { pfa.deallocate_frame(frame) };
+    }
+}
+// the kernel builds and reads the messages it handles itself through the direct physical
+// mapping, a message always taking up at least one page
+pub fn frames_from_bytes(bytes: &[u8]) -> Option<Vec<PhysFrame>> {
+    let mut frames = Vec::new();
+    for chunk_index in 0..bytes.len().div_ceil(mapping::PAGE_SIZE as usize).max(1) {
+        let frame = PAGE_FRAME_ALLOCATOR
+            .lock()
+            .as_mut()
+            .expect("page frame allocator not initialised before message building!")
+            .allocate_frame();
+        let Some(frame) = frame else {
+            release_frames(frames);
+            return None;
+        };
+        let page = unsafe {
+            slice::from_raw_parts_mut(
+                physical_to_virtual_address(frame.start_address().as_u64()) as *mut u8,
+                mapping::PAGE_SIZE as usize,
+            )
+        };
+        let chunk = bytes
+            .chunks(mapping::PAGE_SIZE as usize)
+            .nth(chunk_index)
+            .unwrap_or_default();
+        page[..chunk.len()].copy_from_slice(chunk);
+        page[chunk.len()..].fill(0);
+        frames.push(frame);
+    }
+    Some(frames)
+}
+pub fn frames_to_bytes(frames: &[PhysFrame]) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(frames.len() * mapping::PAGE_SIZE as usize);
+    for frame in frames {
+        bytes.extend_from_slice(unsafe {
+            slice::from_raw_parts(
+                physical_to_virtual_address(frame.start_address().as_u64()) as *const u8,
+                mapping::PAGE_SIZE as usize,
+            )
+        });
+    }
+    bytes
+}
 pub struct ManagedPageTable(*mut PageTable);
 impl ManagedPageTable {
+    // the higher half is shared by every address space, so the kernel's top-level entries are
+    // copied in and only the lower half is ever owned by the table
     pub fn new() -> ManagedPageTable {
         let mut pfa = PAGE_FRAME_ALLOCATOR.lock();
-        ManagedPageTable(physical_to_virtual_address(pfa.as_mut().expect("page frame allocator not initialised before managed page table initialisation!").allocate_frame().expect("failed to allocate frame during managed page table initialisation!").start_address().as_u64()) as *mut PageTable)
+        let table = unsafe { &mut *(physical_to_virtual_address(pfa.as_mut().expect("page frame allocator not initialised before managed page table initialisation!").allocate_frame().expect("failed to allocate frame during managed page table initialisation!").start_address().as_u64()) as *mut PageTable) };
+        let kernel_table = unsafe { &*get_current_pml4() };
+        for index in 256..512 {
+            table[index] = kernel_table[index].clone();
+        }
+        ManagedPageTable(table)
+    }
+    pub fn physical_address(&self) -> PhysAddr {
+        PhysAddr::new(self.0 as u64 - mapping::DIRECT_PHYSICAL)
+    }
+    // backs count pages from address with fresh zeroed frames. pages that are already mapped,
+    // such as one shared by two segments, keep their frame and gain the union of both permissions
+    pub fn map(&mut self, address: u64, count: u64, flags: PageTableFlags) -> Option<()> {
+        let mut table = get_offset_table(unsafe { &mut *self.0 });
+        let mut pfa_guard = PAGE_FRAME_ALLOCATOR.lock();
+        let pfa = pfa_guard
+            .as_mut()
+            .expect("page frame allocator not initialised before managed page table mapping!");
+        let parent_flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE | PageTableFlags::USER_ACCESSIBLE;
+        for page_index in 0..count {
+            let page = Page::<Size4KiB>::containing_address(VirtAddr::new(address + page_index * mapping::PAGE_SIZE));
+            if let TranslateResult::Mapped { flags: old_flags, .. } = table.translate(page.start_address()) {
+                let mut merged_flags = (old_flags | flags) & !PageTableFlags::NO_EXECUTE;
+                if old_flags.contains(PageTableFlags::NO_EXECUTE) & flags.contains(PageTableFlags::NO_EXECUTE) {
+                    merged_flags |= PageTableFlags::NO_EXECUTE;
+                }
+                unsafe { table.update_flags(page, merged_flags) }.ok()?.ignore();
+                continue;
+            }
+            let frame = pfa.allocate_frame()?;
+            unsafe { table.map_to_with_table_flags(page, frame, flags, parent_flags, pfa) }.ok()?.ignore();
+        }
+        Some(())
+    }
+    pub fn mapped(&self, address: u64) -> bool {
+        let table = get_offset_table(unsafe { &mut *self.0 });
+        table.translate_addr(VirtAddr::new(address)).is_some()
+    }
+    // frees the frames behind any mapped pages in the range. only this processor's translations
+    // are flushed, other processors running the same process still need tlb shootdowns
+    pub fn unmap(&mut self, address: u64, count: u64) {
+        let mut table = get_offset_table(unsafe { &mut *self.0 });
+        let mut pfa_guard = PAGE_FRAME_ALLOCATOR.lock();
+        let pfa = pfa_guard
+            .as_mut()
+            .expect("page frame allocator not initialised before managed page table unmapping!");
+        for page_index in 0..count {
+            let page = Page::<Size4KiB>::containing_address(VirtAddr::new(address + page_index * mapping::PAGE_SIZE));
+            let borrowed = matches!(
+                table.translate(page.start_address()),
+                TranslateResult::Mapped { flags, .. } if flags.contains(BORROWED_PAGE_FLAG)
+            );
+            if let Ok((frame, flush)) = table.unmap(page) {
+                flush.flush();
+                if !borrowed {
+                    unsafe { pfa.deallocate_frame(frame) };
+                }
+            }
+        }
+    }
+    // maps device memory at a wholly unmapped range, failing without mapping anything otherwise
+    pub fn map_device(&mut self, address: u64, physical_address: u64, count: u64) -> Option<()> {
+        let mut frames: Vec<PhysFrame> = (0..count)
+            .map(|page_index| PhysFrame::containing_address(PhysAddr::new(physical_address + page_index * mapping::PAGE_SIZE)))
+            .collect();
+        self.map_frames(address, &mut frames, *USER_DEVICE_PAGE_FLAGS)
+    }
+    // unmaps only those pages still borrowing the device memory mapped by map_device, so that
+    // anything the process has since mapped over it is kept
+    pub fn unmap_device(&mut self, address: u64, physical_address: u64, count: u64) {
+        let mut table = get_offset_table(unsafe { &mut *self.0 });
+        for page_index in 0..count {
+            let page = Page::<Size4KiB>::containing_address(VirtAddr::new(address + page_index * mapping::PAGE_SIZE));
+            let expected = PhysAddr::new(physical_address + page_index * mapping::PAGE_SIZE);
+            if let TranslateResult::Mapped { frame, flags, .. } = table.translate(page.start_address())
+                && flags.contains(BORROWED_PAGE_FLAG)
+                && frame.start_address() == expected
+                && let Ok((_, flush)) = table.unmap(page)
+            {
+                flush.flush();
+            }
+        }
+    }
+    // user memory is reached through the direct physical mapping rather than the user's own
+    // addresses, so the table does not need to be the active one
+    fn user_pointer(&self, address: u64) -> Option<*mut u8> {
+        let table = get_offset_table(unsafe { &mut *self.0 });
+        let physical = table.translate_addr(VirtAddr::new(address))?;
+        Some(physical_to_virtual_address(physical.as_u64()) as *mut u8)
+    }
+    pub fn write(&mut self, address: u64, bytes: &[u8]) -> Option<()> {
+        let mut written = 0;
+        while written < bytes.len() {
+            let current = address + written as u64;
+            let length = ((mapping::PAGE_SIZE - current % mapping::PAGE_SIZE) as usize).min(bytes.len() - written);
+            unsafe { slice::from_raw_parts_mut(self.user_pointer(current)?, length) }.copy_from_slice(&bytes[written..written + length]);
+            written += length;
+        }
+        Some(())
+    }
+    pub fn read(&self, address: u64, buffer: &mut [u8]) -> Option<()> {
+        let mut read = 0;
+        while read < buffer.len() {
+            let current = address + read as u64;
+            let length = ((mapping::PAGE_SIZE - current % mapping::PAGE_SIZE) as usize).min(buffer.len() - read);
+            buffer[read..read + length].copy_from_slice(unsafe { slice::from_raw_parts(self.user_pointer(current)?, length) });
+            read += length;
+        }
+        Some(())
     }
 }
-impl Drop for ManagedPageTable {
-    fn drop(&mut self) {
+impl ManagedPageTable {
+    // copies whole pages into fresh frames owned by nothing, failing if any page is unmapped.
+    // messages are copied rather than shared, so the sender is free to reuse its pages at once
+    pub fn copy_frames(&self, address: u64, count: u64) -> Option<Vec<PhysFrame>> {
+        let mut frames = Vec::new();
+        for page_index in 0..count {
+            let source = self.user_pointer(address + page_index * mapping::PAGE_SIZE);
+            let frame = source.and_then(|_| {
+                PAGE_FRAME_ALLOCATOR
+                    .lock()
+                    .as_mut()
+                    .expect("page frame allocator not initialised before frame copying!")
+                    .allocate_frame()
+            });
+            let (Some(source), Some(frame)) = (source, frame) else {
+                release_frames(frames.into_iter().chain(frame));
+                return None;
+            };
+            let destination = physical_to_virtual_address(frame.start_address().as_u64()) as *mut u8;
+            unsafe { destination.copy_from_nonoverlapping(source, mapping::PAGE_SIZE as usize) };
+            frames.push(frame);
+        }
+        Some(frames)
+    }
+    // maps frames handed over from elsewhere at a wholly unmapped range. the frames only leave
+    // the vector once every one of them is mapped, so a failed mapping leaves them with the caller
+    pub fn map_frames(&mut self, address: u64, frames: &mut Vec<PhysFrame>, flags: PageTableFlags) -> Option<()> {
+        let pages = (0..frames.len() as u64)
+            .map(|page_index| Page::<Size4KiB>::containing_address(VirtAddr::new(address + page_index * mapping::PAGE_SIZE)));
+        if pages.clone().any(|page| self.mapped(page.start_address().as_u64())) {
+            return None;
+        }
+        let mut table = get_offset_table(unsafe { &mut *self.0 });
+        let mut pfa_guard = PAGE_FRAME_ALLOCATOR.lock();
+        let pfa = pfa_guard
+            .as_mut()
+            .expect("page frame allocator not initialised before managed page table frame mapping!");
+        let parent_flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE | PageTableFlags::USER_ACCESSIBLE;
+        for (mapped, (page, frame)) in pages.clone().zip(frames.iter()).enumerate() {
+            if unsafe { table.map_to_with_table_flags(page, *frame, flags, parent_flags, pfa) }.is_err() {
+                for page in pages.take(mapped) {
+                    if let Ok((_, flush)) = table.unmap(page) {
+                        flush.flush();
+                    }
+                }
+                return None;
+            }
+        }
+        frames.clear();
+        Some(())
+    }
+    // frees everything the table owns in the lower half, including the frames mapped there,
+    // leaving only the top-level table and the shared kernel half
+    pub fn clear(&mut self) {
         let mut pfa_guard = PAGE_FRAME_ALLOCATOR.lock();
         let pfa = pfa_guard
             .as_mut()
-            .expect("page frame allocator not initialised before managed page table dropping!");
+            .expect("page frame allocator not initialised before managed page table clearing!");
         fn free_page_table_level(
             table_frame: PhysFrame<Size4KiB>,
             level: u8,
@@ -67,6 +328,9 @@ impl Drop for ManagedPageTable {
                         entry.set_unused();
                     }
                     0 => {
+                        if !entry.flags().contains(BORROWED_PAGE_FLAG) {
+                            unsafe { pfa.deallocate_frame(PhysFrame::containing_address(entry.addr())) };
+                        }
                         entry.set_unused();
                     }
                     _ => unreachable!(
@@ -76,8 +340,6 @@ impl Drop for ManagedPageTable {
             }
             unsafe { pfa.deallocate_frame(table_frame) };
         }
-        let pml4_frame =
-            PhysFrame::containing_address(PhysAddr::new(self.0 as u64 - mapping::DIRECT_PHYSICAL));
         for i in 0..256 {
             let entry = &mut unsafe { &mut *self.0 }[i];
             if entry.flags().contains(PageTableFlags::PRESENT) {
@@ -86,7 +348,46 @@ impl Drop for ManagedPageTable {
                 entry.set_unused();
             }
         }
-        unsafe { pfa.deallocate_frame(pml4_frame) };
+    }
+    // counts the pages mapped in the lower half and the frames taken up by the tables mapping
+    // them, including the top-level table
+    pub fn usage(&self) -> (u64, u64) {
+        fn count_page_table_level(table_frame: PhysFrame<Size4KiB>, level: u8) -> (u64, u64) {
+            let table_virt = physical_to_virtual_address(table_frame.start_address().as_u64());
+            let table = unsafe { &*(table_virt as *const PageTable) };
+            let mut usage = (0, 1);
+            for entry in table.iter().filter(|entry| entry.flags().contains(PageTableFlags::PRESENT)) {
+                if level == 0 {
+                    usage.0 += 1;
+                } else {
+                    let (pages, tables) = count_page_table_level(PhysFrame::containing_address(entry.addr()), level - 1);
+                    usage.0 += pages;
+                    usage.1 += tables;
+                }
+            }
+            usage
+        }
+        let table: &PageTable = unsafe { &*self.0 };
+        let mut usage = (0, 1);
+        for entry in table.iter().take(256).filter(|entry| entry.flags().contains(PageTableFlags::PRESENT)) {
+            let (pages, tables) = count_page_table_level(PhysFrame::containing_address(entry.addr()), 2);
+            usage.0 += pages;
+            usage.1 += tables;
+        }
+        usage
+    }
+}
+impl Drop for ManagedPageTable {
+    fn drop(&mut self) {
+        self.clear();
+        let pml4_frame = PhysFrame::containing_address(self.physical_address());
+        unsafe {
+            PAGE_FRAME_ALLOCATOR
+                .lock()
+                .as_mut()
+                .expect("page frame allocator not initialised before managed page table dropping!")
+                .deallocate_frame(pml4_frame)
+        };
     }
 }
 unsafe impl Send for ManagedPageTable {}
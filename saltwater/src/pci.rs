@@ -0,0 +1,420 @@
+use crate::{acpi::ACPI_PLATFORM, page::map_device_region, port, println};
+use acpi::{PciAddress, platform::PciConfigRegions};
+use alloc::vec::Vec;
+use spinning_top::{RwSpinlock, Spinlock};
+const LEGACY_ADDRESS_PORT: u16 = 0xcf8;
+const LEGACY_DATA_PORT: u16 = 0xcfc;
+const LEGACY_CONFIG_SIZE: u16 = 0x100;
+const ECAM_CONFIG_SIZE: u16 = 0x1000;
+const VENDOR_OFFSET: u16 = 0x00;
+const DEVICE_OFFSET: u16 = 0x02;
+const COMMAND_OFFSET: u16 = 0x04;
+const STATUS_OFFSET: u16 = 0x06;
+const REVISION_OFFSET: u16 = 0x08;
+const INTERFACE_OFFSET: u16 = 0x09;
+const SUBCLASS_OFFSET: u16 = 0x0a;
+const CLASS_OFFSET: u16 = 0x0b;
+const HEADER_TYPE_OFFSET: u16 = 0x0e;
+const BAR_OFFSET: u16 = 0x10;
+const SECONDARY_BUS_OFFSET: u16 = 0x19;
+const CAPABILITY_POINTER_OFFSET: u16 = 0x34;
+const INTERRUPT_LINE_OFFSET: u16 = 0x3c;
+const INTERRUPT_PIN_OFFSET: u16 = 0x3d;
+const EXTENDED_CAPABILITY_OFFSET: u16 = 0x100;
+const COMMAND_DECODE_MASK: u16 = 0b11;
+const STATUS_CAPABILITY_LIST: u16 = 1 << 4;
+const HEADER_TYPE_MULTIFUNCTION: u8 = 1 << 7;
+const INVALID_VENDOR: u16 = 0xffff;
+const MAX_CAPABILITIES: usize = 48;
+// each mcfg region is mapped once, so a configuration access is only ever a load or a store
+struct EcamRegion {
+    segment: u16,
+    bus_start: u8,
+    bus_end: u8,
+    base: u64,
+}
+enum ConfigAccess {
+    Ecam(Vec<EcamRegion>),
+    Legacy,
+}
+static CONFIG_ACCESS: RwSpinlock<Option<ConfigAccess>> = RwSpinlock::new(None);
+// the legacy mechanism is an address/data port pair, so accesses must not interleave
+static LEGACY_LOCK: Spinlock<()> = Spinlock::new(());
+#[derive(Clone, Copy, Debug)]
+pub enum Bar {
+    Memory32 {
+        address: u32,
+        size: u32,
+        prefetchable: bool,
+    },
+    Memory64 {
+        address: u64,
+        size: u64,
+        prefetchable: bool,
+    },
+    Io {
+        port: u32,
+        size: u32,
+    },
+}
+impl Bar {
+    pub fn address(&self) -> u64 {
+        match self {
+            Bar::Memory32 { address, .. } => *address as u64,
+            Bar::Memory64 { address, .. } => *address,
+            Bar::Io { port, .. } => *port as u64,
+        }
+    }
+    pub fn size(&self) -> u64 {
+        match self {
+            Bar::Memory32 { size, .. } => *size as u64,
+            Bar::Memory64 { size, .. } => *size,
+            Bar::Io { size, .. } => *size as u64,
+        }
+    }
+}
+#[derive(Clone, Copy, Debug)]
+pub struct Capability {
+    pub id: u16,
+    pub offset: u16,
+    pub extended: bool,
+}
+#[derive(Clone, Debug)]
+pub struct PciDevice {
+    pub address: PciAddress,
+    pub vendor_id: u16,
+    pub device_id: u16,
+    pub revision: u8,
+    pub class: u8,
+    pub subclass: u8,
+    pub interface: u8,
+    pub header_type: u8,
+    pub secondary_bus: Option<u8>,
+    pub bars: [Option<Bar>; 6],
+    pub capabilities: Vec<Capability>,
+    pub interrupt_pin: u8,
+    pub interrupt_line: u8,
+    pub gsi: Option<u32>,
+}
+impl PciDevice {
+    pub fn capability(&self, id: u16, extended: bool) -> Option<Capability> {
+        self.capabilities
+            .iter()
+            .find(|capability| (capability.id == id) & (capability.extended == extended))
+            .copied()
+    }
+}
+pub static PCI_DEVICES: RwSpinlock<Vec<PciDevice>> = RwSpinlock::new(Vec::new());
+// only segment 0 is reachable through the legacy ports, and anything else is treated as absent,
+// since aml may well ask for it
+fn legacy_address(address: PciAddress, offset: u16) -> Option<u32> {
+    if address.segment() != 0 {
+        return None;
+    }
+    Some(
+        0x8000_0000
+            | (address.bus() as u32) << 16
+            | (address.device() as u32) << 11
+            | (address.function() as u32) << 8
+            | (offset & 0xfc) as u32,
+    )
+}
+pub fn config_size() -> u16 {
+    match CONFIG_ACCESS.read().as_ref() {
+        Some(ConfigAccess::Ecam(..)) => ECAM_CONFIG_SIZE,
+        _ => LEGACY_CONFIG_SIZE,
+    }
+}
+fn ecam_address(regions: &[EcamRegion], address: PciAddress, offset: u16) -> Option<u64> {
+    let region = regions.iter().find(|region| {
+        region.segment == address.segment()
+            && (region.bus_start..=region.bus_end).contains(&address.bus())
+    })?;
+    Some(
+        region.base
+            + (((address.bus() - region.bus_start) as u64) << 20
+                | (address.device() as u64) << 15
+                | (address.function() as u64) << 12)
+            + offset as u64,
+    )
+}
+fn map_ecam(regions: &PciConfigRegions) -> Vec<EcamRegion> {
+    regions
+        .regions
+        .iter()
+        .map(|region| {
+            let (base, bus_start, bus_end) = (
+                region.base_address,
+                region.bus_number_start,
+                region.bus_number_end,
+            );
+            let size = ((bus_end.saturating_sub(bus_start) as u64) + 1) << 20;
+            EcamRegion {
+                segment: region.pci_segment_group,
+                bus_start,
+                bus_end,
+                base: map_device_region(base + ((bus_start as u64) << 20), size),
+            }
+        })
+        .collect()
+}
+macro_rules! config_access {
+    ($read:ident, $write:ident, $type:ty, $read_port:path, $write_port:path) => {
+        pub fn $read(address: PciAddress, offset: u16) -> $type {
+            if offset as usize + size_of::<$type>() > config_size() as usize {
+                return <$type>::MAX;
+            }
+            match CONFIG_ACCESS.read().as_ref() {
+                Some(ConfigAccess::Ecam(regions)) => match ecam_address(regions, address, offset) {
+                    Some(virtual_address) => unsafe {
+                        (virtual_address as *const $type).read_volatile()
+                    },
+                    None => <$type>::MAX,
+                },
+                Some(ConfigAccess::Legacy) => {
+                    let Some(legacy_address) = legacy_address(address, offset) else {
+                        return <$type>::MAX;
+                    };
+                    let _legacy_guard = LEGACY_LOCK.lock();
+                    unsafe {
+                        port::write_u32(LEGACY_ADDRESS_PORT, legacy_address);
+                        $read_port(LEGACY_DATA_PORT + (offset & 0b11))
+                    }
+                }
+                None => panic!("pci configuration space read before pci initialisation!"),
+            }
+        }
+        pub fn $write(address: PciAddress, offset: u16, value: $type) {
+            if offset as usize + size_of::<$type>() > config_size() as usize {
+                return;
+            }
+            match CONFIG_ACCESS.read().as_ref() {
+                Some(ConfigAccess::Ecam(regions)) => {
+                    if let Some(virtual_address) = ecam_address(regions, address, offset) {
+                        unsafe { (virtual_address as *mut $type).write_volatile(value) }
+                    }
+                }
+                Some(ConfigAccess::Legacy) => {
+                    let Some(legacy_address) = legacy_address(address, offset) else {
+                        return;
+                    };
+                    let _legacy_guard = LEGACY_LOCK.lock();
+                    unsafe {
+                        port::write_u32(LEGACY_ADDRESS_PORT, legacy_address);
+                        $write_port(LEGACY_DATA_PORT + (offset & 0b11), value)
+                    }
+                }
+                None => panic!("pci configuration space write before pci initialisation!"),
+            }
+        }
+    };
+}
+config_access!(read_u8, write_u8, u8, port::read_u8, port::write_u8);
+config_access!(read_u16, write_u16, u16, port::read_u16, port::write_u16);
+config_access!(read_u32, write_u32, u32, port::read_u32, port::write_u32);
+fn probe_bars(address: PciAddress, bar_count: u16) -> [Option<Bar>; 6] {
+    let mut bars = [None; 6];
+    let command = read_u16(address, COMMAND_OFFSET);
+    // decoding is disabled while sizing so the all-ones probe cannot alias another device
+    write_u16(address, COMMAND_OFFSET, command & !COMMAND_DECODE_MASK);
+    let mut index = 0;
+    while index < bar_count {
+        let offset = BAR_OFFSET + index * 4;
+        let original = read_u32(address, offset);
+        write_u32(address, offset, u32::MAX);
+        let probed = read_u32(address, offset);
+        write_u32(address, offset, original);
+        if original & 0b1 != 0 {
+            if probed & !0b11 != 0 {
+                bars[index as usize] = Some(Bar::Io {
+                    port: original & !0b11,
+                    size: (!(probed & !0b11) & 0xffff).wrapping_add(1),
+                });
+            }
+            index += 1;
+        } else if (original >> 1) & 0b11 == 0b10 && index + 1 == bar_count {
+            // a 64-bit bar in the last slot has no upper half to read, so it is left out
+            index += 1;
+        } else if (original >> 1) & 0b11 == 0b10 {
+            let original_high = read_u32(address, offset + 4);
+            write_u32(address, offset + 4, u32::MAX);
+            let probed_high = read_u32(address, offset + 4);
+            write_u32(address, offset + 4, original_high);
+            let probed_full = (probed_high as u64) << 32 | (probed & !0b1111) as u64;
+            if probed_full != 0 {
+                bars[index as usize] = Some(Bar::Memory64 {
+                    address: (original_high as u64) << 32 | (original & !0b1111) as u64,
+                    size: (!probed_full).wrapping_add(1),
+                    prefetchable: original & 0b1000 != 0,
+                });
+            }
+            index += 2;
+        } else {
+            if probed & !0b1111 != 0 {
+                bars[index as usize] = Some(Bar::Memory32 {
+                    address: original & !0b1111,
+                    size: (!(probed & !0b1111)).wrapping_add(1),
+                    prefetchable: original & 0b1000 != 0,
+                });
+            }
+            index += 1;
+        }
+    }
+    write_u16(address, COMMAND_OFFSET, command);
+    bars
+}
+fn probe_capabilities(address: PciAddress) -> Vec<Capability> {
+    let mut capabilities = Vec::new();
+    if read_u16(address, STATUS_OFFSET) & STATUS_CAPABILITY_LIST != 0 {
+        let mut offset = (read_u8(address, CAPABILITY_POINTER_OFFSET) & 0xfc) as u16;
+        // the walk is bounded in case firmware or a device presents a looping list
+        while (offset != 0) & (capabilities.len() < MAX_CAPABILITIES) {
+            capabilities.push(Capability {
+                id: read_u8(address, offset) as u16,
+                offset,
+                extended: false,
+            });
+            offset = (read_u8(address, offset + 1) & 0xfc) as u16;
+        }
+    }
+    if config_size() == ECAM_CONFIG_SIZE {
+        let mut offset = EXTENDED_CAPABILITY_OFFSET;
+        let mut extended_count = 0;
+        while (offset != 0) & (extended_count < MAX_CAPABILITIES) {
+            let header = read_u32(address, offset);
+            if (header == 0) | (header == u32::MAX) {
+                break;
+            }
+            capabilities.push(Capability {
+                id: (header & 0xffff) as u16,
+                offset,
+                extended: true,
+            });
+            extended_count += 1;
+            offset = ((header >> 20) & 0xffc) as u16;
+        }
+    }
+    capabilities
+}
+fn probe_function(address: PciAddress) -> Option<PciDevice> {
+    let vendor_id = read_u16(address, VENDOR_OFFSET);
+    if vendor_id == INVALID_VENDOR {
+        return None;
+    }
+    let header_type = read_u8(address, HEADER_TYPE_OFFSET);
+    let bar_count = match header_type & !HEADER_TYPE_MULTIFUNCTION {
+        0 => 6,
+        1 => 2,
+        _ => 0,
+    };
+    Some(PciDevice {
+        address,
+        vendor_id,
+        device_id: read_u16(address, DEVICE_OFFSET),
+        revision: read_u8(address, REVISION_OFFSET),
+        class: read_u8(address, CLASS_OFFSET),
+        subclass: read_u8(address, SUBCLASS_OFFSET),
+        interface: read_u8(address, INTERFACE_OFFSET),
+        header_type,
+        secondary_bus: (header_type & !HEADER_TYPE_MULTIFUNCTION == 1)
+            .then(|| read_u8(address, SECONDARY_BUS_OFFSET)),
+        bars: probe_bars(address, bar_count),
+        capabilities: probe_capabilities(address),
+        interrupt_pin: read_u8(address, INTERRUPT_PIN_OFFSET),
+        interrupt_line: read_u8(address, INTERRUPT_LINE_OFFSET),
+        gsi: None,
+    })
+}
+fn enumerate_bus(segment: u16, bus: u8, devices: &mut Vec<PciDevice>, visited: &mut [bool; 256]) {
+    if visited[bus as usize] {
+        return;
+    }
+    visited[bus as usize] = true;
+    for device in 0..32 {
+        let Some(first_function) = probe_function(PciAddress::new(segment, bus, device, 0)) else {
+            continue;
+        };
+        let function_count = if first_function.header_type & HEADER_TYPE_MULTIFUNCTION != 0 {
+            8
+        } else {
+            1
+        };
+        let mut functions = Vec::from([first_function]);
+        functions.extend((1..function_count).filter_map(|function| {
+            probe_function(PciAddress::new(segment, bus, device, function))
+        }));
+        for function in functions {
+            // pci-to-pci bridges lead to further buses, which are walked depth-first
+            let secondary_bus = function.secondary_bus;
+            devices.push(function);
+            if let Some(secondary_bus) = secondary_bus {
+                enumerate_bus(segment, secondary_bus, devices, visited);
+            }
+        }
+    }
+}
+pub fn initialise(_boot_info: &mut bootloader_api::BootInfo) {
+    let segments = {
+        let acpi_platform_guard = ACPI_PLATFORM.read();
+        let acpi_platform = acpi_platform_guard
+            .as_ref()
+            .expect("ACPI platform not initialised before pci initialisation!");
+        match PciConfigRegions::new(&acpi_platform.tables) {
+            Ok(regions) => {
+                println!(
+                    "found MCFG with {} pci segment group/s, using enhanced configuration access...",
+                    regions.regions.len()
+                );
+                let mut segments = regions
+                    .regions
+                    .iter()
+                    .map(|region| region.pci_segment_group)
+                    .collect::<Vec<u16>>();
+                segments.sort();
+                segments.dedup();
+                let _ = CONFIG_ACCESS
+                    .write()
+                    .insert(ConfigAccess::Ecam(map_ecam(&regions)));
+                segments
+            }
+            Err(_) => {
+                println!("no MCFG found, falling back to legacy pci configuration access...");
+                let _ = CONFIG_ACCESS.write().insert(ConfigAccess::Legacy);
+                Vec::from([0])
+            }
+        }
+    };
+    let mut devices = Vec::new();
+    for segment in segments {
+        let mut visited = [false; 256];
+        enumerate_bus(segment, 0, &mut devices, &mut visited);
+        // host bridges presenting several functions each own a root bus of their own
+        if read_u8(PciAddress::new(segment, 0, 0, 0), HEADER_TYPE_OFFSET)
+            & HEADER_TYPE_MULTIFUNCTION
+            != 0
+        {
+            for function in 1..8 {
+                if read_u16(PciAddress::new(segment, 0, 0, function), VENDOR_OFFSET)
+                    != INVALID_VENDOR
+                {
+                    enumerate_bus(segment, function, &mut devices, &mut visited);
+                }
+            }
+        }
+    }
+    for device in devices.iter() {
+        println!(
+            "found pci device {} {:04x}:{:04x} class {:02x}.{:02x}.{:02x} with {} bar/s and {} capability/ies...",
+            device.address,
+            device.vendor_id,
+            device.device_id,
+            device.class,
+            device.subclass,
+            device.interface,
+            device.bars.iter().flatten().count(),
+            device.capabilities.len()
+        );
+    }
+    println!("enumerated {} pci function/s...", devices.len());
+    *PCI_DEVICES.write() = devices;
+}
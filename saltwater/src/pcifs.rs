@@ -0,0 +1,480 @@
+use crate::{
+    file::{self, FileError, KernelFile, KernelFilesystem, Listing, Seek},
+    mapping::{PAGE_SIZE, USER_THREAD_AREAS},
+    page::map_device_region,
+    pci::{self, Bar, PCI_DEVICES, PciDevice},
+    port, println,
+    proc::{Process, State},
+    scheduler::current_thread,
+};
+use alloc::{
+    boxed::Box,
+    format,
+    string::String,
+    sync::{Arc, Weak},
+    vec::Vec,
+};
+use spinning_top::{RwSpinlock, Spinlock};
+const MSI_CAPABILITY: u16 = 0x05;
+const MSIX_CAPABILITY: u16 = 0x11;
+const CONFIG_NAME: &[u8] = b"config";
+const IRQ_NAME: &[u8] = b"irq";
+// holding a claim is what grants bar access and configuration writes, and it is released once
+// the locking descriptor and every descriptor walked from it have been dropped. bars mapped while
+// it is held are unmapped from their processes along with it
+struct Claim {
+    mappings: Spinlock<Vec<Mapping>>,
+}
+struct Mapping {
+    process: Weak<Process>,
+    address: u64,
+    physical_address: u64,
+    count: u64,
+}
+impl Drop for Claim {
+    fn drop(&mut self) {
+        for mapping in self.mappings.get_mut().drain(..) {
+            if let Some(process) = mapping.process.upgrade() {
+                process.pages.write().unmap_device(
+                    mapping.address,
+                    mapping.physical_address,
+                    mapping.count,
+                );
+            }
+        }
+    }
+}
+static CLAIMS: RwSpinlock<Vec<Weak<Claim>>> = RwSpinlock::new(Vec::new());
+#[derive(Clone, Copy)]
+enum Node {
+    Root,
+    Device(usize),
+    Config(usize),
+    Bar(usize, usize),
+    Map(usize, usize),
+    Irq(usize),
+}
+struct PciFile {
+    node: Node,
+    claim: Option<Arc<Claim>>,
+    listing: Listing,
+    position: u64,
+}
+struct PciFilesystem;
+static PCI_FILESYSTEM: PciFilesystem = PciFilesystem;
+impl KernelFilesystem for PciFilesystem {
+    fn attach(&self) -> Box<dyn KernelFile> {
+        Box::new(PciFile::new(Node::Root, None))
+    }
+}
+fn device_name(device: &PciDevice) -> Box<[u8]> {
+    let address = device.address;
+    if address.segment() == 0 {
+        format!(
+            "{:02x}:{:02x}.{:x}",
+            address.bus(),
+            address.device(),
+            address.function()
+        )
+    } else {
+        format!(
+            "{:04x}:{:02x}:{:02x}.{:x}",
+            address.segment(),
+            address.bus(),
+            address.device(),
+            address.function()
+        )
+    }
+    .into_bytes()
+    .into_boxed_slice()
+}
+fn bar_name(index: usize) -> Box<[u8]> {
+    format!("bar{}", index).into_bytes().into_boxed_slice()
+}
+fn map_name(index: usize) -> Box<[u8]> {
+    format!("bar{}.map", index).into_bytes().into_boxed_slice()
+}
+fn device_bar(device: usize, index: usize) -> Option<Bar> {
+    PCI_DEVICES.read().get(device)?.bars[index]
+}
+// only memory bars covering whole pages can be mapped, anything smaller would share its pages
+// with whatever else the firmware placed beside it
+fn mappable(bar: &Bar) -> bool {
+    !matches!(bar, Bar::Io { .. })
+        && bar.address().is_multiple_of(PAGE_SIZE)
+        && bar.size().is_multiple_of(PAGE_SIZE)
+        && bar.size() != 0
+}
+fn irq_description(device: &PciDevice) -> String {
+    let mut description = match (device.interrupt_pin, device.gsi) {
+        (1..=4, Some(gsi)) => format!(
+            "pin {} gsi {}\n",
+            (b'a' + device.interrupt_pin - 1) as char,
+            gsi
+        ),
+        (1..=4, None) => format!(
+            "pin {} line {}\n",
+            (b'a' + device.interrupt_pin - 1) as char,
+            device.interrupt_line
+        ),
+        _ => String::from("pin none\n"),
+    };
+    if let Some(msi) = device.capability(MSI_CAPABILITY, false) {
+        let control = pci::read_u16(device.address, msi.offset + 2);
+        description.push_str(&format!("msi {}\n", 1 << ((control >> 1) & 0b111)));
+    }
+    if let Some(msix) = device.capability(MSIX_CAPABILITY, false) {
+        let control = pci::read_u16(device.address, msix.offset + 2);
+        description.push_str(&format!("msi-x {}\n", (control & 0x7ff) + 1));
+    }
+    description
+}
+// mmio and port i/o are performed at the widest width the offset and length allow, since
+// many devices misbehave when their registers are accessed a byte at a time
+fn access_width(position: u64, length: usize, widest: usize) -> usize {
+    [8, 4, 2, 1]
+        .into_iter()
+        .filter(|width| *width <= widest)
+        .find(|width| (position as usize).is_multiple_of(*width) & length.is_multiple_of(*width))
+        .unwrap_or(1)
+}
+fn bar_read(bar: Bar, position: u64, length: usize) -> Vec<u8> {
+    let mut content = Vec::with_capacity(length);
+    match bar {
+        Bar::Io { port, .. } => {
+            let width = access_width(position, length, 4);
+            for offset in (0..length).step_by(width) {
+                let port = (port as u64 + position + offset as u64) as u16;
+                match width {
+                    4 => content.extend(unsafe { port::read_u32(port) }.to_le_bytes()),
+                    2 => content.extend(unsafe { port::read_u16(port) }.to_le_bytes()),
+                    _ => content.push(unsafe { port::read_u8(port) }),
+                }
+            }
+        }
+        _ => {
+            let base = map_device_region(bar.address() + position, length as u64);
+            let width = access_width(position, length, 8);
+            for offset in (0..length).step_by(width) {
+                let address = base + offset as u64;
+                match width {
+                    8 => content
+                        .extend(unsafe { (address as *const u64).read_volatile() }.to_le_bytes()),
+                    4 => content
+                        .extend(unsafe { (address as *const u32).read_volatile() }.to_le_bytes()),
+                    2 => content
+                        .extend(unsafe { (address as *const u16).read_volatile() }.to_le_bytes()),
+                    _ => content.push(unsafe { (address as *const u8).read_volatile() }),
+                }
+            }
+        }
+    }
+    content
+}
+fn bar_write(bar: Bar, position: u64, content: &[u8]) {
+    match bar {
+        Bar::Io { port, .. } => {
+            let width = access_width(position, content.len(), 4);
+            for (offset, chunk) in content.chunks(width).enumerate() {
+                let port = (port as u64 + position + (offset * width) as u64) as u16;
+                match width {
+                    4 => unsafe {
+                        port::write_u32(port, u32::from_le_bytes(chunk.try_into().unwrap()))
+                    },
+                    2 => unsafe {
+                        port::write_u16(port, u16::from_le_bytes(chunk.try_into().unwrap()))
+                    },
+                    _ => unsafe { port::write_u8(port, chunk[0]) },
+                }
+            }
+        }
+        _ => {
+            let base = map_device_region(bar.address() + position, content.len() as u64);
+            let width = access_width(position, content.len(), 8);
+            for (offset, chunk) in content.chunks(width).enumerate() {
+                let address = base + (offset * width) as u64;
+                match width {
+                    8 => unsafe {
+                        (address as *mut u64)
+                            .write_volatile(u64::from_le_bytes(chunk.try_into().unwrap()))
+                    },
+                    4 => unsafe {
+                        (address as *mut u32)
+                            .write_volatile(u32::from_le_bytes(chunk.try_into().unwrap()))
+                    },
+                    2 => unsafe {
+                        (address as *mut u16)
+                            .write_volatile(u16::from_le_bytes(chunk.try_into().unwrap()))
+                    },
+                    _ => unsafe { (address as *mut u8).write_volatile(chunk[0]) },
+                }
+            }
+        }
+    }
+}
+impl PciFile {
+    fn new(node: Node, claim: Option<Arc<Claim>>) -> PciFile {
+        PciFile {
+            node,
+            claim,
+            listing: Listing::new(),
+            position: 0,
+        }
+    }
+    fn entries(&self) -> Vec<Box<[u8]>> {
+        match self.node {
+            Node::Root => PCI_DEVICES.read().iter().map(device_name).collect(),
+            Node::Device(device) => [Box::from(CONFIG_NAME), Box::from(IRQ_NAME)]
+                .into_iter()
+                .chain(
+                    (0..6)
+                        .filter(|index| device_bar(device, *index).is_some())
+                        .map(bar_name),
+                )
+                .chain(
+                    (0..6)
+                        .filter(|index| device_bar(device, *index).is_some_and(|bar| mappable(&bar)))
+                        .map(map_name),
+                )
+                .collect(),
+            _ => Vec::new(),
+        }
+    }
+    fn length(&self) -> u64 {
+        match self.node {
+            Node::Config(..) => pci::config_size() as u64,
+            Node::Bar(device, index) => device_bar(device, index).map_or(0, |bar| bar.size()),
+            Node::Irq(device) => PCI_DEVICES
+                .read()
+                .get(device)
+                .map_or(0, |device| irq_description(device).len() as u64),
+            _ => 0,
+        }
+    }
+    fn claimed(&self) -> bool {
+        self.claim.is_some()
+    }
+    fn bounded_length(&self, length: usize) -> usize {
+        length.min(self.length().saturating_sub(self.position) as usize)
+    }
+    // a page-aligned address written to a map file, as a little-endian word, maps the whole bar
+    // there in the process that wrote it
+    fn map(&self, device: usize, index: usize, content: &[u8]) -> Result<usize, FileError> {
+        let Some(claim) = &self.claim else {
+            return Err(FileError::Denied);
+        };
+        let address = u64::from_le_bytes(content.try_into().map_err(|_| FileError::Invalid)?);
+        let bar = device_bar(device, index)
+            .filter(mappable)
+            .ok_or(FileError::NotFound)?;
+        if !address.is_multiple_of(PAGE_SIZE)
+            || address
+                .checked_add(bar.size())
+                .is_none_or(|end| end > USER_THREAD_AREAS)
+        {
+            return Err(FileError::Invalid);
+        }
+        let process = current_thread()
+            .read()
+            .process
+            .upgrade()
+            .ok_or(FileError::NotFound)?;
+        let count = bar.size() / PAGE_SIZE;
+        process
+            .pages
+            .write()
+            .map_device(address, bar.address(), count)
+            .ok_or(FileError::Invalid)?;
+        claim.mappings.lock().push(Mapping {
+            process: Arc::downgrade(&process),
+            address,
+            physical_address: bar.address(),
+            count,
+        });
+        Ok(content.len())
+    }
+}
+impl KernelFile for PciFile {
+    fn state(&self) -> State {
+        match self.node {
+            Node::Root => State::new().walk(true),
+            Node::Device(..) => State::new().walk(true).lock(true),
+            Node::Config(..) => State::new()
+                .read(true)
+                .overwrite(self.claimed())
+                .seek(true)
+                .tell(true),
+            Node::Bar(..) => State::new()
+                .read(self.claimed())
+                .overwrite(self.claimed())
+                .seek(self.claimed())
+                .tell(self.claimed()),
+            Node::Map(..) => State::new().overwrite(self.claimed()),
+            Node::Irq(..) => State::new().read(true).seek(true).tell(true),
+        }
+    }
+    fn walk(&mut self, name: &[u8]) -> Result<Box<dyn KernelFile>, FileError> {
+        if name.is_empty() {
+            return Ok(Box::new(PciFile::new(self.node, self.claim.clone())));
+        }
+        match self.node {
+            Node::Root => PCI_DEVICES
+                .read()
+                .iter()
+                .position(|device| *device_name(device) == *name)
+                .map(|device| {
+                    Box::new(PciFile::new(Node::Device(device), None)) as Box<dyn KernelFile>
+                })
+                .ok_or(FileError::NotFound),
+            Node::Device(device) => {
+                let node = match name {
+                    CONFIG_NAME => Node::Config(device),
+                    IRQ_NAME => Node::Irq(device),
+                    _ => (0..6)
+                        .find_map(|index| {
+                            let bar = device_bar(device, index)?;
+                            if *bar_name(index) == *name {
+                                Some(Node::Bar(device, index))
+                            } else if *map_name(index) == *name && mappable(&bar) {
+                                Some(Node::Map(device, index))
+                            } else {
+                                None
+                            }
+                        })
+                        .ok_or(FileError::NotFound)?,
+                };
+                Ok(Box::new(PciFile::new(node, self.claim.clone())))
+            }
+            _ => Err(FileError::Unsupported),
+        }
+    }
+    fn list(&mut self, count: usize, advance: bool) -> Result<Vec<Box<[u8]>>, FileError> {
+        match self.node {
+            Node::Root | Node::Device(..) => {
+                let entries = self.entries();
+                self.listing.list(entries, count, advance)
+            }
+            _ => Err(FileError::Unsupported),
+        }
+    }
+    fn list_seek(&mut self, seek: Seek) -> Result<u64, FileError> {
+        let length = self.entries().len();
+        self.listing.seek(seek, length)
+    }
+    fn list_tell(&mut self) -> Result<u64, FileError> {
+        Ok(self.listing.position)
+    }
+    fn read(&mut self, length: usize, advance: bool) -> Result<Vec<u8>, FileError> {
+        if !self.state().read {
+            return Err(FileError::Denied);
+        }
+        let length = self.bounded_length(length);
+        let content = match self.node {
+            Node::Config(device) => {
+                let address = PCI_DEVICES.read()[device].address;
+                let width = access_width(self.position, length, 4);
+                (0..length)
+                    .step_by(width)
+                    .flat_map(|offset| {
+                        let offset = (self.position as usize + offset) as u16;
+                        match width {
+                            4 => Vec::from(pci::read_u32(address, offset).to_le_bytes()),
+                            2 => Vec::from(pci::read_u16(address, offset).to_le_bytes()),
+                            _ => Vec::from([pci::read_u8(address, offset)]),
+                        }
+                    })
+                    .collect()
+            }
+            Node::Bar(device, index) => bar_read(
+                device_bar(device, index).ok_or(FileError::NotFound)?,
+                self.position,
+                length,
+            ),
+            Node::Irq(device) => {
+                let description = irq_description(&PCI_DEVICES.read()[device]);
+                description.as_bytes()[self.position as usize..][..length].to_vec()
+            }
+            _ => return Err(FileError::Unsupported),
+        };
+        if advance {
+            self.position += content.len() as u64;
+        }
+        Ok(content)
+    }
+    fn overwrite(&mut self, content: &[u8]) -> Result<usize, FileError> {
+        if !self.state().overwrite {
+            return Err(FileError::Denied);
+        }
+        if let Node::Map(device, index) = self.node {
+            return self.map(device, index, content);
+        }
+        let content = &content[..self.bounded_length(content.len())];
+        match self.node {
+            Node::Config(device) => {
+                let address = PCI_DEVICES.read()[device].address;
+                let width = access_width(self.position, content.len(), 4);
+                for (offset, chunk) in content.chunks(width).enumerate() {
+                    let offset = (self.position as usize + offset * width) as u16;
+                    match width {
+                        4 => pci::write_u32(
+                            address,
+                            offset,
+                            u32::from_le_bytes(chunk.try_into().unwrap()),
+                        ),
+                        2 => pci::write_u16(
+                            address,
+                            offset,
+                            u16::from_le_bytes(chunk.try_into().unwrap()),
+                        ),
+                        _ => pci::write_u8(address, offset, chunk[0]),
+                    }
+                }
+            }
+            Node::Bar(device, index) => bar_write(
+                device_bar(device, index).ok_or(FileError::NotFound)?,
+                self.position,
+                content,
+            ),
+            _ => return Err(FileError::Unsupported),
+        }
+        self.position += content.len() as u64;
+        Ok(content.len())
+    }
+    fn seek(&mut self, seek: Seek) -> Result<u64, FileError> {
+        if !seek.permitted(self.state()) {
+            return Err(FileError::Denied);
+        }
+        self.position = seek.apply(self.position, self.length())?;
+        Ok(self.position)
+    }
+    fn tell(&mut self) -> Result<u64, FileError> {
+        if !self.state().tell {
+            return Err(FileError::Denied);
+        }
+        Ok(self.position)
+    }
+    fn lock(&mut self) -> Result<(), FileError> {
+        let Node::Device(device) = self.node else {
+            return Err(FileError::Unsupported);
+        };
+        if self.claimed() {
+            return Ok(());
+        }
+        let mut claims = CLAIMS.write();
+        if claims[device].upgrade().is_some() {
+            return Err(FileError::Busy);
+        }
+        let claim = Arc::new(Claim {
+            mappings: Spinlock::new(Vec::new()),
+        });
+        claims[device] = Arc::downgrade(&claim);
+        self.claim = Some(claim);
+        Ok(())
+    }
+}
+pub fn initialise(_boot_info: &mut bootloader_api::BootInfo) {
+    let device_count = PCI_DEVICES.read().len();
+    CLAIMS.write().resize_with(device_count, Weak::new);
+    file::register(b"pci", &PCI_FILESYSTEM);
+    println!("serving {} pci function/s under /pci...", device_count);
+}
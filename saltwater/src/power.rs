@@ -0,0 +1,173 @@
+use crate::{
+    acpi::{ACPI_PLATFORM, AML_INTERPRETER},
+    file::{self, FileError, KernelFile, KernelFilesystem},
+    hcf::hcf,
+    page::map_device_region,
+    pci, port, println,
+    proc::State,
+    time,
+};
+use acpi::{
+    PciAddress,
+    address::{AddressSpace, GenericAddress},
+    aml::{
+        namespace::AmlName,
+        object::{Object, WrappedObject},
+    },
+    sdt::fadt::Fadt,
+};
+use alloc::{boxed::Box, vec};
+use core::{arch::asm, str::FromStr};
+use x86_64::{VirtAddr, instructions::interrupts, structures::DescriptorTablePointer};
+const SOFT_OFF_STATE: u64 = 5;
+const SLEEP_TYPE_SHIFT: u64 = 10;
+const SLEEP_TYPE_MASK: u64 = 0b111 << SLEEP_TYPE_SHIFT;
+const SLEEP_ENABLE: u64 = 1 << 13;
+const KBC_STATUS_PORT: u16 = 0x64;
+const KBC_COMMAND_PORT: u16 = 0x64;
+const KBC_INPUT_FULL: u8 = 1 << 1;
+const KBC_PULSE_RESET: u8 = 0xfe;
+// how long each mechanism is given to take effect before falling back to the next
+const FALLBACK_MICROSECONDS: u64 = 100_000;
+const SHUTDOWN_COMMAND: &[u8] = b"shutdown";
+const REBOOT_COMMAND: &[u8] = b"reboot";
+fn sleep_types() -> Option<(u64, u64)> {
+    let interpreter_guard = AML_INTERPRETER.read();
+    let interpreter = interpreter_guard.as_ref()?;
+    let _ = interpreter.evaluate_if_present(
+        AmlName::from_str("\\_PTS").unwrap(),
+        vec![WrappedObject::new(Object::Integer(SOFT_OFF_STATE))],
+    );
+    let package = interpreter
+        .evaluate(AmlName::from_str("\\_S5").unwrap(), vec![])
+        .ok()?;
+    let Object::Package(elements) = &*package else {
+        return None;
+    };
+    let sleep_type = |index: usize| match elements.get(index).map(|element| &**element) {
+        Some(Object::Integer(sleep_type)) => Some(*sleep_type),
+        _ => None,
+    };
+    // the second value is optional on platforms without a pm1b control block
+    let sleep_type_a = sleep_type(0)?;
+    Some((sleep_type_a, sleep_type(1).unwrap_or(sleep_type_a)))
+}
+pub fn shutdown() -> ! {
+    println!("shutting down...");
+    interrupts::disable();
+    match sleep_types() {
+        Some((sleep_type_a, sleep_type_b)) => {
+            let acpi_platform_guard = ACPI_PLATFORM.read();
+            let control = &acpi_platform_guard
+                .as_ref()
+                .expect("ACPI platform not initialised before shutdown!")
+                .registers
+                .pm1_control_registers;
+            let enter = |register: &acpi::address::MappedGas<_>, sleep_type: u64| {
+                if let Ok(value) = register.read() {
+                    let _ = register.write(
+                        (value & !SLEEP_TYPE_MASK)
+                            | (sleep_type << SLEEP_TYPE_SHIFT)
+                            | SLEEP_ENABLE,
+                    );
+                }
+            };
+            enter(&control.pm1a, sleep_type_a);
+            if let Some(pm1b) = control.pm1b.as_ref() {
+                enter(pm1b, sleep_type_b);
+            }
+            time::stall(FALLBACK_MICROSECONDS);
+            println!("platform did not enter S5 soft-off!");
+        }
+        None => println!("firmware does not describe an S5 soft-off state!"),
+    }
+    hcf();
+}
+fn reset_register() -> Option<(GenericAddress, u8)> {
+    let acpi_platform_guard = ACPI_PLATFORM.read();
+    let fadt = acpi_platform_guard.as_ref()?.tables.find_table::<Fadt>()?;
+    if !{ fadt.flags }.supports_system_reset_via_fadt() {
+        return None;
+    }
+    Some((fadt.reset_register().ok()?, fadt.reset_value))
+}
+fn fadt_reset() {
+    let Some((register, value)) = reset_register() else {
+        println!("FADT does not describe a reset register...");
+        return;
+    };
+    println!("resetting via FADT reset register...");
+    match register.address_space {
+        AddressSpace::SystemIo => unsafe { port::write_u8(register.address as u16, value) },
+        AddressSpace::SystemMemory => unsafe {
+            (map_device_region(register.address, 1) as *mut u8).write_volatile(value)
+        },
+        // the address encodes a function on segment 0, bus 0 as device:function:offset
+        AddressSpace::PciConfigSpace => pci::write_u8(
+            PciAddress::new(
+                0,
+                0,
+                (register.address >> 32) as u8,
+                (register.address >> 16) as u8,
+            ),
+            register.address as u16,
+            value,
+        ),
+        _ => println!("FADT reset register is in an unsupported address space!"),
+    }
+}
+fn keyboard_controller_reset() {
+    println!("resetting via keyboard controller...");
+    unsafe {
+        let deadline = time::nanos_since_boot() + FALLBACK_MICROSECONDS * 1_000;
+        while (port::read_u8(KBC_STATUS_PORT) & KBC_INPUT_FULL != 0)
+            & (time::nanos_since_boot() < deadline)
+        {
+            core::hint::spin_loop();
+        }
+        port::write_u8(KBC_COMMAND_PORT, KBC_PULSE_RESET);
+    }
+}
+fn triple_fault() -> ! {
+    println!("resetting via triple fault...");
+    unsafe {
+        x86_64::instructions::tables::lidt(&DescriptorTablePointer {
+            limit: 0,
+            base: VirtAddr::zero(),
+        });
+        asm!("int3", options(noreturn));
+    }
+}
+pub fn reboot() -> ! {
+    println!("rebooting...");
+    interrupts::disable();
+    fadt_reset();
+    time::stall(FALLBACK_MICROSECONDS);
+    keyboard_controller_reset();
+    time::stall(FALLBACK_MICROSECONDS);
+    triple_fault();
+}
+// a single write-only file, trailing whitespace is ignored so that `echo reboot > /power` works
+struct PowerFile;
+struct PowerFilesystem;
+static POWER_FILESYSTEM: PowerFilesystem = PowerFilesystem;
+impl KernelFilesystem for PowerFilesystem {
+    fn attach(&self) -> Box<dyn KernelFile> {
+        Box::new(PowerFile)
+    }
+}
+impl KernelFile for PowerFile {
+    fn state(&self) -> State {
+        State::new().overwrite(true)
+    }
+    fn overwrite(&mut self, content: &[u8]) -> Result<usize, FileError> {
+        match content.trim_ascii_end() {
+            SHUTDOWN_COMMAND => shutdown(),
+            REBOOT_COMMAND => reboot(),
+            _ => Err(FileError::Invalid),
+        }
+    }
+}
+pub fn initialise(_boot_info: &mut bootloader_api::BootInfo) {
+    file::register(b"power", &POWER_FILESYSTEM);
+}
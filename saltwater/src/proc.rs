@@ -1,5 +1,5 @@
 use crate::{
-    frame::PAGE_FRAME_ALLOCATOR, mapping::physical_to_virtual_address, page::ManagedPageTable, sstacks::SyscallStack
+    file::{KernelFile, KernelFilesystem, Listing}, fpu::ExtendedState, loader::{self, LoadError, TlsTemplate}, locks, mapping::{PAGE_SIZE, USER_THREAD_AREA_SIZE, physical_to_virtual_address, user_thread_area_address}, page::{self, ManagedPageTable, USER_PAGE_FLAGS}, scheduler::{self, ANY_PROCESSOR, initial_kernel_context}, sstacks::SyscallStack, tls, wire::{self, Negotiated, Selector}
 };
 use alloc::{
     boxed::Box,
@@ -7,64 +7,300 @@ use alloc::{
     sync::{Arc, Weak},
     vec::Vec,
 };
-use core::sync::atomic::AtomicU64;
+use core::{mem, sync::atomic::{AtomicU64, Ordering}};
 use spinning_top::RwSpinlock;
 use x86_64::
     structures::
-        paging::{FrameAllocator, PageTable, PhysFrame}
+        paging::{FrameAllocator, PageTable, PageTableFlags, PhysFrame}
     
 ;
-enum MessageStatus {
+pub enum MessageStatus {
     Sent(Vec<PhysFrame>),
     Received,
     Responded(Vec<PhysFrame>),
+    // the client gave up on the message, which its server may still be holding
+    Cancelled,
 }
-struct Message {
+pub struct Message {
     tag: u64,
-    status: RwSpinlock<MessageStatus>,
-    waiting: RwSpinlock<Vec<Arc<RwSpinlock<Thread>>>>,
-}
-struct State {
-    walk: bool,
-    rename: bool,
-    make: bool,
-    remove: bool,
-    read: bool,
-    insert: bool,
-    overwrite: bool,
-    truncate: bool,
-    seek_forward: bool,
-    seek_backward: bool,
-    seek_start: bool,
-    seek_end: bool,
-    tell: bool,
-    lock: bool,
-}
-struct Binding {
-    from_server: Weak<Server>,
-    from_path: Box<[u8]>,
-    to_path: Box<[u8]>,
-    state_mask: State,
-}
-struct Server {
-    bindings: RwSpinlock<Vec<Binding>>,
-    kind: ServerKind,
-}
-enum ServerKind {
+    client: Weak<Process>,
+    pub status: RwSpinlock<MessageStatus>,
+    pub waiting: RwSpinlock<Vec<Arc<RwSpinlock<Thread>>>>,
+    // descriptors handed over along with the message, which its receiver takes once it blocks
+    // on it
+    pub passed: RwSpinlock<Vec<Descriptor>>,
+}
+impl Message {
+    // messages the kernel sends on its own behalf, such as a drop for a handle nobody holds any
+    // more, have no client to respond to
+    pub fn new(tag: u64, client: Weak<Process>, frames: Vec<PhysFrame>) -> Message {
+        Message {
+            tag,
+            client,
+            status: RwSpinlock::new(MessageStatus::Sent(frames)),
+            waiting: RwSpinlock::new(Vec::new()),
+            passed: RwSpinlock::new(Vec::new()),
+        }
+    }
+    pub fn responded(&self) -> bool {
+        matches!(*self.status.read(), MessageStatus::Responded(_))
+    }
+    pub fn cancelled(&self) -> bool {
+        matches!(*self.status.read(), MessageStatus::Cancelled)
+    }
+    // the frames go straight away, and anyone still waiting on the message, such as a server
+    // watching for its client giving up, is woken
+    pub fn cancel(&self) {
+        let status = mem::replace(&mut *self.status.write(), MessageStatus::Cancelled);
+        if let MessageStatus::Sent(frames) | MessageStatus::Responded(frames) = status {
+            page::release_frames(frames);
+        }
+        drop(mem::take(&mut *self.passed.write()));
+        wake_all(&self.waiting);
+    }
+    // the client only keeps its tag weakly, so a responded message is handed to the client to
+    // keep until it blocks on it. a response to a cancelled message is thrown away
+    pub fn respond(self_arc: Arc<Self>, frames: Vec<PhysFrame>) {
+        {
+            let mut status_write = self_arc.status.write();
+            if let MessageStatus::Cancelled = *status_write {
+                drop(status_write);
+                page::release_frames(frames);
+                return;
+            }
+            *status_write = MessageStatus::Responded(frames);
+        }
+        if let Some(client) = self_arc.client.upgrade() {
+            client.responses.write().push_back(self_arc.clone());
+        }
+        wake_all(&self_arc.waiting);
+    }
+}
+// a message in flight owns the frames it carries until they are mapped into a receiver, and
+// whoever is still waiting on it finds it gone once woken
+impl Drop for Message {
+    fn drop(&mut self) {
+        if let MessageStatus::Sent(frames) | MessageStatus::Responded(frames) = &mut *self.status.write() {
+            page::release_frames(frames.drain(..));
+        }
+        wake_all(&self.waiting);
+    }
+}
+pub fn wake_all(waiting: &RwSpinlock<Vec<Arc<RwSpinlock<Thread>>>>) {
+    for thread in mem::take(&mut *waiting.write()) {
+        scheduler::wake(thread);
+    }
+}
+// threads waiting on several things at once take themselves back off everything they did not
+// wake for
+pub fn stop_waiting(waiting: &RwSpinlock<Vec<Arc<RwSpinlock<Thread>>>>, thread: &Arc<RwSpinlock<Thread>>) {
+    waiting.write().retain(|other| !Arc::ptr_eq(other, thread));
+}
+// shared with tethys_lib through tethys_wire, along with its encoding as a word
+pub use tethys_wire::State;
+// where a binding's contents go in the union at its bindpoint. a replacing binding hides
+// everything bound before it along with the bindpoint's own contents
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Order {
+    Replace,
+    Before,
+    After,
+}
+static NEXT_BINDING_ID: AtomicU64 = AtomicU64::new(0);
+// a copied namespace keeps the ids of the bindings it was copied with, so descriptors walked
+// through them stay usable in the copy
+#[derive(Clone)]
+pub struct Binding {
+    pub id: u64,
+    pub from_server: Weak<Server>,
+    pub from_path: Box<[u8]>,
+    pub to_server: Weak<Server>,
+    pub to_path: Box<[u8]>,
+    pub state_mask: State,
+    pub order: Order,
+    // derived from the descriptor the binding was made from, so that revoking it takes the
+    // binding back too
+    pub grant: Arc<Grant>,
+}
+// the bindings a process sees, which may be shared with other processes
+pub struct Namespace {
+    pub bindings: RwSpinlock<Vec<Binding>>,
+}
+impl Namespace {
+    pub fn new() -> Namespace {
+        Namespace {
+            bindings: RwSpinlock::new(Vec::new()),
+        }
+    }
+    pub fn next_binding_id() -> u64 {
+        NEXT_BINDING_ID.fetch_add(1, Ordering::Relaxed)
+    }
+    pub fn contains(&self, id: u64) -> bool {
+        self.bindings.read().iter().any(|binding| binding.id == id)
+    }
+    pub fn revoked(&self, id: u64) -> bool {
+        self.bindings
+            .read()
+            .iter()
+            .any(|binding| binding.id == id && binding.grant.revoked())
+    }
+    // bindings made in a copy afterwards are not seen by the original, or the other way round
+    pub fn copy(&self) -> Namespace {
+        Namespace {
+            bindings: RwSpinlock::new(self.bindings.read().clone()),
+        }
+    }
+}
+// how a new process's namespace is made from its parent's
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Inheritance {
+    Shared,
+    Copied,
+    Fresh,
+}
+pub struct Server {
+    pub kind: ServerKind,
+}
+pub enum ServerKind {
     User(Arc<RwSpinlock<UserServer>>),
     Kernel(KernelServer),
 }
-struct UserServer {
+pub struct UserServer {
     priority_sum: RwSpinlock<u64>,
-    requests: RwSpinlock<VecDeque<Arc<Message>>>,
-    working: RwSpinlock<Vec<Arc<Message>>>,
-    waiting: RwSpinlock<Vec<Arc<RwSpinlock<Thread>>>>,
+    pub requests: RwSpinlock<VecDeque<Arc<Message>>>,
+    pub working: RwSpinlock<Vec<Arc<Message>>>,
+    pub waiting: RwSpinlock<Vec<Arc<RwSpinlock<Thread>>>>,
+    // what the server speaks, once the kernel has asked
+    pub protocol: RwSpinlock<Option<Negotiated>>,
+}
+impl UserServer {
+    pub fn new() -> UserServer {
+        UserServer {
+            priority_sum: RwSpinlock::new(0),
+            requests: RwSpinlock::new(VecDeque::new()),
+            working: RwSpinlock::new(Vec::new()),
+            waiting: RwSpinlock::new(Vec::new()),
+            protocol: RwSpinlock::new(None),
+        }
+    }
+    pub fn pending(&self) -> bool {
+        self.requests.read().iter().any(|message| !message.cancelled())
+    }
+    pub fn enqueue(&self, message: Arc<Message>) {
+        self.requests.write().push_back(message);
+        wake_all(&self.waiting);
+    }
+    // moves the oldest request into the working set, where it stays until it is responded to.
+    // requests cancelled before being received are skipped over
+    pub fn dequeue(&self) -> Option<Arc<Message>> {
+        let message = {
+            let mut requests_write = self.requests.write();
+            loop {
+                let message = requests_write.pop_front()?;
+                if !message.cancelled() {
+                    break message;
+                }
+            }
+        };
+        self.working.write().push(message.clone());
+        Some(message)
+    }
+    pub fn finish(&self, message: &Arc<Message>) -> bool {
+        let mut working_write = self.working.write();
+        let Some(index) = working_write.iter().position(|other| Arc::ptr_eq(other, message)) else {
+            return false;
+        };
+        working_write.swap_remove(index);
+        true
+    }
+}
+pub struct KernelServer {
+    pub filesystem: &'static dyn KernelFilesystem,
+}
+// a user server's root is always open under handle 0, every other handle is one the server
+// gave out in response to a walk or make
+pub const ROOT_HANDLE: u64 = 0;
+// the file a descriptor's messages end up at, held open on the server that owns it
+pub enum Handle {
+    Kernel(Box<dyn KernelFile>),
+    User { server: Weak<Server>, handle: u64 },
+}
+// handles are given back to their user server when the last descriptor using them goes, without
+// waiting for the server to acknowledge it
+impl Drop for Handle {
+    fn drop(&mut self) {
+        let Handle::User { server, handle } = self else {
+            return;
+        };
+        if *handle == ROOT_HANDLE {
+            return;
+        }
+        let Some(server) = server.upgrade() else {
+            return;
+        };
+        let ServerKind::User(user_server) = &server.kind else {
+            return;
+        };
+        if let Some(frames) = page::frames_from_bytes(&wire::request(*handle, Selector::Drop, &[])) {
+            user_server.read().enqueue(Arc::new(Message::new(0, Weak::new(), frames)));
+        }
+    }
 }
-struct KernelServer {}
 pub struct Descriptor {
-    server: Weak<Server>,
-    path: Box<[u8]>,
-    state_mask: State,
+    // where the descriptor sits in the namespace, which is what bindings are looked up against
+    pub server: Weak<Server>,
+    pub path: Box<[u8]>,
+    pub state_mask: State,
+    // the first member of the union at that location, which every message not answered by the
+    // kernel itself is forwarded to
+    pub handle: RwSpinlock<Handle>,
+    pub handle_mask: State,
+    // the bindings the descriptor was walked through or gets its file from, and goes stale
+    // without
+    pub through: Box<[u64]>,
+    // the list head of a union directory, whose listing is merged by the kernel
+    pub listing: RwSpinlock<Listing>,
+    pub grant: Arc<Grant>,
+}
+// every descriptor is granted by the one it was walked, made or passed from. revoking a grant
+// takes back everything derived from it until then, which each derived grant notices by its
+// parent's generation having moved on, while the revoked descriptor itself stays usable
+pub struct Grant {
+    generation: AtomicU64,
+    parent: Option<(Arc<Grant>, u64)>,
+}
+impl Grant {
+    pub fn root() -> Arc<Grant> {
+        Arc::new(Grant {
+            generation: AtomicU64::new(0),
+            parent: None,
+        })
+    }
+    pub fn derive(self_arc: &Arc<Self>) -> Arc<Grant> {
+        Arc::new(Grant {
+            generation: AtomicU64::new(0),
+            parent: Some((self_arc.clone(), self_arc.generation.load(Ordering::Acquire))),
+        })
+    }
+    pub fn revoked(&self) -> bool {
+        let mut grant = self;
+        while let Some((parent, generation)) = &grant.parent {
+            if parent.generation.load(Ordering::Acquire) != *generation {
+                return true;
+            }
+            grant = parent;
+        }
+        false
+    }
+    pub fn revoke(&self) {
+        self.generation.fetch_add(1, Ordering::AcqRel);
+    }
+}
+// a descriptor's locks go with it, whether it is dropped or its process dies
+impl Drop for Descriptor {
+    fn drop(&mut self) {
+        locks::release(self);
+    }
 }
 pub struct PanicVectors {
     emergency: u64,
@@ -89,9 +325,38 @@ pub struct ExecutionContext {
     pub instruction_pointer: u64,
     pub rflags: u64,
     pub debug: [u64; 8],
-    pub segment_base: u64,
+    pub fs_base: u64,
+    pub gs_base: u64,
+}
+// registers are indexed in their x86 encoding order
+pub const REGISTER_RSP: usize = 4;
+pub const REGISTER_RDI: usize = 7;
+// interrupts are enabled in user mode so that the timer can preempt threads
+pub const USER_RFLAGS: u64 = 0x202;
+const INITIAL_STACK_SIZE: u64 = 0x10000;
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ThreadState {
+    Ready,
+    Running,
+    // not runnable until woken, which is also how a new thread waits to be started
+    Blocked,
+    // held back by its process being suspended, and released only by resuming it
+    Suspended,
+    Exited(u64),
 }
 pub struct Thread {
+    pub id: u64,
+    pub process: Weak<Process>,
+    pub state: ThreadState,
+    // still executing on its kernel stack, even if its state says otherwise
+    pub on_processor: bool,
+    // set the first time the thread enters user mode, before which it holds nothing on its
+    // kernel stack
+    pub started: bool,
+    pub detached: bool,
+    pub joiner: Option<Arc<RwSpinlock<Thread>>>,
+    // the kernel stack pointer saved when the thread was last switched away from
+    pub kernel_context: AtomicU64,
     pub user_context: ExecutionContext,
     pub handler_context: ExecutionContext,
     pub aborted: bool,
@@ -100,61 +365,354 @@ pub struct Thread {
     pub kernel_stack: SyscallStack,
     pub panic_vectors: PanicVectors,
     pub virtual_time: isize,
+    pub affinity: u64,
+    pub extended_state: ExtendedState,
+}
+// the status a killed process reports when its killer gives none, and the one orphans with no
+// living ancestor to adopt them are killed with
+pub const KILLED_STATUS: u64 = u64::MAX;
+// the priority shared out from the root of the process tree, small enough that the propagated
+// priorities of every thread on a processor never saturate its load
+pub const ROOT_PRIORITY: u64 = 1 << 32;
+// the status a process that aborted itself reports
+pub const ABORTED_STATUS: u64 = u64::MAX - 1;
+static NEXT_PROCESS_ID: AtomicU64 = AtomicU64::new(0);
+// processes with no living ancestor are kept here until they have finished dying
+static ORPHANS: RwSpinlock<Vec<Arc<Process>>> = RwSpinlock::new(Vec::new());
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ProcessStatus {
+    Running,
+    // threads are still winding down
+    Killed(u64),
+    // every resource has been released and the status is waiting to be collected
+    Exited(u64),
+}
+pub struct Lifecycle {
+    pub status: ProcessStatus,
+    pub waiters: Vec<Arc<RwSpinlock<Thread>>>,
 }
 pub struct Process {
+    pub id: u64,
+    pub lifecycle: RwSpinlock<Lifecycle>,
     pub set_priority: AtomicU64,
     pub propagated_priority: AtomicU64,
     pub inherited_priority: AtomicU64,
-    pub parent: Option<Weak<Process>>,
+    pub parent: RwSpinlock<Option<Weak<Process>>>,
     pub pages: RwSpinlock<ManagedPageTable>,
     pub threads: RwSpinlock<Vec<Arc<RwSpinlock<Thread>>>>,
     pub children: RwSpinlock<Vec<Arc<Process>>>,
+    // the tags of messages this process has sent, shared with the tags of those it has
+    // received as a server
+    pub next_tag: AtomicU64,
     pub requests: RwSpinlock<Vec<(u64, Weak<Message>)>>,
+    pub received: RwSpinlock<Vec<(u64, Weak<Message>)>>,
     pub responses: RwSpinlock<VecDeque<Arc<Message>>>,
     pub servers: RwSpinlock<Vec<Arc<Server>>>,
-    pub descriptors: RwSpinlock<Vec<Descriptor>>,
+    pub namespace: RwSpinlock<Arc<Namespace>>,
+    // dropped descriptors leave a gap that the next new descriptor fills
+    pub descriptors: RwSpinlock<Vec<Option<Arc<Descriptor>>>>,
+    pub tls_template: RwSpinlock<Option<TlsTemplate>>,
+    pub next_thread_area: AtomicU64,
+    // the threads held back while the process is suspended, none when it is not
+    pub held: RwSpinlock<Option<Vec<Arc<RwSpinlock<Thread>>>>>,
 }
 impl Process {
-    pub fn add_child(self_arc: Arc<Self>) -> Arc<Self> {
-        let mut children_write = self_arc.children.write();
-        let new_process = Arc::new(Self {
+    pub fn new(parent: Option<Weak<Process>>) -> Process {
+        Self {
+            id: NEXT_PROCESS_ID.fetch_add(1, Ordering::Relaxed),
+            lifecycle: RwSpinlock::new(Lifecycle {
+                status: ProcessStatus::Running,
+                waiters: Vec::new(),
+            }),
             set_priority: AtomicU64::new(0),
             propagated_priority: AtomicU64::new(0),
             inherited_priority: AtomicU64::new(0),
-            parent: Some(Arc::downgrade(&self_arc)),
+            parent: RwSpinlock::new(parent),
             pages: RwSpinlock::new(ManagedPageTable::new()),
             threads: RwSpinlock::new(Vec::new()),
             children: RwSpinlock::new(Vec::new()),
+            next_tag: AtomicU64::new(0),
             requests: RwSpinlock::new(Vec::new()),
+            received: RwSpinlock::new(Vec::new()),
             responses: RwSpinlock::new(VecDeque::new()),
             servers: RwSpinlock::new(Vec::new()),
+            namespace: RwSpinlock::new(Arc::new(Namespace::new())),
             descriptors: RwSpinlock::new(Vec::new()),
-        });
+            tls_template: RwSpinlock::new(None),
+            next_thread_area: AtomicU64::new(0),
+            held: RwSpinlock::new(None),
+        }
+    }
+    pub fn add_child(self_arc: Arc<Self>) -> Arc<Self> {
+        let mut children_write = self_arc.children.write();
+        let new_process = Arc::new(Self::new(Some(Arc::downgrade(&self_arc))));
+        *new_process.namespace.write() = self_arc.namespace.read().clone();
         children_write.push(new_process.clone());
+        drop(children_write);
+        self_arc.propagate_priorities();
         new_process
     }
-    pub fn add_thread(self: &Self) -> Arc<RwSpinlock<Thread>> {
-        let mut threads_write = self.threads.write();
+    // builds a process from an elf with its first thread ready to start at the entry point, the
+    // caller decides where it sits in the process tree and when it starts
+    pub fn from_elf(parent: Option<&Arc<Process>>, bytes: &[u8]) -> Result<Arc<Process>, LoadError> {
+        let process = Arc::new(Self::new(parent.map(Arc::downgrade)));
+        let image = loader::load(&mut process.pages.write(), bytes)?;
+        *process.tls_template.write() = image.tls_template;
+        let thread = Process::add_thread(&process).ok_or(LoadError::OutOfMemory)?;
+        let mut thread_write = thread.write();
+        // the first thread's stack sits at the top of its own thread area
+        let stack_top = user_thread_area_address(thread_write.id) + USER_THREAD_AREA_SIZE;
+        process
+            .pages
+            .write()
+            .map(
+                stack_top - INITIAL_STACK_SIZE,
+                INITIAL_STACK_SIZE / PAGE_SIZE,
+                *USER_PAGE_FLAGS | PageTableFlags::NO_EXECUTE,
+            )
+            .ok_or(LoadError::OutOfMemory)?;
+        thread_write.user_context.instruction_pointer = image.entry;
+        // entered as if called, one return address below a 16-byte boundary
+        thread_write.user_context.registers[REGISTER_RSP] = stack_top - size_of::<u64>() as u64;
+        drop(thread_write);
+        Ok(process)
+    }
+    pub fn spawn(
+        self_arc: &Arc<Self>,
+        bytes: &[u8],
+        namespace: Arc<Namespace>,
+    ) -> Result<Arc<Process>, LoadError> {
+        let child = Process::from_elf(Some(self_arc), bytes)?;
+        *child.namespace.write() = namespace;
+        self_arc.children.write().push(child.clone());
+        self_arc.propagate_priorities();
+        Ok(child)
+    }
+    pub fn inherit_namespace(&self, inheritance: Inheritance) -> Arc<Namespace> {
+        match inheritance {
+            Inheritance::Shared => self.namespace.read().clone(),
+            Inheritance::Copied => Arc::new(self.namespace.read().copy()),
+            Inheritance::Fresh => Arc::new(Namespace::new()),
+        }
+    }
+    pub fn start(&self) {
+        for thread in self.threads.read().clone() {
+            scheduler::wake(thread);
+        }
+    }
+    pub fn dying(&self) -> bool {
+        self.lifecycle.read().status != ProcessStatus::Running
+    }
+    pub fn child(&self, id: u64) -> Option<Arc<Process>> {
+        self.children
+            .read()
+            .iter()
+            .find(|child| child.id == id)
+            .cloned()
+    }
+    // collects the status of a child that has exited, releasing the last reference to it
+    pub fn reap_child(&self, child: &Arc<Process>) -> Option<u64> {
+        let ProcessStatus::Exited(status) = child.lifecycle.read().status else {
+            return None;
+        };
+        self.children
+            .write()
+            .retain(|other| !Arc::ptr_eq(other, child));
+        self.propagate_priorities();
+        Some(status)
+    }
+    // threads can only be stopped on their own processors, so blocked ones are woken to unwind
+    // out of whatever they were waiting on and exit, and running ones exit at their next syscall.
+    // threads that have never run have nothing to unwind and are retired on the spot
+    pub fn kill(self_arc: &Arc<Self>, status: u64) {
+        {
+            let mut lifecycle_write = self_arc.lifecycle.write();
+            if lifecycle_write.status != ProcessStatus::Running {
+                return;
+            }
+            lifecycle_write.status = ProcessStatus::Killed(status);
+        }
+        let threads = self_arc.threads.read().clone();
+        if threads.is_empty() {
+            Process::finish(self_arc, status);
+        }
+        // a suspended process is released so that its held threads can exit
+        let held = self_arc.held.write().take().unwrap_or_default();
+        for thread in threads {
+            let mut thread_write = thread.write();
+            if !matches!(thread_write.state, ThreadState::Blocked | ThreadState::Suspended) {
+                continue;
+            }
+            if thread_write.started {
+                thread_write.state = ThreadState::Blocked;
+                drop(thread_write);
+                scheduler::wake(thread);
+            } else {
+                thread_write.state = ThreadState::Exited(status);
+                drop(thread_write);
+                scheduler::retire(thread);
+            }
+        }
+        drop(held);
+    }
+    // threads are held back the next time they would be switched in, so running ones stop at
+    // their next syscall
+    pub fn suspend(&self) -> bool {
+        let mut held_write = self.held.write();
+        if held_write.is_some() || self.dying() {
+            return false;
+        }
+        let _ = held_write.insert(Vec::new());
+        true
+    }
+    pub fn resume(&self) -> bool {
+        let Some(held) = self.held.write().take() else {
+            return false;
+        };
+        for thread in held {
+            let mut thread_write = thread.write();
+            if thread_write.state == ThreadState::Suspended {
+                thread_write.state = ThreadState::Ready;
+                drop(thread_write);
+                scheduler::place(thread);
+            }
+        }
+        true
+    }
+    // keeps a thread that is about to be switched in from running while the process is
+    // suspended, returning whether it was held. the thread may be resumed onto another processor
+    // as soon as it is held, so its processor lets go of it first
+    pub fn hold(&self, thread: &Arc<RwSpinlock<Thread>>, release: impl FnOnce()) -> bool {
+        let mut held_write = self.held.write();
+        let Some(held) = held_write.as_mut() else {
+            return false;
+        };
+        release();
+        let mut thread_write = thread.write();
+        thread_write.state = ThreadState::Suspended;
+        thread_write.on_processor = false;
+        drop(thread_write);
+        held.push(thread.clone());
+        true
+    }
+    // called for every thread that exits once it is off its processor, finishing the process
+    // when no thread is left that could still run
+    pub fn thread_retired(self_arc: &Arc<Self>, value: u64) {
+        let finished = self_arc.threads.read().iter().all(|thread| {
+            let thread_read = thread.read();
+            matches!(thread_read.state, ThreadState::Exited(_)) && !thread_read.on_processor
+        });
+        if finished {
+            let status = match self_arc.lifecycle.read().status {
+                ProcessStatus::Killed(status) => status,
+                _ => value,
+            };
+            Process::finish(self_arc, status);
+        }
+    }
+    // releases everything the process owns except the top-level page table, which goes with the
+    // last reference once the status has been collected
+    fn finish(self_arc: &Arc<Self>, status: u64) {
+        let waiters = {
+            let mut lifecycle_write = self_arc.lifecycle.write();
+            if let ProcessStatus::Exited(_) = lifecycle_write.status {
+                return;
+            }
+            lifecycle_write.status = ProcessStatus::Exited(status);
+            mem::take(&mut lifecycle_write.waiters)
+        };
+        // threads joining each other would otherwise keep each other alive
+        for thread in mem::take(&mut *self_arc.threads.write()) {
+            thread.write().joiner = None;
+        }
+        // dropping handles queues drops on their servers, which is kept out from under the lock
+        let descriptors = mem::take(&mut *self_arc.descriptors.write());
+        drop(descriptors);
+        // dropping queued messages wakes their clients, which is kept out from under the lock
+        let servers = mem::take(&mut *self_arc.servers.write());
+        drop(servers);
+        self_arc.requests.write().clear();
+        self_arc.received.write().clear();
+        let responses = mem::take(&mut *self_arc.responses.write());
+        drop(responses);
+        self_arc.pages.write().clear();
+        Process::orphan_children(self_arc);
+        if self_arc.parent.read().as_ref().and_then(Weak::upgrade).is_none() {
+            ORPHANS.write().retain(|orphan| !Arc::ptr_eq(orphan, self_arc));
+        }
+        for waiter in waiters {
+            scheduler::wake(waiter);
+        }
+    }
+    // children are adopted by the nearest ancestor still running. with none left they cannot be
+    // waited for by anyone, so they are killed and kept until they finish
+    fn orphan_children(self_arc: &Arc<Self>) {
+        let children = mem::take(&mut *self_arc.children.write());
+        let mut ancestor = self_arc.parent.read().as_ref().and_then(Weak::upgrade);
+        while let Some(candidate) = ancestor.as_ref() {
+            if !candidate.dying() {
+                break;
+            }
+            let next = candidate.parent.read().as_ref().and_then(Weak::upgrade);
+            ancestor = next;
+        }
+        for child in children {
+            match ancestor.as_ref() {
+                Some(ancestor) => {
+                    *child.parent.write() = Some(Arc::downgrade(ancestor));
+                    ancestor.children.write().push(child);
+                }
+                None => {
+                    *child.parent.write() = None;
+                    ORPHANS.write().push(child.clone());
+                    Process::kill(&child, KILLED_STATUS);
+                }
+            }
+        }
+        if let Some(ancestor) = ancestor {
+            ancestor.propagate_priorities();
+        }
+    }
+    // the thread is created blocked and only runs once woken by the scheduler
+    pub fn add_thread(self_arc: &Arc<Self>) -> Option<Arc<RwSpinlock<Thread>>> {
+        let mut threads_write = self_arc.threads.write();
+        // thread areas are never reused, so the area index doubles as a process-unique id
+        let id = self_arc.next_thread_area.fetch_add(1, Ordering::Relaxed);
+        let thread_pointer = tls::create_block(
+            &mut self_arc.pages.write(),
+            self_arc.tls_template.read().as_ref(),
+            user_thread_area_address(id),
+        )?;
+        let kernel_stack = SyscallStack::new()?;
         let new_thread = Arc::new(RwSpinlock::new(Thread {
+            id,
+            process: Arc::downgrade(self_arc),
+            state: ThreadState::Blocked,
+            on_processor: false,
+            started: false,
+            detached: false,
+            joiner: None,
+            kernel_context: AtomicU64::new(initial_kernel_context(kernel_stack.top())),
             user_context: ExecutionContext {
                 registers: [0; 16],
                 instruction_pointer: 0,
-                rflags: 0,
+                rflags: USER_RFLAGS,
                 debug: [0; 8],
-                segment_base: 0,
+                fs_base: thread_pointer,
+                gs_base: 0,
             },
             handler_context: ExecutionContext {
                 registers: [0; 16],
                 instruction_pointer: 0,
                 rflags: 0,
                 debug: [0; 8],
-                segment_base: 0,
+                fs_base: 0,
+                gs_base: 0,
             },
             aborted: true,
             set_priority: 0,
             propagated_priority: 0,
-            kernel_stack: SyscallStack::new()
-                .expect("failed to allocate kernel stack during thread creation!"),
+            kernel_stack,
             panic_vectors: PanicVectors {
                 emergency: 0,
                 divide: 0,
@@ -174,42 +732,160 @@ impl Process {
                 security: 0,
             },
             virtual_time: 0,
+            affinity: ANY_PROCESSOR,
+            extended_state: ExtendedState::new(),
         }));
         threads_write.push(new_thread.clone());
-        new_thread
+        drop(threads_write);
+        self_arc.propagate_priorities();
+        Some(new_thread)
     }
-    /*fn propagate_priorities(self: &Self) {
-        let set_priority_sum = unsafe { self.threads.read() }
+    pub fn thread(&self, id: u64) -> Option<Arc<RwSpinlock<Thread>>> {
+        self.threads
+            .read()
             .iter()
-            .map(|thread| unsafe { thread.read() }.set_priority as u128)
-            .chain(
-                unsafe { self.children.read() }
-                    .iter()
-                    .map(|child| child.set_priority.load(Ordering::Relaxed) as u128),
-            )
-            .sum::<u128>();
-        for (set_priority, propagated_priority) in
-            unsafe { self.threads.read() }
-                .iter()
-                .map(|thread| {
-                    (
-                        unsafe { thread.read() }.set_priority,
-                        &mut unsafe { thread.make_write_guard_unchecked() }.propagated_priority,
-                    )
-                })
-                .chain(
-                    unsafe { self.children.make_write_guard_unchecked() }
-                        .iter()
-                        .map(|child| {
-                            (
-                                child.set_priority.load(Ordering::Relaxed),
-                                &mut child.propagated_priority,
-                            )
-                        }),
-                )
-        {
-            *propagated_priority = ((set_priority as u128 / set_priority_sum)
-                * self.propagated_priority as u128) as u64
+            .find(|thread| thread.read().id == id)
+            .cloned()
+    }
+    pub fn sent(&self, tag: u64) -> Option<Weak<Message>> {
+        self.requests
+            .read()
+            .iter()
+            .find(|(other, _)| *other == tag)
+            .map(|(_, message)| message.clone())
+    }
+    pub fn add_sent(&self, message: &Arc<Message>) {
+        self.requests.write().push((message.tag, Arc::downgrade(message)));
+    }
+    // a client's tag is given up once the response has been taken
+    pub fn remove_sent(&self, tag: u64) {
+        let message = {
+            let mut requests_write = self.requests.write();
+            let Some(index) = requests_write.iter().position(|(other, _)| *other == tag) else {
+                return;
+            };
+            requests_write.swap_remove(index).1
+        };
+        if let Some(message) = message.upgrade() {
+            self.responses.write().retain(|other| !Arc::ptr_eq(other, &message));
         }
-    }*/
+    }
+    pub fn received(&self, tag: u64) -> Option<Arc<Message>> {
+        self.received
+            .read()
+            .iter()
+            .find(|(other, _)| *other == tag)
+            .and_then(|(_, message)| message.upgrade())
+    }
+    pub fn add_received(&self, message: &Arc<Message>) -> u64 {
+        let tag = self.next_tag.fetch_add(1, Ordering::Relaxed);
+        self.received.write().push((tag, Arc::downgrade(message)));
+        tag
+    }
+    pub fn remove_received(&self, tag: u64) {
+        self.received.write().retain(|(other, _)| *other != tag);
+    }
+    // a new server starts out reachable only through a descriptor on its root held by the
+    // process serving it, returning the server's tag and the descriptor's index
+    pub fn serve(&self) -> (u64, u64) {
+        let server = Arc::new(Server {
+            kind: ServerKind::User(Arc::new(RwSpinlock::new(UserServer::new()))),
+        });
+        let descriptor = Descriptor {
+            server: Arc::downgrade(&server),
+            path: Box::from([]),
+            state_mask: State::all(),
+            handle: RwSpinlock::new(Handle::User {
+                server: Arc::downgrade(&server),
+                handle: ROOT_HANDLE,
+            }),
+            handle_mask: State::all(),
+            through: Box::from([]),
+            listing: RwSpinlock::new(Listing::new()),
+            grant: Grant::root(),
+        };
+        let mut servers_write = self.servers.write();
+        servers_write.push(server);
+        let server_tag = servers_write.len() as u64 - 1;
+        drop(servers_write);
+        (server_tag, self.add_descriptor(descriptor))
+    }
+    pub fn descriptor(&self, index: u64) -> Option<Arc<Descriptor>> {
+        self.descriptors.read().get(index as usize)?.clone()
+    }
+    pub fn add_descriptor(&self, descriptor: Descriptor) -> u64 {
+        let mut descriptors_write = self.descriptors.write();
+        let descriptor = Some(Arc::new(descriptor));
+        match descriptors_write.iter().position(Option::is_none) {
+            Some(index) => {
+                descriptors_write[index] = descriptor;
+                index as u64
+            }
+            None => {
+                descriptors_write.push(descriptor);
+                descriptors_write.len() as u64 - 1
+            }
+        }
+    }
+    pub fn remove_descriptor(&self, index: u64) -> Option<Arc<Descriptor>> {
+        self.descriptors.write().get_mut(index as usize)?.take()
+    }
+    pub fn user_server(&self, tag: u64) -> Option<Arc<RwSpinlock<UserServer>>> {
+        match &self.servers.read().get(tag as usize)?.kind {
+            ServerKind::User(user_server) => Some(user_server.clone()),
+            ServerKind::Kernel(..) => None,
+        }
+    }
+    pub fn reap_thread(&self, thread: &Arc<RwSpinlock<Thread>>) {
+        self.threads
+            .write()
+            .retain(|other| !Arc::ptr_eq(other, thread));
+        self.propagate_priorities();
+    }
+    // shares the process's propagated priority out between its threads and children in
+    // proportion to their set priorities, or evenly when none is set
+    fn share_priority(&self) -> Vec<Arc<Process>> {
+        let propagated = self.propagated_priority.load(Ordering::Relaxed) as u128;
+        let threads = self.threads.read().clone();
+        let children = self.children.read().clone();
+        let set_priorities = threads
+            .iter()
+            .map(|thread| thread.read().set_priority)
+            .chain(children.iter().map(|child| child.set_priority.load(Ordering::Relaxed)))
+            .collect::<Vec<_>>();
+        let sum = set_priorities.iter().map(|priority| *priority as u128).sum::<u128>();
+        let count = set_priorities.len().max(1) as u128;
+        let share = |set_priority: u64| match sum {
+            0 => (propagated / count) as u64,
+            _ => (propagated * set_priority as u128 / sum) as u64,
+        };
+        for thread in threads.iter() {
+            let mut thread_write = thread.write();
+            thread_write.propagated_priority = share(thread_write.set_priority);
+        }
+        for child in children.iter() {
+            child
+                .propagated_priority
+                .store(share(child.set_priority.load(Ordering::Relaxed)), Ordering::Relaxed);
+        }
+        children
+    }
+    // recomputes the propagated priorities of everything below the process, which needs doing
+    // whenever a set priority changes or a thread or child comes or goes. the tree is walked
+    // with a list rather than recursion, so a deep tree cannot run out of kernel stack
+    pub fn propagate_priorities(&self) {
+        let mut pending = self.share_priority();
+        while let Some(child) = pending.pop() {
+            pending.extend(child.share_priority());
+        }
+    }
+    // a process's share comes from its parent, so a change to its set priority is propagated
+    // from there
+    pub fn set_priority(self_arc: &Arc<Self>, priority: u64) {
+        self_arc.set_priority.store(priority, Ordering::Relaxed);
+        match self_arc.parent.read().as_ref().and_then(Weak::upgrade) {
+            Some(parent) => parent.propagate_priorities(),
+            None => self_arc.propagate_priorities(),
+        }
+    }
 }
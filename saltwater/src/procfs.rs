@@ -0,0 +1,346 @@
+use crate::{
+    file::{self, FileError, KERNEL_SERVERS, KernelFile, KernelFilesystem, Listing, Seek},
+    kickstart::KICKSTART_ARC,
+    println,
+    proc::{KILLED_STATUS, Order, Process, Server, ServerKind, State, ThreadState},
+    wire,
+};
+use alloc::{
+    boxed::Box,
+    format,
+    string::String,
+    sync::{Arc, Weak},
+    vec::Vec,
+};
+use core::{fmt::Write, sync::atomic::Ordering};
+const THREADS_NAME: &[u8] = b"threads";
+const PRIORITY_NAME: &[u8] = b"priority";
+const MEMORY_NAME: &[u8] = b"memory";
+const DESCRIPTORS_NAME: &[u8] = b"descriptors";
+const SERVERS_NAME: &[u8] = b"servers";
+const NAMESPACE_NAME: &[u8] = b"namespace";
+const CTL_NAME: &[u8] = b"ctl";
+const FILE_NAMES: [&[u8]; 7] = [
+    THREADS_NAME,
+    PRIORITY_NAME,
+    MEMORY_NAME,
+    DESCRIPTORS_NAME,
+    SERVERS_NAME,
+    NAMESPACE_NAME,
+    CTL_NAME,
+];
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Node {
+    Directory,
+    Threads,
+    Priority,
+    Memory,
+    Descriptors,
+    Servers,
+    Namespace,
+    Ctl,
+}
+// files only hold on to their process weakly, so an open descriptor never keeps a dead process
+// around and starts failing as soon as it has been reaped
+struct ProcFile {
+    node: Node,
+    process: Weak<Process>,
+    listing: Listing,
+    position: u64,
+}
+struct ProcFilesystem;
+static PROCESS_FILESYSTEM: ProcFilesystem = ProcFilesystem;
+impl KernelFilesystem for ProcFilesystem {
+    fn attach(&self) -> Box<dyn KernelFile> {
+        let root = KICKSTART_ARC
+            .read()
+            .as_ref()
+            .map_or(Weak::new(), Arc::downgrade);
+        Box::new(ProcFile::new(Node::Directory, root))
+    }
+}
+fn server_name(server: &Arc<Server>) -> String {
+    match &server.kind {
+        ServerKind::Kernel(..) => KERNEL_SERVERS
+            .read()
+            .iter()
+            .find(|(_, kernel_server)| Arc::ptr_eq(kernel_server, server))
+            .map_or(String::from("kernel"), |(name, _)| {
+                String::from_utf8_lossy(name).into_owned()
+            }),
+        ServerKind::User(..) => String::from("user"),
+    }
+}
+fn state_description(state: State) -> String {
+    [
+        (state.walk, "walk"),
+        (state.rename, "rename"),
+        (state.make, "make"),
+        (state.remove, "remove"),
+        (state.read, "read"),
+        (state.insert, "insert"),
+        (state.overwrite, "overwrite"),
+        (state.truncate, "truncate"),
+        (state.seek_forward, "seek_forward"),
+        (state.seek_backward, "seek_backward"),
+        (state.seek_start, "seek_start"),
+        (state.seek_end, "seek_end"),
+        (state.tell, "tell"),
+        (state.lock, "lock"),
+    ]
+    .into_iter()
+    .filter(|(enabled, _)| *enabled)
+    .map(|(_, name)| name)
+    .collect::<Vec<_>>()
+    .join(",")
+}
+fn threads_description(process: &Process) -> String {
+    let mut description = String::new();
+    for thread in process.threads.read().iter() {
+        let thread = thread.read();
+        let state = match thread.state {
+            ThreadState::Ready => String::from("ready"),
+            ThreadState::Running => String::from("running"),
+            ThreadState::Blocked => String::from("blocked"),
+            ThreadState::Suspended => String::from("suspended"),
+            ThreadState::Exited(value) => format!("exited {}", value),
+        };
+        let _ = writeln!(description, "{} {} {}", thread.id, thread.set_priority, state);
+    }
+    description
+}
+fn memory_description(process: &Process) -> String {
+    let (pages, tables) = process.pages.read().usage();
+    format!("pages {}\ntables {}\n", pages, tables)
+}
+fn descriptors_description(process: &Process) -> String {
+    let mut description = String::new();
+    for (index, descriptor) in process.descriptors.read().iter().enumerate() {
+        let Some(descriptor) = descriptor else {
+            continue;
+        };
+        let server = weak_server_name(&descriptor.server);
+        let _ = writeln!(
+            description,
+            "{} {} /{} {}",
+            index,
+            server,
+            String::from_utf8_lossy(&descriptor.path),
+            state_description(descriptor.state_mask)
+        );
+    }
+    description
+}
+// a user server is followed by the version the kernel negotiated with it, if it has yet
+fn protocol_description(server: &Server) -> String {
+    let ServerKind::User(user_server) = &server.kind else {
+        return String::new();
+    };
+    match *user_server.read().protocol.read() {
+        Some(negotiated) => format!(
+            " {}.{}{}",
+            wire::major(negotiated.protocol.version),
+            negotiated.protocol.version & u64::from(u32::MAX),
+            if negotiated.predates_version { " legacy" } else { "" }
+        ),
+        None => String::from(" unnegotiated"),
+    }
+}
+fn servers_description(process: &Process) -> String {
+    let mut description = String::new();
+    for (index, server) in process.servers.read().iter().enumerate() {
+        let _ = writeln!(
+            description,
+            "{} {}{}",
+            index,
+            server_name(server),
+            protocol_description(server)
+        );
+    }
+    description
+}
+fn weak_server_name(server: &Weak<Server>) -> String {
+    server
+        .upgrade()
+        .map_or(String::from("dead"), |server| server_name(&server))
+}
+fn namespace_description(process: &Process) -> String {
+    let mut description = String::new();
+    for binding in process.namespace.read().bindings.read().iter() {
+        let order = match binding.order {
+            Order::Replace => "replace",
+            Order::Before => "before",
+            Order::After => "after",
+        };
+        let _ = writeln!(
+            description,
+            "{} {} /{} {} /{} {}",
+            order,
+            weak_server_name(&binding.from_server),
+            String::from_utf8_lossy(&binding.from_path),
+            weak_server_name(&binding.to_server),
+            String::from_utf8_lossy(&binding.to_path),
+            state_description(binding.state_mask)
+        );
+    }
+    description
+}
+fn child_name(child: &Process) -> Box<[u8]> {
+    format!("{}", child.id).into_bytes().into_boxed_slice()
+}
+fn parse_decimal(content: &[u8]) -> Result<u64, FileError> {
+    core::str::from_utf8(content.trim_ascii())
+        .ok()
+        .and_then(|text| text.parse().ok())
+        .ok_or(FileError::Invalid)
+}
+impl ProcFile {
+    fn new(node: Node, process: Weak<Process>) -> ProcFile {
+        ProcFile {
+            node,
+            process,
+            listing: Listing::new(),
+            position: 0,
+        }
+    }
+    fn process(&self) -> Result<Arc<Process>, FileError> {
+        self.process.upgrade().ok_or(FileError::NotFound)
+    }
+    // a directory's children mirror the process's own, listed after its files
+    fn entries(&self) -> Vec<Box<[u8]>> {
+        let Ok(process) = self.process() else {
+            return Vec::new();
+        };
+        FILE_NAMES
+            .into_iter()
+            .map(Box::from)
+            .chain(process.children.read().iter().map(|child| child_name(child)))
+            .collect()
+    }
+    // text files are regenerated on every access, so reading in pieces can tear across changes
+    fn content(&self) -> Result<String, FileError> {
+        let process = self.process()?;
+        Ok(match self.node {
+            Node::Threads => threads_description(&process),
+            Node::Priority => format!("{}\n", process.set_priority.load(Ordering::Relaxed)),
+            Node::Memory => memory_description(&process),
+            Node::Descriptors => descriptors_description(&process),
+            Node::Servers => servers_description(&process),
+            Node::Namespace => namespace_description(&process),
+            _ => return Err(FileError::Unsupported),
+        })
+    }
+    fn control(&self, command: &[u8]) -> Result<(), FileError> {
+        let process = self.process()?;
+        match command.trim_ascii() {
+            b"kill" if !process.dying() => Process::kill(&process, KILLED_STATUS),
+            b"suspend" if process.suspend() => {}
+            b"resume" if process.resume() => {}
+            _ => return Err(FileError::Invalid),
+        }
+        Ok(())
+    }
+}
+impl KernelFile for ProcFile {
+    fn state(&self) -> State {
+        match self.node {
+            Node::Directory => State::new().walk(true),
+            Node::Priority => State::new()
+                .read(true)
+                .overwrite(true)
+                .seek(true)
+                .tell(true),
+            Node::Ctl => State::new().overwrite(true),
+            _ => State::new().read(true).seek(true).tell(true),
+        }
+    }
+    fn walk(&mut self, name: &[u8]) -> Result<Box<dyn KernelFile>, FileError> {
+        if name.is_empty() {
+            return Ok(Box::new(ProcFile::new(self.node, self.process.clone())));
+        }
+        if self.node != Node::Directory {
+            return Err(FileError::Unsupported);
+        }
+        let process = self.process()?;
+        let node = match name {
+            THREADS_NAME => Node::Threads,
+            PRIORITY_NAME => Node::Priority,
+            MEMORY_NAME => Node::Memory,
+            DESCRIPTORS_NAME => Node::Descriptors,
+            SERVERS_NAME => Node::Servers,
+            NAMESPACE_NAME => Node::Namespace,
+            CTL_NAME => Node::Ctl,
+            _ => {
+                let child = process
+                    .children
+                    .read()
+                    .iter()
+                    .find(|child| *child_name(child) == *name)
+                    .map(Arc::downgrade)
+                    .ok_or(FileError::NotFound)?;
+                return Ok(Box::new(ProcFile::new(Node::Directory, child)));
+            }
+        };
+        Ok(Box::new(ProcFile::new(node, Arc::downgrade(&process))))
+    }
+    fn list(&mut self, count: usize, advance: bool) -> Result<Vec<Box<[u8]>>, FileError> {
+        if self.node != Node::Directory {
+            return Err(FileError::Unsupported);
+        }
+        let entries = self.entries();
+        self.listing.list(entries, count, advance)
+    }
+    fn list_seek(&mut self, seek: Seek) -> Result<u64, FileError> {
+        let length = self.entries().len();
+        self.listing.seek(seek, length)
+    }
+    fn list_tell(&mut self) -> Result<u64, FileError> {
+        Ok(self.listing.position)
+    }
+    fn read(&mut self, length: usize, advance: bool) -> Result<Vec<u8>, FileError> {
+        if !self.state().read {
+            return Err(FileError::Denied);
+        }
+        let content = self.content()?;
+        let content = content
+            .as_bytes()
+            .get(self.position as usize..)
+            .unwrap_or_default();
+        let content = content[..length.min(content.len())].to_vec();
+        if advance {
+            self.position += content.len() as u64;
+        }
+        Ok(content)
+    }
+    // writable files take the whole of each write as a single value or command, wherever the
+    // head happens to be
+    fn overwrite(&mut self, content: &[u8]) -> Result<usize, FileError> {
+        if !self.state().overwrite {
+            return Err(FileError::Denied);
+        }
+        match self.node {
+            Node::Priority => Process::set_priority(&self.process()?, parse_decimal(content)?),
+            Node::Ctl => self.control(content)?,
+            _ => return Err(FileError::Unsupported),
+        }
+        Ok(content.len())
+    }
+    fn seek(&mut self, seek: Seek) -> Result<u64, FileError> {
+        if !seek.permitted(self.state()) {
+            return Err(FileError::Denied);
+        }
+        let length = self.content()?.len() as u64;
+        self.position = seek.apply(self.position, length)?;
+        Ok(self.position)
+    }
+    fn tell(&mut self) -> Result<u64, FileError> {
+        if !self.state().tell {
+            return Err(FileError::Denied);
+        }
+        Ok(self.position)
+    }
+}
+pub fn initialise(_boot_info: &mut bootloader_api::BootInfo) {
+    file::register(b"proc", &PROCESS_FILESYSTEM);
+    println!("serving the process tree under /proc...");
+}
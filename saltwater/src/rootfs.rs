@@ -0,0 +1,103 @@
+use crate::{
+    file::{FileError, KERNEL_SERVERS, KernelFile, KernelFilesystem, Listing, Seek},
+    namespace::{self, Location},
+    println,
+    proc::{Binding, Grant, KernelServer, Namespace, Order, Process, Server, ServerKind, State},
+};
+use alloc::{boxed::Box, sync::Arc, vec::Vec};
+use spinning_top::RwSpinlock;
+const DEV_NAME: &[u8] = b"dev";
+// kickstart's root, a directory of empty bindpoints: one named after each kernel server, which
+// the server is bound onto before kickstart starts, and /dev for kickstart to gather devices
+// under. it is not a kernel server of its own, so it never lists itself
+struct RootFile {
+    root: bool,
+    listing: Listing,
+}
+struct RootFilesystem;
+static ROOT_FILESYSTEM: RootFilesystem = RootFilesystem;
+static ROOT_SERVER: RwSpinlock<Option<Arc<Server>>> = RwSpinlock::new(None);
+impl KernelFilesystem for RootFilesystem {
+    fn attach(&self) -> Box<dyn KernelFile> {
+        Box::new(RootFile::new(true))
+    }
+}
+impl RootFile {
+    fn new(root: bool) -> RootFile {
+        RootFile {
+            root,
+            listing: Listing::new(),
+        }
+    }
+    fn entries(&self) -> Vec<Box<[u8]>> {
+        if !self.root {
+            return Vec::new();
+        }
+        KERNEL_SERVERS
+            .read()
+            .iter()
+            .map(|(name, _)| *name)
+            .chain([DEV_NAME])
+            .map(Box::from)
+            .collect()
+    }
+}
+impl KernelFile for RootFile {
+    fn state(&self) -> State {
+        State::new().walk(true)
+    }
+    fn walk(&mut self, name: &[u8]) -> Result<Box<dyn KernelFile>, FileError> {
+        if !self.entries().iter().any(|entry| **entry == *name) {
+            return Err(FileError::NotFound);
+        }
+        Ok(Box::new(RootFile::new(false)))
+    }
+    fn list(&mut self, count: usize, advance: bool) -> Result<Vec<Box<[u8]>>, FileError> {
+        let entries = self.entries();
+        self.listing.list(entries, count, advance)
+    }
+    fn list_seek(&mut self, seek: Seek) -> Result<u64, FileError> {
+        let length = self.entries().len();
+        self.listing.seek(seek, length)
+    }
+    fn list_tell(&mut self) -> Result<u64, FileError> {
+        Ok(self.listing.position)
+    }
+}
+// binds every kernel server onto its bindpoint in the process's namespace and gives the process
+// a descriptor on the root, returning the descriptor's index
+pub fn workspace(process: &Process) -> Result<u64, FileError> {
+    let root = ROOT_SERVER
+        .read()
+        .clone()
+        .expect("root server used before it was initialised!");
+    let namespace = process.namespace.read().clone();
+    for (name, server) in KERNEL_SERVERS.read().iter() {
+        namespace.bindings.write().push(Binding {
+            id: Namespace::next_binding_id(),
+            from_server: Arc::downgrade(server),
+            from_path: Box::from([]),
+            to_server: Arc::downgrade(&root),
+            to_path: Box::from(*name),
+            state_mask: State::all(),
+            order: Order::Replace,
+            grant: Grant::root(),
+        });
+    }
+    let location = Location {
+        server: root,
+        path: Box::from([]),
+        state_mask: State::all(),
+        through: Vec::new(),
+    };
+    let descriptor = namespace::descriptor(&namespace, location, Grant::root())?;
+    Ok(process.add_descriptor(descriptor))
+}
+pub fn initialise(_boot_info: &mut bootloader_api::BootInfo) {
+    let _ = ROOT_SERVER.write().insert(Arc::new(Server {
+        kind: ServerKind::Kernel(KernelServer {
+            filesystem: &ROOT_FILESYSTEM,
+        }),
+    }));
+    println!("serving kickstart's root...");
+}
@@ -1,16 +1,617 @@
-use alloc::{collections::vec_deque::VecDeque, sync::{Arc, Weak}};
+use alloc::{collections::{binary_heap::BinaryHeap, vec_deque::VecDeque}, sync::{Arc, Weak}, vec::Vec};
+use core::{arch::{asm, global_asm}, cmp, ptr, sync::atomic::Ordering};
 use spinning_top::RwSpinlock;
-use crate::proc::{Process, Thread};
+use x86_64::{PrivilegeLevel, VirtAddr, registers::control::Cr3, structures::{idt::InterruptStackFrame, paging::PhysFrame}};
+use crate::{core::{KernelGs, PROCESSOR_DATA_VEC, ProcessorData, ProcessorLocal, local}, fpu, kickstart, lapic, proc::{KILLED_STATUS, Message, Process, ProcessStatus, REGISTER_RDI, REGISTER_RSP, Thread, ThreadState, UserServer, stop_waiting}, println, time, tls};
+pub const TIMESLICE_NANOS: u64 = 10_000_000;
+pub const TICK_NANOS: u64 = 1_000_000;
+pub const REBALANCE_NANOS: u64 = 100_000_000;
+pub const ANY_PROCESSOR: u64 = u64::MAX;
+#[derive(Clone, Copy, Debug, Default)]
+pub struct RunStatistics {
+    pub dispatched: u64,
+    pub stolen: u64,
+    pub stolen_from: u64,
+    pub busy_nanos: u64,
+    pub idle_nanos: u64,
+}
+// saves the callee-saved registers on the current stack, stores its pointer through the first
+// argument and resumes whatever context was saved on the stack given as the second
+global_asm!(
+    ".global switch_context",
+    "switch_context:",
+    "push rbp",
+    "push rbx",
+    "push r12",
+    "push r13",
+    "push r14",
+    "push r15",
+    "mov [rdi], rsp",
+    "mov rsp, rsi",
+    "pop r15",
+    "pop r14",
+    "pop r13",
+    "pop r12",
+    "pop rbx",
+    "pop rbp",
+    "ret",
+);
+unsafe extern "C" {
+    fn switch_context(from: *mut u64, to: u64);
+}
+const SAVED_REGISTERS: usize = 6;
+// builds a context on a new thread's kernel stack that switch_context will return from straight
+// into user_entry, with the stack aligned as if user_entry had been called
+pub fn initial_kernel_context(stack_top: u64) -> u64 {
+    let frame = ((stack_top & !0xf) - (SAVED_REGISTERS as u64 + 2) * 8) as *mut u64;
+    unsafe {
+        for index in 0..SAVED_REGISTERS {
+            frame.add(index).write(0);
+        }
+        frame.add(SAVED_REGISTERS).write(user_entry as *const () as u64);
+        frame.add(SAVED_REGISTERS + 1).write(0);
+    }
+    frame as u64
+}
+// a thread's first time on a processor, which leaves for its entry point with the gs bases
+// swapped as the syscall return path would leave them
+extern "C" fn user_entry() -> ! {
+    let thread = current_thread();
+    let (instruction_pointer, rflags, stack_pointer, argument) = {
+        let mut thread_write = thread.write();
+        thread_write.started = true;
+        let context = &thread_write.user_context;
+        (context.instruction_pointer, context.rflags, context.registers[REGISTER_RSP], context.registers[REGISTER_RDI])
+    };
+    drop(thread);
+    checkpoint();
+    unsafe {
+        asm!(
+            "mov rsp, {stack_pointer}",
+            "xor eax, eax",
+            "xor edx, edx",
+            "xor esi, esi",
+            "xor ebx, ebx",
+            "xor ebp, ebp",
+            "xor r8d, r8d",
+            "xor r9d, r9d",
+            "xor r10d, r10d",
+            "xor r12d, r12d",
+            "xor r13d, r13d",
+            "xor r14d, r14d",
+            "xor r15d, r15d",
+            "swapgs",
+            "sysretq",
+            stack_pointer = in(reg) stack_pointer,
+            in("rcx") instruction_pointer,
+            in("r11") rflags,
+            in("rdi") argument,
+            options(noreturn),
+        )
+    }
+}
+// a thread switched back in to find its process killed, which has to unwind back to the syscall
+// boundary rather than carry on
+#[derive(Debug)]
+pub struct Killed;
+// hands the running thread back to this processor's idle loop, which decides what happens to it
+// from the state it left itself in. returns once the thread has been switched back in, failing
+// if its process has been killed in the meantime so that the caller unwinds back to the syscall
+// boundary, where the thread exits with nothing left on its kernel stack
+pub fn leave_processor() -> Result<(), Killed> {
+    let local = local();
+    let context = local.data.read().scheduler.current_thread.as_ref().expect("left processor with no thread running!").read().kernel_context.as_ptr();
+    unsafe { switch_context(context, local.scheduler_context.load(Ordering::Relaxed)) };
+    if current_process().is_none_or(|process| process.dying()) {
+        return Err(Killed);
+    }
+    Ok(())
+}
+// blocks the running thread until it is woken. the caller must already have published it
+// somewhere a waker will find it
+pub fn block_current() -> Result<(), Killed> {
+    current_thread().write().state = ThreadState::Blocked;
+    leave_processor()
+}
+pub fn yield_current() -> Result<(), Killed> {
+    leave_processor()
+}
+fn current_process() -> Option<Arc<Process>> {
+    current_thread().read().process.upgrade()
+}
+// stops the running thread where its process wants it stopped, only to be called where the
+// thread holds nothing on its kernel stack
+pub fn checkpoint() {
+    exit_if_killed();
+    if current_process().is_some_and(|process| process.held.read().is_some()) {
+        // the thread is held back the next time it is switched in, and carries on from here
+        // once resumed
+        if yield_current().is_err() {
+            exit_if_killed();
+        }
+    }
+}
+fn exit_if_killed() {
+    let status = current_process().map_or(Some(KILLED_STATUS), |process| match process.lifecycle.read().status {
+        ProcessStatus::Running => None,
+        ProcessStatus::Killed(status) | ProcessStatus::Exited(status) => Some(status),
+    });
+    if let Some(status) = status {
+        exit_current(status);
+    }
+}
+pub fn exit_current(value: u64) -> ! {
+    current_thread().write().state = ThreadState::Exited(value);
+    // an exited thread is never switched back in, dying process or not
+    let _ = leave_processor();
+    unreachable!("exited thread was switched back in!");
+}
+pub fn current_thread() -> Arc<RwSpinlock<Thread>> {
+    local().data.read().scheduler.current_thread.clone().expect("no thread running on this processor!")
+}
+// makes a blocked thread runnable. one that is still on its way off a processor is only marked
+// ready, and its processor requeues it once it has switched away
+pub fn wake(thread: Arc<RwSpinlock<Thread>>) {
+    let mut thread_write = thread.write();
+    if thread_write.state != ThreadState::Blocked {
+        return;
+    }
+    thread_write.state = ThreadState::Ready;
+    if !thread_write.on_processor {
+        drop(thread_write);
+        place(thread);
+    }
+}
+// something a thread can wait on, woken through the waiting list of whatever it refers to
+pub enum Source {
+    Response(Weak<Message>),
+    Request(Arc<RwSpinlock<UserServer>>),
+    Cancellation(Weak<Message>),
+}
+impl Source {
+    // a message that has gone away counts as ready, so that waiting on it fails straight away
+    // rather than never returning
+    fn register(&self, thread: &Arc<RwSpinlock<Thread>>) -> bool {
+        match self {
+            Source::Response(message) | Source::Cancellation(message) => {
+                let Some(message) = message.upgrade() else {
+                    return true;
+                };
+                message.waiting.write().push(thread.clone());
+                match self {
+                    Source::Response(..) => message.responded(),
+                    _ => message.cancelled(),
+                }
+            }
+            Source::Request(user_server) => {
+                let user_server_read = user_server.read();
+                user_server_read.waiting.write().push(thread.clone());
+                user_server_read.pending()
+            }
+        }
+    }
+    fn deregister(&self, thread: &Arc<RwSpinlock<Thread>>) {
+        match self {
+            Source::Response(message) | Source::Cancellation(message) => {
+                if let Some(message) = message.upgrade() {
+                    stop_waiting(&message.waiting, thread);
+                }
+            }
+            Source::Request(user_server) => stop_waiting(&user_server.read().waiting, thread),
+        }
+    }
+}
+// blocks until one of the sources is ready, returning the first that is, or until the deadline
+// passes. the thread is marked blocked before registering, so a source becoming ready between
+// registering and leaving the processor only makes it ready again
+pub fn wait_any(sources: &[Source], deadline: Option<u64>) -> Result<Option<usize>, Killed> {
+    loop {
+        let current = current_thread();
+        current.write().state = ThreadState::Blocked;
+        let ready = sources
+            .iter()
+            .map(|source| source.register(&current))
+            .collect::<Vec<_>>()
+            .into_iter()
+            .position(|ready| ready);
+        let expired = deadline.is_some_and(|deadline| time::nanos_since_boot() >= deadline);
+        let left = if ready.is_some() || expired {
+            current.write().state = ThreadState::Running;
+            Ok(())
+        } else {
+            if let Some(deadline) = deadline {
+                local().data.write().scheduler.park_current(deadline);
+            }
+            leave_processor()
+        };
+        for source in sources {
+            source.deregister(&current);
+        }
+        left?;
+        if ready.is_some() || expired {
+            return Ok(ready);
+        }
+    }
+}
+// the kernel runs with interrupts masked, so the timer only ever interrupts ring 3 and the
+// interrupted thread's kernel stack holds nothing but the interrupt frame. a thread that has used
+// up its timeslice leaves the processor from here like any yield, and carries on returning to
+// user mode whenever and wherever it is switched back in
+pub extern "x86-interrupt" fn timer_handler(stack_frame: InterruptStackFrame) {
+    let kernel_gs = KernelGs::enter(&stack_frame);
+    lapic::end_of_interrupt();
+    if stack_frame.code_segment.rpl() != PrivilegeLevel::Ring3 {
+        return;
+    }
+    let expired = {
+        let mut data_write = local().data.write();
+        data_write.scheduler.current_thread.is_some() && data_write.scheduler.account()
+    };
+    if expired {
+        let _ = yield_current();
+        checkpoint();
+    }
+    drop(kernel_gs);
+}
+// switches from the idle loop into a dispatched thread and back again
+fn run(local: &ProcessorLocal, thread: Arc<RwSpinlock<Thread>>) {
+    let (context, stack_top, process) = {
+        let mut thread_write = thread.write();
+        thread_write.state = ThreadState::Running;
+        thread_write.on_processor = true;
+        (thread_write.kernel_context.load(Ordering::Relaxed), thread_write.kernel_stack.top(), thread_write.process.upgrade())
+    };
+    let Some(process) = process else {
+        // the process went away while the thread was queued
+        release(local, &thread);
+        thread.write().on_processor = false;
+        return;
+    };
+    if process.hold(&thread, || release(local, &thread)) {
+        return;
+    }
+    drop(thread);
+    let kernel_table = Cr3::read();
+    let table = PhysFrame::containing_address(process.pages.read().physical_address());
+    if table != kernel_table.0 {
+        unsafe { Cr3::write(table, kernel_table.1) };
+    }
+    local.syscall_stack_top.store(stack_top, Ordering::Relaxed);
+    {
+        let mut data_write = local.data.write();
+        data_write.task_state.privilege_stack_table[0] = VirtAddr::new(stack_top);
+        data_write.scheduler.current_process = Some(Arc::downgrade(&process));
+    }
+    drop(process);
+    unsafe { switch_context(local.scheduler_context.as_ptr(), context) };
+    // the process may be torn down as soon as its thread is off the processor, so its table
+    // cannot be left loaded
+    unsafe { Cr3::write(kernel_table.0, kernel_table.1) };
+    settle(local);
+}
+// takes a thread that is not going to be switched in after all back off its processor
+fn release(local: &ProcessorLocal, thread: &Arc<RwSpinlock<Thread>>) {
+    let mut data_write = local.data.write();
+    if data_write.scheduler.owns_fpu(thread) {
+        fpu::resume();
+        thread.write().extended_state.save();
+        data_write.scheduler.fpu_owner = None;
+    }
+    data_write.scheduler.current_thread = None;
+    local.current_thread.store(ptr::null_mut(), Ordering::Release);
+}
+// deals with the thread that just switched back to the idle loop
+fn settle(local: &ProcessorLocal) {
+    let mut data_write = local.data.write();
+    data_write.scheduler.charge();
+    let Some(thread) = data_write.scheduler.current_thread.take() else {
+        return;
+    };
+    data_write.scheduler.current_process = None;
+    local.current_thread.store(ptr::null_mut(), Ordering::Release);
+    let owns_fpu = data_write.scheduler.owns_fpu(&thread);
+    let mut thread_write = thread.write();
+    thread_write.on_processor = false;
+    tls::save_bases(&mut thread_write.user_context);
+    match thread_write.state {
+        // a yield, or a wake that arrived while the thread was switching away. its extended
+        // state can stay loaded, as threads owning it are never stolen
+        ThreadState::Running | ThreadState::Ready => {
+            thread_write.state = ThreadState::Ready;
+            drop(thread_write);
+            if allowed(&thread, local.processor) {
+                data_write.scheduler.ready_queue.push_back(thread);
+                return;
+            }
+            // its affinity changed while it ran here, and only one scheduler is locked at a time
+            if owns_fpu {
+                fpu::resume();
+                thread.write().extended_state.save();
+                data_write.scheduler.fpu_owner = None;
+            }
+            drop(data_write);
+            place(thread);
+        }
+        // a blocked thread may be woken onto any processor, so its extended state is saved now.
+        // threads are only ever suspended before being switched in
+        ThreadState::Blocked | ThreadState::Suspended => {
+            if owns_fpu {
+                fpu::resume();
+                thread_write.extended_state.save();
+                data_write.scheduler.fpu_owner = None;
+            }
+        }
+        ThreadState::Exited(_) => {
+            if owns_fpu {
+                data_write.scheduler.fpu_owner = None;
+            }
+            drop(data_write);
+            drop(thread_write);
+            retire(thread);
+        }
+    }
+}
+// hands an exited thread that is off its processor to whoever is waiting for it, or releases it
+// straight away if nobody can wait for it
+pub fn retire(thread: Arc<RwSpinlock<Thread>>) {
+    let (process, detached, joiner, value) = {
+        let mut thread_write = thread.write();
+        let ThreadState::Exited(value) = thread_write.state else {
+            panic!("retired a thread that has not exited!");
+        };
+        (thread_write.process.upgrade(), thread_write.detached, thread_write.joiner.take(), value)
+    };
+    let Some(process) = process else {
+        return;
+    };
+    if detached || process.dying() {
+        process.reap_thread(&thread);
+    }
+    if let Some(joiner) = joiner {
+        wake(joiner);
+    }
+    drop(thread);
+    Process::thread_retired(&process, value);
+}
+pub struct Sleeper {
+    pub deadline: u64,
+    pub thread: Arc<RwSpinlock<Thread>>,
+}
+// ordered by earliest deadline first, as the binary heap is a max-heap
+impl Ord for Sleeper {
+    fn cmp(&self, other: &Self) -> cmp::Ordering {
+        other.deadline.cmp(&self.deadline)
+    }
+}
+impl PartialOrd for Sleeper {
+    fn partial_cmp(&self, other: &Self) -> Option<cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+impl PartialEq for Sleeper {
+    fn eq(&self, other: &Self) -> bool {
+        self.deadline == other.deadline
+    }
+}
+impl Eq for Sleeper {}
 pub struct ProcessorScheduler {
     pub ready_queue: VecDeque<Arc<RwSpinlock<Thread>>>,
+    pub sleeping: BinaryHeap<Sleeper>,
     pub current_process: Option<Weak<Process>>,
     pub current_thread: Option<Arc<RwSpinlock<Thread>>>,
+    pub timeslice_start: u64,
+    pub charged_until: u64,
+    pub statistics: RunStatistics,
+    pub fpu_owner: Option<Weak<RwSpinlock<Thread>>>,
+}
+// a thread's share of a processor grows with the priority propagated down to it from the root of
+// the process tree, and a thread whose share rounds to 0 still counts as load
+fn weight(thread: &Arc<RwSpinlock<Thread>>) -> u64 {
+    thread.read().propagated_priority.saturating_add(1)
+}
+// affinity masks only have room for the first 64 processors, any beyond that are only allowed
+// for threads with no affinity set
+fn allows(affinity: u64, processor: usize) -> bool {
+    match 1u64.checked_shl(processor as u32) {
+        Some(bit) if processor < 64 => affinity & bit != 0,
+        _ => affinity == ANY_PROCESSOR,
+    }
+}
+fn allowed(thread: &Arc<RwSpinlock<Thread>>, processor: usize) -> bool {
+    allows(thread.read().affinity, processor)
+}
+// whether a thread with the affinity mask would have any online processor to run on
+pub fn runnable_with(affinity: u64) -> bool {
+    online_processors()
+        .into_iter()
+        .any(|(processor, _)| allows(affinity, processor))
+}
+fn online_processors() -> Vec<(usize, &'static RwSpinlock<ProcessorData>)> {
+    PROCESSOR_DATA_VEC
+        .read()
+        .iter()
+        .copied()
+        .enumerate()
+        .filter(|(_, data)| data.read().online)
+        .collect()
+}
+pub fn report() {
+    for (processor, data) in online_processors() {
+        let statistics = data.read().scheduler.statistics;
+        println!(
+            "processor no. {} dispatched {} thread/s, stole {} and gave {}, busy for {}ms and idle for {}ms...",
+            processor,
+            statistics.dispatched,
+            statistics.stolen,
+            statistics.stolen_from,
+            statistics.busy_nanos / 1_000_000,
+            statistics.idle_nanos / 1_000_000
+        );
+    }
+}
+// queues a runnable thread on the least loaded processor its affinity allows. masks without an
+// online processor are refused when they are set, but should one slip through the thread is
+// still run somewhere rather than lost
+pub fn place(thread: Arc<RwSpinlock<Thread>>) {
+    let processors = online_processors();
+    let least_loaded = |allowed_only: bool| {
+        processors
+            .iter()
+            .filter(|(processor, _)| !allowed_only || allowed(&thread, *processor))
+            .min_by_key(|(_, data)| data.read().scheduler.load())
+            .map(|(_, data)| *data)
+    };
+    let data = least_loaded(true)
+        .or_else(|| least_loaded(false))
+        .unwrap_or(local().data);
+    data.write().scheduler.ready_queue.push_back(thread);
+}
+// takes one thread from the most loaded other processor. only one scheduler is ever locked at a
+// time, so two processors stealing from each other cannot deadlock
+pub fn steal(processor: usize) -> Option<Arc<RwSpinlock<Thread>>> {
+    let (_, victim) = online_processors()
+        .into_iter()
+        .filter(|(victim, _)| *victim != processor)
+        .map(|(_, data)| (data.read().scheduler.queued_load(), data))
+        .filter(|(load, _)| *load > 0)
+        .max_by_key(|(load, _)| *load)?;
+    victim.write().scheduler.give(processor)
+}
+// pulls work towards this processor until it carries at least its share of the total load, each
+// processor only ever pulls so that rebalancing never fights itself
+pub fn rebalance(processor: usize) {
+    let processors = online_processors();
+    let total = processors
+        .iter()
+        .map(|(_, data)| data.read().scheduler.load())
+        .fold(0, u64::saturating_add);
+    let share = total / processors.len().max(1) as u64;
+    let data = local().data;
+    for _ in 0..processors.len() {
+        if data.read().scheduler.load() >= share {
+            break;
+        }
+        let Some(thread) = steal(processor) else {
+            break;
+        };
+        let mut data_write = data.write();
+        data_write.scheduler.statistics.stolen += 1;
+        data_write.scheduler.ready_queue.push_back(thread);
+    }
 }
 impl ProcessorScheduler {
+    // a processor never returns from its scheduler, until threads can be switched into it idles
+    // here keeping its sleeping queue drained into its ready queue
     pub fn enter() -> ! {
-        panic!()
+        let local = local();
+        let mut last_rebalance = time::nanos_since_boot();
+        let mut last_loop = last_rebalance;
+        loop {
+            let idle = {
+                let mut data_write = local.data.write();
+                data_write.scheduler.wake_sleepers();
+                data_write.scheduler.ready_queue.is_empty() & data_write.scheduler.current_thread.is_none()
+            };
+            let now = time::nanos_since_boot();
+            if idle {
+                kickstart::check_finished();
+                local.data.write().scheduler.statistics.idle_nanos += now - last_loop;
+                if let Some(thread) = steal(local.processor) {
+                    let mut data_write = local.data.write();
+                    data_write.scheduler.statistics.stolen += 1;
+                    data_write.scheduler.ready_queue.push_back(thread);
+                }
+            } else if now - last_rebalance >= REBALANCE_NANOS {
+                rebalance(local.processor);
+                last_rebalance = now;
+            }
+            last_loop = now;
+            let next = local.data.write().scheduler.dispatch();
+            match next {
+                Some(thread) => run(local, thread),
+                None => core::hint::spin_loop(),
+            }
+        }
     }
     pub fn new() -> ProcessorScheduler {
-        ProcessorScheduler { ready_queue: VecDeque::new(), current_process: None, current_thread: None }
+        ProcessorScheduler { ready_queue: VecDeque::new(), sleeping: BinaryHeap::new(), current_process: None, current_thread: None, timeslice_start: 0, charged_until: 0, statistics: RunStatistics::default(), fpu_owner: None }
+    }
+    // charges the time since the last charge to the running thread
+    pub fn charge(&mut self) {
+        let now = time::nanos_since_boot();
+        let elapsed = now.saturating_sub(self.charged_until);
+        if let Some(thread) = self.current_thread.as_ref() {
+            let mut thread_write = thread.write();
+            thread_write.virtual_time = thread_write.virtual_time.saturating_add(elapsed as isize);
+            self.statistics.busy_nanos += elapsed;
+        }
+        self.charged_until = now;
+    }
+    // charges the running thread on every tick, returning whether its timeslice has been used up
+    pub fn account(&mut self) -> bool {
+        self.charge();
+        self.charged_until.saturating_sub(self.timeslice_start) >= TIMESLICE_NANOS
     }
-}
\ No newline at end of file
+    // blocks the running thread until the deadline has passed, without it occupying the ready
+    // queue in the meantime. the caller leaves the processor once the scheduler is unlocked
+    pub fn park_current(&mut self, deadline: u64) {
+        if let Some(thread) = self.current_thread.as_ref() {
+            thread.write().state = ThreadState::Blocked;
+            self.sleeping.push(Sleeper { deadline, thread: thread.clone() });
+        }
+    }
+    // the weight of everything that could be migrated away from this processor. weights go up
+    // to the whole priority range, so loads saturate rather than overflow
+    pub fn queued_load(&self) -> u64 {
+        self.ready_queue.iter().map(weight).fold(0, u64::saturating_add)
+    }
+    pub fn load(&self) -> u64 {
+        self.queued_load().saturating_add(self.current_thread.as_ref().map_or(0, weight))
+    }
+    // hands over the thread nearest the back of the queue, which would otherwise wait longest
+    // here, that the stealing processor is allowed to run
+    fn give(&mut self, processor: usize) -> Option<Arc<RwSpinlock<Thread>>> {
+        // a thread whose extended state is still loaded in this processor's registers has to be
+        // run here, as nothing else can save that state
+        let index = self.ready_queue.iter().rposition(|thread| allowed(thread, processor) & !self.owns_fpu(thread))?;
+        self.statistics.stolen_from += 1;
+        self.ready_queue.remove(index)
+    }
+    // moves the next queued thread onto this processor, returning it for the context switch
+    pub fn dispatch(&mut self) -> Option<Arc<RwSpinlock<Thread>>> {
+        let thread = self.ready_queue.pop_front()?;
+        if let Some(previous) = self.current_thread.replace(thread.clone()) {
+            self.ready_queue.push_back(previous);
+        }
+        local().current_thread.store(Arc::as_ptr(&thread) as *mut _, Ordering::Release);
+        self.timeslice_start = time::nanos_since_boot();
+        self.charged_until = self.timeslice_start;
+        self.statistics.dispatched += 1;
+        if self.owns_fpu(&thread) {
+            fpu::resume();
+        } else {
+            fpu::defer();
+        }
+        tls::load_bases(&thread.read().user_context);
+        Some(thread)
+    }
+    fn owns_fpu(&self, thread: &Arc<RwSpinlock<Thread>>) -> bool {
+        self.fpu_owner.as_ref().is_some_and(|owner| ptr::eq(owner.as_ptr(), Arc::as_ptr(thread)))
+    }
+    pub fn wake_sleepers(&mut self) {
+        let now = time::nanos_since_boot();
+        while self.sleeping.peek().is_some_and(|sleeper| sleeper.deadline <= now) {
+            let sleeper = self.sleeping.pop().expect("sleeping queue emptied while waking sleepers!");
+            let mut thread_write = sleeper.thread.write();
+            if thread_write.state != ThreadState::Blocked {
+                continue;
+            }
+            thread_write.state = ThreadState::Ready;
+            // a sleeper still switching away is requeued by its own processor
+            if !thread_write.on_processor {
+                drop(thread_write);
+                self.ready_queue.push_back(sleeper.thread);
+            }
+        }
+    }
+}
@@ -0,0 +1,305 @@
+use crate::{
+    acpi::ACPI_PLATFORM,
+    core::{PROCESSOR_DATA_VEC, install_local},
+    fpu,
+    frame::PAGE_FRAME_ALLOCATOR,
+    gdt, idt, lapic,
+    mapping::{
+        INTERRUPT_STACK_SIZE, PAGE_SIZE, bootstrap_stack_address, physical_to_virtual_address,
+    },
+    page::{get_current_pml4, get_offset_table},
+    println,
+    scheduler::ProcessorScheduler,
+    syscall, time,
+};
+use acpi::platform::ProcessorState;
+use alloc::vec::Vec;
+use core::{
+    arch::global_asm,
+    sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering},
+};
+use x86_64::{
+    PhysAddr, VirtAddr,
+    instructions::tlb,
+    registers::control::Cr3,
+    structures::paging::{FrameDeallocator, Mapper, Page, PageTableFlags, PhysFrame, Size4KiB},
+};
+const CONVENTIONAL_MEMORY_LIMIT: u64 = 0x10_0000;
+const FOUR_GIGABYTES: u64 = 0x1_0000_0000;
+const INIT_DELAY_MICROSECONDS: u64 = 10_000;
+const STARTUP_DELAY_MICROSECONDS: u64 = 200;
+const ONLINE_TIMEOUT_MICROSECONDS: u64 = 100_000;
+// the trampoline is copied into a conventional memory page and entered in real mode with cs set
+// to that page, so it only addresses itself relative to its start until it reaches long mode,
+// after which rip-relative addressing works from wherever it was copied to. the fields after the
+// code are patched by the bootstrap processor before each startup
+global_asm!(
+    ".section .rodata.trampoline, \"a\"",
+    ".global trampoline_start",
+    ".global trampoline_long_mode",
+    ".global trampoline_gdt",
+    ".global trampoline_gdt_base",
+    ".global trampoline_long_mode_target",
+    ".global trampoline_cr3",
+    ".global trampoline_stack",
+    ".global trampoline_entry",
+    ".global trampoline_processor",
+    ".global trampoline_end",
+    ".code16",
+    "trampoline_start:",
+    "cli",
+    "cld",
+    "mov ax, cs",
+    "mov ds, ax",
+    // lgdt [trampoline_gdt_pointer], the assembler cannot take the offset as a memory operand
+    ".byte 0x0f, 0x01, 0x16",
+    ".word trampoline_gdt_pointer - trampoline_start",
+    // pae and global pages
+    "mov eax, cr4",
+    "or eax, 0xa0",
+    "mov cr4, eax",
+    // mov eax, dword [trampoline_cr3]
+    ".byte 0x66, 0xa1",
+    ".word trampoline_cr3 - trampoline_start",
+    "mov cr3, eax",
+    // long mode and no-execute in the extended feature enable register
+    "mov ecx, 0xc0000080",
+    "rdmsr",
+    "or eax, 0x900",
+    "wrmsr",
+    // protection, write protection and paging all at once, skipping protected mode proper
+    "mov eax, cr0",
+    "or eax, 0x80010001",
+    "mov cr0, eax",
+    // jmp far dword [trampoline_long_mode_pointer]
+    ".byte 0x66, 0xff, 0x2e",
+    ".word trampoline_long_mode_pointer - trampoline_start",
+    ".code64",
+    "trampoline_long_mode:",
+    "mov ax, 0x10",
+    "mov ds, ax",
+    "mov es, ax",
+    "mov ss, ax",
+    "xor ax, ax",
+    "mov fs, ax",
+    "mov gs, ax",
+    "mov rsp, [rip + trampoline_stack]",
+    "mov rdi, [rip + trampoline_processor]",
+    "mov rax, [rip + trampoline_entry]",
+    "call rax",
+    "ud2",
+    "trampoline_gdt:",
+    ".quad 0",
+    ".quad 0x00af9a000000ffff",
+    ".quad 0x00cf92000000ffff",
+    "trampoline_gdt_pointer:",
+    ".word 23",
+    "trampoline_gdt_base:",
+    ".long 0",
+    "trampoline_long_mode_pointer:",
+    "trampoline_long_mode_target:",
+    ".long 0",
+    ".word 0x08",
+    "trampoline_cr3:",
+    ".quad 0",
+    "trampoline_stack:",
+    ".quad 0",
+    "trampoline_entry:",
+    ".quad 0",
+    "trampoline_processor:",
+    ".quad 0",
+    "trampoline_end:",
+    ".code64",
+    ".text",
+);
+unsafe extern "C" {
+    static trampoline_start: u8;
+    static trampoline_long_mode: u8;
+    static trampoline_gdt: u8;
+    static trampoline_gdt_base: u8;
+    static trampoline_long_mode_target: u8;
+    static trampoline_cr3: u8;
+    static trampoline_stack: u8;
+    static trampoline_entry: u8;
+    static trampoline_processor: u8;
+    static trampoline_end: u8;
+}
+static PROCESSOR_ONLINE: AtomicBool = AtomicBool::new(false);
+// once every processor is started the trampoline's identity mapping is removed, and each
+// application processor flushes it from its own tlb before the frame behind it is given back
+static TRAMPOLINE_PAGE: AtomicU64 = AtomicU64::new(0);
+static TRAMPOLINE_UNMAPPED: AtomicBool = AtomicBool::new(false);
+static TRAMPOLINE_FLUSHES: AtomicUsize = AtomicUsize::new(0);
+pub static ONLINE_PROCESSORS: AtomicUsize = AtomicUsize::new(1);
+fn trampoline_offset(symbol: *const u8) -> usize {
+    symbol as usize - &raw const trampoline_start as usize
+}
+unsafe fn patch<T>(trampoline: u64, symbol: *const u8, value: T) {
+    unsafe {
+        ((physical_to_virtual_address(trampoline) as usize + trampoline_offset(symbol)) as *mut T)
+            .write_unaligned(value)
+    };
+}
+extern "C" fn application_entry(processor: usize) -> ! {
+    unsafe { gdt::load(&PROCESSOR_DATA_VEC.read()[processor].read().gdt_selectors) };
+    install_local(processor);
+    idt::load();
+    syscall::enable();
+    fpu::enable();
+    lapic::enable();
+    println!(
+        "processor no. {} with apic id {} online...",
+        processor,
+        lapic::id()
+    );
+    ONLINE_PROCESSORS.fetch_add(1, Ordering::SeqCst);
+    PROCESSOR_ONLINE.store(true, Ordering::SeqCst);
+    while !TRAMPOLINE_UNMAPPED.load(Ordering::SeqCst) {
+        core::hint::spin_loop();
+    }
+    tlb::flush(VirtAddr::new(TRAMPOLINE_PAGE.load(Ordering::SeqCst)));
+    TRAMPOLINE_FLUSHES.fetch_add(1, Ordering::SeqCst);
+    ProcessorScheduler::enter();
+}
+fn start_processor(trampoline: u64, processor: usize, apic_id: u32) -> bool {
+    unsafe {
+        patch(
+            trampoline,
+            &raw const trampoline_stack,
+            bootstrap_stack_address(processor) + INTERRUPT_STACK_SIZE,
+        );
+        patch(
+            trampoline,
+            &raw const trampoline_processor,
+            processor as u64,
+        );
+    }
+    PROCESSOR_ONLINE.store(false, Ordering::SeqCst);
+    lapic::send_init(apic_id);
+    time::stall(INIT_DELAY_MICROSECONDS);
+    // the second startup is only sent if the first was missed, as recommended by the mp spec
+    for _ in 0..2 {
+        lapic::send_startup(apic_id, (trampoline / PAGE_SIZE) as u8);
+        time::stall(STARTUP_DELAY_MICROSECONDS);
+        if PROCESSOR_ONLINE.load(Ordering::SeqCst) {
+            return true;
+        }
+    }
+    let deadline = time::nanos_since_boot() + ONLINE_TIMEOUT_MICROSECONDS * 1_000;
+    while !PROCESSOR_ONLINE.load(Ordering::SeqCst) {
+        if time::nanos_since_boot() >= deadline {
+            return false;
+        }
+        core::hint::spin_loop();
+    }
+    true
+}
+pub fn initialise(_boot_info: &mut bootloader_api::BootInfo) {
+    let application_processors = ACPI_PLATFORM
+        .read()
+        .as_ref()
+        .expect("ACPI platform not initialised before processor startup!")
+        .processor_info
+        .as_ref()
+        .expect("ACPI platform does not contain processor info!")
+        .application_processors
+        .iter()
+        .map(|processor| (processor.local_apic_id, processor.state))
+        .collect::<Vec<(u32, ProcessorState)>>();
+    let cr3 = Cr3::read().0.start_address().as_u64();
+    if cr3 >= FOUR_GIGABYTES {
+        panic!("kernel page table is out of reach of the 32-bit trampoline!");
+    }
+    let trampoline = PAGE_FRAME_ALLOCATOR
+        .lock()
+        .as_mut()
+        .expect("page frame allocator not initialised before processor startup!")
+        .allocate_frame_below(CONVENTIONAL_MEMORY_LIMIT)
+        .expect("no conventional memory frame free for the processor trampoline!");
+    let trampoline_address = trampoline.start_address().as_u64();
+    TRAMPOLINE_PAGE.store(trampoline_address, Ordering::SeqCst);
+    let trampoline_length = trampoline_offset(&raw const trampoline_end);
+    unsafe {
+        core::ptr::copy_nonoverlapping(
+            &raw const trampoline_start,
+            physical_to_virtual_address(trampoline_address) as *mut u8,
+            trampoline_length,
+        );
+        patch(
+            trampoline_address,
+            &raw const trampoline_gdt_base,
+            (trampoline_address + trampoline_offset(&raw const trampoline_gdt) as u64) as u32,
+        );
+        patch(
+            trampoline_address,
+            &raw const trampoline_long_mode_target,
+            (trampoline_address + trampoline_offset(&raw const trampoline_long_mode) as u64) as u32,
+        );
+        patch(trampoline_address, &raw const trampoline_cr3, cr3);
+        patch(
+            trampoline_address,
+            &raw const trampoline_entry,
+            application_entry as *const () as u64,
+        );
+    }
+    // paging is switched on from inside the trampoline, so its page has to be identity mapped for
+    // the instructions between enabling paging and jumping to long mode
+    let mut table = get_offset_table(unsafe { &mut *get_current_pml4() });
+    let page = Page::<Size4KiB>::containing_address(VirtAddr::new(trampoline_address));
+    {
+        let mut pfa_guard = PAGE_FRAME_ALLOCATOR.lock();
+        unsafe {
+            table.map_to(
+                page,
+                PhysFrame::containing_address(PhysAddr::new(trampoline_address)),
+                PageTableFlags::PRESENT | PageTableFlags::WRITABLE,
+                pfa_guard
+                    .as_mut()
+                    .expect("page frame allocator not initialised before processor startup!"),
+            )
+        }
+        .expect("failed to identity map processor trampoline!")
+        .flush();
+    }
+    println!(
+        "copied 0x{:x}-byte processor trampoline to address 0x{:x}...",
+        trampoline_length, trampoline_address
+    );
+    // processor 0 is the bootstrap processor, enabled application processors follow in madt
+    // order, numbered as they were counted when the per-processor data was sized
+    let mut processor = 0;
+    for (apic_id, state) in application_processors {
+        if state == ProcessorState::Disabled {
+            println!("skipping disabled processor with apic id {}...", apic_id);
+            continue;
+        }
+        processor += 1;
+        if !start_processor(trampoline_address, processor, apic_id) {
+            println!(
+                "processor no. {} with apic id {} did not come online!",
+                processor, apic_id
+            );
+        }
+    }
+    table
+        .unmap(page)
+        .expect("failed to unmap processor trampoline!")
+        .1
+        .flush();
+    TRAMPOLINE_UNMAPPED.store(true, Ordering::SeqCst);
+    while TRAMPOLINE_FLUSHES.load(Ordering::SeqCst) < ONLINE_PROCESSORS.load(Ordering::SeqCst) - 1 {
+        core::hint::spin_loop();
+    }
+    unsafe {
+        PAGE_FRAME_ALLOCATOR
+            .lock()
+            .as_mut()
+            .expect("page frame allocator not initialised before processor startup!")
+            .deallocate_frame(trampoline)
+    };
+    println!(
+        "{} of {} processor/s online...",
+        ONLINE_PROCESSORS.load(Ordering::SeqCst),
+        crate::acpi::PROCESSOR_COUNT.read().unwrap()
+    );
+}
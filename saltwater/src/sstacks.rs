@@ -4,7 +4,7 @@ use crate::{
 use alloc::vec::Vec;
 use spinning_top::RwSpinlock;
 use x86_64::{VirtAddr, structures::paging::{
-    FrameAllocator, Mapper, Page, Size4KiB
+    FrameAllocator, FrameDeallocator, Mapper, Page, Size4KiB
 }};
 static SYSCALL_STACK_BOOLMAP: RwSpinlock<Vec<bool>> = RwSpinlock::new(Vec::new());
 #[derive(Debug)]
@@ -25,7 +25,7 @@ impl SyscallStack {
                 boolmap.len() - 1
             });
         let mut table = get_offset_table(unsafe {&mut *get_current_pml4()});
-        for page in (0..(SYSCALL_STACK_SIZE / PAGE_SIZE)).map(|page_index| Page::<Size4KiB>::containing_address(VirtAddr::new(syscall_stack_address(stack_index) + page_index))) {
+        for page in (0..(SYSCALL_STACK_SIZE / PAGE_SIZE)).map(|page_index| Page::<Size4KiB>::containing_address(VirtAddr::new(syscall_stack_address(stack_index) + page_index * PAGE_SIZE))) {
             let mut pfa_lock = PAGE_FRAME_ALLOCATOR.lock();
             let pfa = pfa_lock.as_mut().expect("page frame allocator not initialised before allocation of syscall stack!");
             unsafe {table.map_to(page, pfa.allocate_frame()?, *KERNEL_PAGE_FLAGS, pfa)}.ok()?.flush();
@@ -39,8 +39,18 @@ impl SyscallStack {
         self.bottom() + SYSCALL_STACK_SIZE - size_of::<usize>() as u64
     }
 }
+// other processors may still hold global translations for the stack until tlb shootdowns exist,
+// which is only safe because a stack is never dropped while its thread can still run
 impl Drop for SyscallStack {
     fn drop(&mut self) {
-        todo!()
+        let mut table = get_offset_table(unsafe {&mut *get_current_pml4()});
+        let mut pfa_lock = PAGE_FRAME_ALLOCATOR.lock();
+        let pfa = pfa_lock.as_mut().expect("page frame allocator not initialised before deallocation of syscall stack!");
+        for page in (0..(SYSCALL_STACK_SIZE / PAGE_SIZE)).map(|page_index| Page::<Size4KiB>::containing_address(VirtAddr::new(self.bottom() + page_index * PAGE_SIZE))) {
+            let (frame, flush) = table.unmap(page).expect("failed to unmap page during deallocation of syscall stack!");
+            flush.flush();
+            unsafe { pfa.deallocate_frame(frame) };
+        }
+        SYSCALL_STACK_BOOLMAP.write()[self.0] = true;
     }
 }
\ No newline at end of file
@@ -0,0 +1,618 @@
+use crate::{
+    core::{ProcessorLocal, local},
+    mapping::{PAGE_SIZE, USER_LIMIT, USER_THREAD_AREAS},
+    namespace::{self, Location},
+    page::{self, USER_PAGE_FLAGS},
+    println,
+    proc::{
+        ABORTED_STATUS, Descriptor, Grant, Inheritance, Message, MessageStatus, Process, ProcessStatus,
+        REGISTER_RDI, REGISTER_RSP, ThreadState,
+    },
+    scheduler::{self, Killed, Source, current_thread, wait_any},
+    time, wire,
+};
+use alloc::{sync::Arc, vec, vec::Vec};
+use core::{
+    arch::global_asm,
+    mem::{self, offset_of},
+    sync::atomic::Ordering,
+};
+use x86_64::{
+    VirtAddr,
+    registers::{
+        model_specific::{Efer, EferFlags, LStar, SFMask, Star},
+        rflags::RFlags,
+    },
+    structures::paging::PageTableFlags,
+};
+// numbered in the order of tethys_lib's syscall enum
+const ABORT: u64 = 0;
+const MAP: u64 = 1;
+const SWITCH: u64 = 2;
+const LENGTH: u64 = 3;
+const SEND: u64 = 4;
+const QUERY: u64 = 5;
+const BLOCK: u64 = 6;
+const RESPOND: u64 = 7;
+const CHECK: u64 = 8;
+const RECEIVE: u64 = 9;
+const SPAWN: u64 = 10;
+const JOIN: u64 = 11;
+const DETACH: u64 = 12;
+const PRIORITISE: u64 = 13;
+const EXIT: u64 = 14;
+const LAUNCH: u64 = 15;
+const KILL: u64 = 16;
+const WAIT: u64 = 17;
+const SERVE: u64 = 18;
+const SELECT: u64 = 19;
+const CANCEL: u64 = 20;
+const REVOKE: u64 = 21;
+const AFFINITY: u64 = 22;
+// a wait set is copied into the kernel whole, so its size is bounded
+const SELECT_LIMIT: u64 = 64;
+const SELECT_RESPONSE: u64 = 0;
+const SELECT_REQUEST: u64 = 1;
+const SELECT_CANCELLATION: u64 = 2;
+const NO_TIMEOUT: u64 = u64::MAX;
+const NAMESPACE_SHARED: u64 = 0;
+const NAMESPACE_COPIED: u64 = 1;
+const NAMESPACE_FRESH: u64 = 2;
+const NO_DESCRIPTOR: u64 = u64::MAX;
+const PASS_LIMIT: u64 = 16;
+const NOTHING_PASSED: u64 = 0;
+// a message is copied into kernel memory as it is sent, so its size is capped rather than left
+// to whatever a process asks for
+const MAX_MESSAGE_PAGES: u64 = 0x100;
+// likewise an executable is read whole before it is loaded
+const MAX_EXECUTABLE_SIZE: usize = 0x400_0000;
+#[repr(C)]
+pub struct SyscallReturn {
+    pub success: u64,
+    pub result: u64,
+}
+// the number arrives in rax and arguments in rdi, rsi, rdx, r10 and r8, success is returned in rax
+// and the result in rdi. everything else the caller can see is preserved, so the kernel stack is
+// taken from the processor-local block and the caller-saved registers not used for returning are
+// saved around the call into rust
+global_asm!(
+    ".global syscall_entry",
+    "syscall_entry:",
+    "swapgs",
+    "mov gs:[{user_stack}], rsp",
+    "mov rsp, gs:[{syscall_stack_top}]",
+    "and rsp, -16",
+    "push qword ptr gs:[{user_stack}]",
+    "push rcx",
+    "push r11",
+    "push rsi",
+    "push rdx",
+    "push r10",
+    "push r8",
+    "push r9",
+    "mov r9, r8",
+    "mov r8, r10",
+    "mov rcx, rdx",
+    "mov rdx, rsi",
+    "mov rsi, rdi",
+    "mov rdi, rax",
+    "call {dispatch}",
+    "mov rdi, rdx",
+    "pop r9",
+    "pop r8",
+    "pop r10",
+    "pop rdx",
+    "pop rsi",
+    "pop r11",
+    "pop rcx",
+    "pop rsp",
+    "swapgs",
+    "sysretq",
+    user_stack = const offset_of!(ProcessorLocal, user_stack),
+    syscall_stack_top = const offset_of!(ProcessorLocal, syscall_stack_top),
+    dispatch = sym dispatch,
+);
+unsafe extern "C" {
+    fn syscall_entry();
+}
+extern "C" fn dispatch(
+    number: u64,
+    argument_0: u64,
+    argument_1: u64,
+    argument_2: u64,
+    argument_3: u64,
+    argument_4: u64,
+) -> SyscallReturn {
+    let result = match number {
+        ABORT => abort(),
+        MAP => map(argument_0, argument_1),
+        SWITCH => switch(),
+        LENGTH => length(argument_0, argument_1),
+        SEND => send(argument_0, argument_1, argument_2),
+        QUERY => query(argument_0),
+        BLOCK => block(argument_0, argument_1, argument_2, argument_3),
+        RESPOND => respond(argument_0, argument_1, argument_2, argument_3, argument_4),
+        CHECK => check(argument_0),
+        RECEIVE => receive(argument_0),
+        SPAWN => spawn(argument_0, argument_1, argument_2),
+        JOIN => join(argument_0),
+        DETACH => detach(argument_0),
+        PRIORITISE => prioritise(argument_0, argument_1),
+        EXIT => scheduler::exit_current(argument_0),
+        LAUNCH => launch(argument_0, argument_1, argument_2),
+        KILL => kill(argument_0, argument_1),
+        WAIT => wait(argument_0),
+        SERVE => serve(argument_0),
+        SELECT => select(argument_0, argument_1, argument_2),
+        CANCEL => cancel(argument_0),
+        REVOKE => revoke(argument_0),
+        AFFINITY => affinity(argument_0, argument_1),
+        _ => {
+            println!(
+                "processor no. {} received unimplemented syscall {}!",
+                local().processor,
+                number
+            );
+            Err(())
+        }
+    };
+    // a thread killed or suspended while it was in the kernel stops here rather than returning
+    // to its process
+    scheduler::checkpoint();
+    match result {
+        Ok(result) => SyscallReturn { success: 1, result },
+        Err(()) => SyscallReturn {
+            success: 0,
+            result: 0,
+        },
+    }
+}
+fn current_process() -> Result<Arc<Process>, ()> {
+    current_thread().read().process.upgrade().ok_or(())
+}
+// the address of a page range a process manages itself, thread areas are managed by the kernel
+// and out of reach
+fn user_range(index: u64, count: u64) -> Result<u64, ()> {
+    let address = index.checked_mul(PAGE_SIZE).ok_or(())?;
+    let end = count
+        .checked_mul(PAGE_SIZE)
+        .and_then(|length| length.checked_add(address))
+        .ok_or(())?;
+    if count == 0 || end > USER_THREAD_AREAS {
+        return Err(());
+    }
+    Ok(address)
+}
+// user memory is reached through the process's own table, which also maps the kernel, so
+// anything outside the lower half is refused before it is touched
+fn user_bytes(address: u64, length: u64) -> Result<(), ()> {
+    match address.checked_add(length) {
+        Some(end) if end <= USER_LIMIT => Ok(()),
+        _ => Err(()),
+    }
+}
+// kills the calling process, whose threads all exit at their next syscall, this one at the
+// checkpoint on its way out of this one
+fn abort() -> Result<u64, ()> {
+    Process::kill(&current_process()?, ABORTED_STATUS);
+    Ok(0)
+}
+// gives up the rest of the timeslice
+fn switch() -> Result<u64, ()> {
+    scheduler::yield_current().map_err(|Killed| ())?;
+    Ok(0)
+}
+// maps a wholly unmapped range or unmaps a wholly mapped one, anything in between is refused
+fn map(index: u64, count: u64) -> Result<u64, ()> {
+    let address = user_range(index, count)?;
+    let process = current_process()?;
+    let mut pages = process.pages.write();
+    let mapped = (0..count)
+        .filter(|page_index| pages.mapped(address + page_index * PAGE_SIZE))
+        .count() as u64;
+    if mapped == count {
+        pages.unmap(address, count);
+    } else if mapped == 0 {
+        if pages
+            .map(address, count, *USER_PAGE_FLAGS | PageTableFlags::NO_EXECUTE)
+            .is_none()
+        {
+            pages.unmap(address, count);
+            return Err(());
+        }
+    } else {
+        return Err(());
+    }
+    Ok(0)
+}
+// sysret faults in ring 0 on a non-canonical return address, so the entry point is checked here
+// rather than left for the thread to fault on
+fn spawn(entry: u64, stack: u64, argument: u64) -> Result<u64, ()> {
+    if entry >= USER_LIMIT || stack >= USER_LIMIT {
+        return Err(());
+    }
+    let process = current_process()?;
+    let thread = Process::add_thread(&process).ok_or(())?;
+    let id = {
+        let mut thread_write = thread.write();
+        thread_write.user_context.instruction_pointer = entry;
+        thread_write.user_context.registers[REGISTER_RSP] = stack;
+        thread_write.user_context.registers[REGISTER_RDI] = argument;
+        thread_write.id
+    };
+    scheduler::wake(thread);
+    Ok(id)
+}
+// a thread that has exited is only reaped once it is off its processor, as reaping frees the
+// kernel stack it exits on
+fn join(id: u64) -> Result<u64, ()> {
+    let process = current_process()?;
+    let target = process.thread(id).ok_or(())?;
+    loop {
+        let current = current_thread();
+        if Arc::ptr_eq(&target, &current) {
+            return Err(());
+        }
+        let mut target_write = target.write();
+        if let ThreadState::Exited(value) = target_write.state
+            && !target_write.on_processor
+        {
+            drop(target_write);
+            process.reap_thread(&target);
+            return Ok(value);
+        }
+        if target_write.detached
+            || target_write
+                .joiner
+                .as_ref()
+                .is_some_and(|joiner| !Arc::ptr_eq(joiner, &current))
+        {
+            return Err(());
+        }
+        let _ = target_write.joiner.insert(current.clone());
+        current.write().state = ThreadState::Blocked;
+        drop(target_write);
+        drop(current);
+        scheduler::leave_processor().map_err(|Killed| ())?;
+    }
+}
+fn detach(id: u64) -> Result<u64, ()> {
+    let process = current_process()?;
+    let target = process.thread(id).ok_or(())?;
+    let mut target_write = target.write();
+    if target_write.detached || target_write.joiner.is_some() {
+        return Err(());
+    }
+    if matches!(target_write.state, ThreadState::Exited(_)) && !target_write.on_processor {
+        drop(target_write);
+        process.reap_thread(&target);
+    } else {
+        target_write.detached = true;
+    }
+    Ok(0)
+}
+fn prioritise(id: u64, priority: u64) -> Result<u64, ()> {
+    let process = current_process()?;
+    process.thread(id).ok_or(())?.write().set_priority = priority;
+    process.propagate_priorities();
+    Ok(0)
+}
+// a mask that leaves the thread no online processor to run on is refused. the thread moves to an
+// allowed processor the next time it is switched away from one it is no longer allowed on
+fn affinity(id: u64, mask: u64) -> Result<u64, ()> {
+    if !scheduler::runnable_with(mask) {
+        return Err(());
+    }
+    current_process()?.thread(id).ok_or(())?.write().affinity = mask;
+    Ok(0)
+}
+// the child's namespace is made from this process's, and the workspace descriptor, if there is
+// one, is walked again in the child's namespace and handed to its first thread as its argument
+fn launch(descriptor: u64, inheritance: u64, workspace: u64) -> Result<u64, ()> {
+    let inheritance = match inheritance {
+        NAMESPACE_SHARED => Inheritance::Shared,
+        NAMESPACE_COPIED => Inheritance::Copied,
+        NAMESPACE_FRESH => Inheritance::Fresh,
+        _ => return Err(()),
+    };
+    let process = current_process()?;
+    let elf = process.descriptor(descriptor).ok_or(())?;
+    let bytes = namespace::read_all(&process.namespace.read().clone(), &elf, MAX_EXECUTABLE_SIZE).map_err(|_| ())?;
+    let namespace = process.inherit_namespace(inheritance);
+    let workspace = match workspace {
+        NO_DESCRIPTOR => None,
+        workspace => {
+            let workspace = process.descriptor(workspace).ok_or(())?;
+            namespace::usable(&process.namespace.read().clone(), &workspace).map_err(|_| ())?;
+            let location = Location::of(&workspace).map_err(|_| ())?;
+            let grant = Grant::derive(&workspace.grant);
+            Some(namespace::descriptor(&namespace, location, grant).map_err(|_| ())?)
+        }
+    };
+    let child = Process::spawn(&process, &bytes, namespace).map_err(|_| ())?;
+    let argument = workspace.map_or(NO_DESCRIPTOR, |workspace| child.add_descriptor(workspace));
+    child.threads.read()[0].write().user_context.registers[REGISTER_RDI] = argument;
+    child.start();
+    Ok(child.id)
+}
+fn kill(child: u64, status: u64) -> Result<u64, ()> {
+    Process::kill(&current_process()?.child(child).ok_or(())?, status);
+    Ok(0)
+}
+// the waiter is registered under the same lock the child's exit is published under, so the
+// wake cannot be missed between checking and blocking
+fn wait(child: u64) -> Result<u64, ()> {
+    let process = current_process()?;
+    let child = process.child(child).ok_or(())?;
+    loop {
+        {
+            let mut lifecycle_write = child.lifecycle.write();
+            if let ProcessStatus::Exited(_) = lifecycle_write.status {
+                drop(lifecycle_write);
+                return process.reap_child(&child).ok_or(());
+            }
+            let current = current_thread();
+            current.write().state = ThreadState::Blocked;
+            lifecycle_write.waiters.push(current);
+        }
+        scheduler::leave_processor().map_err(|Killed| ())?;
+    }
+}
+// a timeout of all ones never expires, and one of zero only polls
+fn deadline(timeout: u64) -> Option<u64> {
+    (timeout != NO_TIMEOUT).then(|| time::nanos_since_boot().saturating_add(timeout))
+}
+// blocks until the message behind a client's tag has been responded to, or none once the
+// deadline passes. a message its server dropped unanswered gives up the tag and fails
+fn await_response(
+    process: &Process,
+    tag: u64,
+    deadline: Option<u64>,
+) -> Result<Option<Arc<Message>>, ()> {
+    let message = process.sent(tag).ok_or(())?;
+    if wait_any(&[Source::Response(message.clone())], deadline).map_err(|Killed| ())?.is_none() {
+        return Ok(None);
+    }
+    match message.upgrade() {
+        Some(message) => Ok(Some(message)),
+        None => {
+            process.remove_sent(tag);
+            Err(())
+        }
+    }
+}
+// either the response to a message this process sent or a request it received as a server
+fn message(process: &Process, tag: u64, deadline: Option<u64>) -> Result<Option<Arc<Message>>, ()> {
+    match process.received(tag) {
+        Some(message) => Ok(Some(message)),
+        None => await_response(process, tag, deadline),
+    }
+}
+// no message is empty, so a length of zero means the timeout passed first
+fn length(tag: u64, timeout: u64) -> Result<u64, ()> {
+    let process = current_process()?;
+    let Some(message) = message(&process, tag, deadline(timeout))? else {
+        return Ok(0);
+    };
+    match &*message.status.read() {
+        MessageStatus::Sent(frames) | MessageStatus::Responded(frames) => Ok(frames.len() as u64),
+        MessageStatus::Received | MessageStatus::Cancelled => Err(()),
+    }
+}
+// the message is read out of the sender's pages and delivered through the multiplexer, which
+// answers anything it cannot deliver with an error response rather than failing the send
+// descriptors passed along with a message are listed as a count followed by a pair of words for
+// each, its index and the state it is passed with
+fn passed(process: &Process, address: u64) -> Result<Vec<Descriptor>, ()> {
+    if address == NOTHING_PASSED {
+        return Ok(Vec::new());
+    }
+    user_bytes(address, size_of::<u64>() as u64)?;
+    let mut count = [0; size_of::<u64>()];
+    process.pages.read().read(address, &mut count).ok_or(())?;
+    let count = u64::from_le_bytes(count);
+    if count > PASS_LIMIT {
+        return Err(());
+    }
+    let entries_address = address + size_of::<u64>() as u64;
+    let length = count * 2 * size_of::<u64>() as u64;
+    user_bytes(entries_address, length)?;
+    let mut entries = vec![0; length as usize];
+    process.pages.read().read(entries_address, &mut entries).ok_or(())?;
+    let entries = entries
+        .chunks_exact(2 * size_of::<u64>())
+        .map(|entry| {
+            let (index, state) = entry.split_at(size_of::<u64>());
+            (
+                u64::from_le_bytes(index.try_into().unwrap()),
+                wire::state_from_word(u64::from_le_bytes(state.try_into().unwrap())),
+            )
+        })
+        .collect::<Vec<_>>();
+    namespace::pass(process, &entries).map_err(|_| ())
+}
+fn send(index: u64, count: u64, passed_address: u64) -> Result<u64, ()> {
+    if count > MAX_MESSAGE_PAGES {
+        return Err(());
+    }
+    let address = user_range(index, count)?;
+    let process = current_process()?;
+    let mut bytes = vec![0; (count * PAGE_SIZE) as usize];
+    process.pages.read().read(address, &mut bytes).ok_or(())?;
+    let passed = passed(&process, passed_address)?;
+    let tag = process.next_tag.fetch_add(1, Ordering::Relaxed);
+    namespace::send(&process, tag, bytes, passed).ok_or(())?;
+    Ok(tag)
+}
+// a message whose server went away unanswered is reported as ready, blocking on it then fails.
+// a server querying a request it received learns whether its client has given up on it
+fn query(tag: u64) -> Result<u64, ()> {
+    let process = current_process()?;
+    if let Some(message) = process.received(tag) {
+        return Ok(message.cancelled() as u64);
+    }
+    let message = process.sent(tag).ok_or(())?;
+    Ok(message.upgrade().is_none_or(|message| message.responded()) as u64)
+}
+// maps a response into its client, giving up the client's tag, or a request into its server,
+// whose tag is kept until it is responded to. returns whether the message was mapped, as the
+// timeout may pass first. descriptors passed along with the message are added to the process,
+// with their indices written out as a count followed by the indices themselves, or dropped if
+// there is nowhere to write them
+fn block(tag: u64, index: u64, timeout: u64, passed_address: u64) -> Result<u64, ()> {
+    if passed_address != NOTHING_PASSED {
+        user_bytes(passed_address, (PASS_LIMIT + 1) * size_of::<u64>() as u64)?;
+    }
+    let process = current_process()?;
+    let received = process.received(tag).is_some();
+    let Some(message) = message(&process, tag, deadline(timeout))? else {
+        return Ok(0);
+    };
+    let mut status_write = message.status.write();
+    let (MessageStatus::Sent(frames) | MessageStatus::Responded(frames)) = &mut *status_write
+    else {
+        return Err(());
+    };
+    let address = user_range(index, frames.len() as u64)?;
+    process
+        .pages
+        .write()
+        .map_frames(address, frames, *USER_PAGE_FLAGS | PageTableFlags::NO_EXECUTE)
+        .ok_or(())?;
+    *status_write = MessageStatus::Received;
+    drop(status_write);
+    if !received {
+        process.remove_sent(tag);
+    }
+    let passed = mem::take(&mut *message.passed.write());
+    if passed_address != NOTHING_PASSED {
+        let mut indices = wire::word_bytes(passed.len() as u64);
+        for descriptor in passed {
+            indices.extend_from_slice(&process.add_descriptor(descriptor).to_le_bytes());
+        }
+        process
+            .pages
+            .write()
+            .write(passed_address, &indices)
+            .ok_or(())?;
+    }
+    Ok(1)
+}
+// gives up on a message this process sent, whether or not it has been responded to. its server
+// can still respond, and the response is thrown away
+fn cancel(tag: u64) -> Result<u64, ()> {
+    let process = current_process()?;
+    let message = process.sent(tag).ok_or(())?;
+    process.remove_sent(tag);
+    if let Some(message) = message.upgrade() {
+        message.cancel();
+    }
+    Ok(0)
+}
+// takes back every descriptor and binding derived from a descriptor so far, wherever they have
+// been passed, leaving the descriptor itself as it was
+fn revoke(descriptor: u64) -> Result<u64, ()> {
+    current_process()?.descriptor(descriptor).ok_or(())?.grant.revoke();
+    Ok(0)
+}
+fn respond(server: u64, tag: u64, index: u64, count: u64, passed_address: u64) -> Result<u64, ()> {
+    let address = user_range(index, count)?;
+    let process = current_process()?;
+    let user_server = process.user_server(server).ok_or(())?;
+    let message = process.received(tag).ok_or(())?;
+    let passed = passed(&process, passed_address)?;
+    let frames = process.pages.read().copy_frames(address, count).ok_or(())?;
+    if !user_server.read().finish(&message) {
+        page::release_frames(frames);
+        return Err(());
+    }
+    process.remove_received(tag);
+    // anything passed with the request and never taken goes, rather than back to the client
+    *message.passed.write() = passed;
+    Message::respond(message, frames);
+    Ok(0)
+}
+fn check(server: u64) -> Result<u64, ()> {
+    Ok(current_process()?.user_server(server).ok_or(())?.read().pending() as u64)
+}
+fn receive(server: u64) -> Result<u64, ()> {
+    let process = current_process()?;
+    let user_server = process.user_server(server).ok_or(())?;
+    loop {
+        // another thread serving the same server may take the request first
+        if let Some(message) = user_server.read().dequeue() {
+            return Ok(process.add_received(&message));
+        }
+        wait_any(&[Source::Request(user_server.clone())], None).map_err(|Killed| ())?;
+    }
+}
+// the index of the descriptor on the new server's root is written out, as only the server's tag
+// fits in the result
+fn serve(descriptor_address: u64) -> Result<u64, ()> {
+    user_bytes(descriptor_address, size_of::<u64>() as u64)?;
+    let process = current_process()?;
+    let (server, descriptor) = process.serve();
+    process
+        .pages
+        .write()
+        .write(descriptor_address, &descriptor.to_le_bytes())
+        .ok_or(())?;
+    Ok(server)
+}
+// waits on any of a set of response tags, server tags and received tags, given as pairs of words naming the
+// kind of each entry and its tag. returns the index of the first ready entry, or the length of
+// the set if the timeout in nanoseconds passes first
+fn select(set_address: u64, count: u64, timeout: u64) -> Result<u64, ()> {
+    if count > SELECT_LIMIT {
+        return Err(());
+    }
+    let length = count * 2 * size_of::<u64>() as u64;
+    user_bytes(set_address, length)?;
+    let process = current_process()?;
+    let mut set = vec![0; length as usize];
+    process.pages.read().read(set_address, &mut set).ok_or(())?;
+    let sources = set
+        .chunks_exact(2 * size_of::<u64>())
+        .map(|entry| {
+            let (kind, tag) = entry.split_at(size_of::<u64>());
+            let tag = u64::from_le_bytes(tag.try_into().unwrap());
+            match u64::from_le_bytes(kind.try_into().unwrap()) {
+                SELECT_RESPONSE => process.sent(tag).map(Source::Response).ok_or(()),
+                SELECT_REQUEST => process.user_server(tag).map(Source::Request).ok_or(()),
+                SELECT_CANCELLATION => process
+                    .received(tag)
+                    .map(|message| Source::Cancellation(Arc::downgrade(&message)))
+                    .ok_or(()),
+                _ => Err(()),
+            }
+        })
+        .collect::<Result<Vec<_>, ()>>()?;
+    drop(process);
+    Ok(wait_any(&sources, deadline(timeout)).map_err(|Killed| ())?.map_or(count, |index| index as u64))
+}
+// the syscall msrs are per-processor, so every processor enables them once its gdt is loaded
+pub fn enable() {
+    let selectors = local().data.read().gdt_selectors.1.clone();
+    unsafe {
+        Efer::update(|flags| flags.insert(EferFlags::SYSTEM_CALL_EXTENSIONS));
+        Star::write(
+            selectors.user_code,
+            selectors.user_data,
+            selectors.kernel_code,
+            selectors.kernel_data,
+        )
+        .expect("global descriptor table is not laid out for syscall/sysret!");
+    }
+    LStar::write(VirtAddr::new(syscall_entry as *const () as u64));
+    // interrupts stay off until the entry path has switched stacks
+    SFMask::write(
+        RFlags::INTERRUPT_FLAG
+            | RFlags::DIRECTION_FLAG
+            | RFlags::TRAP_FLAG
+            | RFlags::ALIGNMENT_CHECK,
+    );
+}
+pub fn initialise(_boot_info: &mut bootloader_api::BootInfo) {
+    enable();
+    println!("enabled syscall entry for bootstrap processor...");
+}
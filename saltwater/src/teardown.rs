@@ -0,0 +1,64 @@
+use core::sync::atomic::Ordering;
+use alloc::{sync::Arc, vec::Vec};
+use crate::{frame::PAGE_FRAME_ALLOCATOR, println, proc::{KILLED_STATUS, Namespace, Process, ProcessStatus, ThreadState}, smp::ONLINE_PROCESSORS, time};
+const TEARDOWN_CHECK_BYTES: &[u8] = if cfg!(debug_assertions) {
+    include_bytes!("../../target/x86_64-unknown-none/debug/teardown_check")
+} else {
+    include_bytes!("../../target/x86_64-unknown-none/release/teardown_check")
+};
+// generous enough for a slow or heavily loaded host, as no stage should take anywhere near it
+const TEARDOWN_TIMEOUT_NANOS: u64 = 30_000_000_000;
+fn free_frames() -> usize {
+    PAGE_FRAME_ALLOCATOR
+        .lock()
+        .as_ref()
+        .expect("page frame allocator not initialised before process teardown check!")
+        .free_frames()
+}
+// waits on the processors running the teardown check process
+fn await_teardown(condition: impl Fn() -> bool, stage: &str) {
+    let deadline = time::nanos_since_boot() + TEARDOWN_TIMEOUT_NANOS;
+    while !condition() {
+        if time::nanos_since_boot() >= deadline {
+            panic!("teardown check process did not {} in time!", stage);
+        }
+        core::hint::spin_loop();
+    }
+}
+// spawns, kills and reaps a child twice, the first round mapping any kernel page tables that
+// stay behind for later processes, and checks the second returns every frame it took. the child
+// is let run until it has served a server and blocked waiting on it, which needs another
+// processor to run it, as this one only enters its scheduler once initialisation is over
+pub fn check(root: &Arc<Process>) {
+    let running = ONLINE_PROCESSORS.load(Ordering::SeqCst) > 1;
+    if !running {
+        println!("checking process teardown without running the child, as no other processor is online...");
+    }
+    let mut leaked = 0;
+    for _ in 0..2 {
+        let before = free_frames();
+        let child = Process::spawn(root, TEARDOWN_CHECK_BYTES, Arc::new(Namespace::new()))
+            .unwrap_or_else(|error| panic!("failed to spawn teardown check process: {:?}!", error));
+        let threads = child.threads.read().iter().map(Arc::downgrade).collect::<Vec<_>>();
+        if running {
+            child.start();
+            await_teardown(
+                || !child.servers.read().is_empty() && threads[0].upgrade().is_some_and(|thread| thread.read().state == ThreadState::Blocked),
+                "serve and block",
+            );
+        }
+        Process::kill(&child, KILLED_STATUS);
+        await_teardown(|| child.lifecycle.read().status != ProcessStatus::Killed(KILLED_STATUS), "exit");
+        if root.reap_child(&child) != Some(KILLED_STATUS) {
+            panic!("killed teardown check process did not exit with the killed status!");
+        }
+        drop(child);
+        // the processor that ran the child's last thread may still hold it for a moment
+        await_teardown(|| threads.iter().all(|thread| thread.strong_count() == 0), "release its threads");
+        leaked = before as isize - free_frames() as isize;
+    }
+    if leaked != 0 {
+        panic!("process teardown leaked {} frame/s!", leaked);
+    }
+    println!("verified process teardown returns every frame...");
+}
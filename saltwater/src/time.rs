@@ -0,0 +1,216 @@
+use crate::{acpi::ACPI_PLATFORM, page::map_device_region, port, println};
+use core::arch::x86_64::{__cpuid, _rdtsc};
+use spinning_top::{RwSpinlock, Spinlock};
+const NANOS_PER_SECOND: u64 = 1_000_000_000;
+const FEMTOS_PER_NANO: u64 = 1_000_000;
+const HPET_CAPABILITIES_OFFSET: u64 = 0x000;
+const HPET_CONFIGURATION_OFFSET: u64 = 0x010;
+const HPET_COUNTER_OFFSET: u64 = 0x0f0;
+const HPET_ENABLE: u64 = 1 << 0;
+const HPET_COUNT_SIZE_CAP: u64 = 1 << 13;
+const HPET_REGION_SIZE: u64 = 0x400;
+const PIT_FREQUENCY: u64 = 1_193_182;
+const PIT_CHANNEL_0_PORT: u16 = 0x40;
+const PIT_COMMAND_PORT: u16 = 0x43;
+// channel 0, lobyte/hibyte access, mode 2 (rate generator), binary counting
+const PIT_RATE_GENERATOR_COMMAND: u8 = 0x34;
+const PIT_LATCH_COMMAND: u8 = 0x00;
+const CALIBRATION_NANOS: u64 = 10_000_000;
+const CPUID_MAX_EXTENDED_LEAF: u32 = 0x8000_0000;
+const CPUID_ADVANCED_POWER_LEAF: u32 = 0x8000_0007;
+const CPUID_INVARIANT_TSC: u32 = 1 << 8;
+const CPUID_TSC_LEAF: u32 = 0x15;
+#[derive(Clone, Copy)]
+enum ClockSource {
+    Tsc { frequency: u64, base: u64 },
+    Hpet { address: u64, period: u64, base: u64, wide: bool },
+    Pit,
+}
+// the pit counter wraps every ~55ms, so elapsed ticks are accumulated on every read and the
+// clock is only monotonic as long as something reads it at least that often
+struct PitAccumulator {
+    last_count: u16,
+    ticks: u64,
+}
+// a 32-bit hpet counter wraps every few minutes, and is accumulated the same way
+struct HpetAccumulator {
+    last_count: u32,
+    ticks: u64,
+}
+static CLOCK_SOURCE: RwSpinlock<Option<ClockSource>> = RwSpinlock::new(None);
+static PIT_ACCUMULATOR: Spinlock<PitAccumulator> = Spinlock::new(PitAccumulator {
+    last_count: 0,
+    ticks: 0,
+});
+static HPET_ACCUMULATOR: Spinlock<HpetAccumulator> = Spinlock::new(HpetAccumulator {
+    last_count: 0,
+    ticks: 0,
+});
+fn ticks_to_nanos(ticks: u64, frequency: u64) -> u64 {
+    (ticks as u128 * NANOS_PER_SECOND as u128 / frequency as u128) as u64
+}
+fn hpet_counter(address: u64, wide: bool) -> u64 {
+    if wide {
+        return unsafe { ((address + HPET_COUNTER_OFFSET) as *const u64).read_volatile() };
+    }
+    let mut accumulator = HPET_ACCUMULATOR.lock();
+    let count = unsafe { ((address + HPET_COUNTER_OFFSET) as *const u32).read_volatile() };
+    accumulator.ticks += count.wrapping_sub(accumulator.last_count) as u64;
+    accumulator.last_count = count;
+    accumulator.ticks
+}
+fn pit_ticks() -> u64 {
+    let mut accumulator = PIT_ACCUMULATOR.lock();
+    let count = unsafe {
+        port::write_u8(PIT_COMMAND_PORT, PIT_LATCH_COMMAND);
+        let low = port::read_u8(PIT_CHANNEL_0_PORT);
+        let high = port::read_u8(PIT_CHANNEL_0_PORT);
+        u16::from_le_bytes([low, high])
+    };
+    // the counter counts down, so the distance travelled since the last read is last - current
+    accumulator.ticks += accumulator.last_count.wrapping_sub(count) as u64;
+    accumulator.last_count = count;
+    accumulator.ticks
+}
+fn source_nanos(source: ClockSource) -> u64 {
+    match source {
+        ClockSource::Tsc { frequency, base } => {
+            ticks_to_nanos(unsafe { _rdtsc() }.wrapping_sub(base), frequency)
+        }
+        ClockSource::Hpet {
+            address,
+            period,
+            base,
+            wide,
+        } => {
+            (hpet_counter(address, wide).wrapping_sub(base) as u128 * period as u128
+                / FEMTOS_PER_NANO as u128) as u64
+        }
+        ClockSource::Pit => ticks_to_nanos(pit_ticks(), PIT_FREQUENCY),
+    }
+}
+pub fn nanos_since_boot() -> u64 {
+    source_nanos(
+        CLOCK_SOURCE
+            .read()
+            .expect("clock read before timekeeping initialisation!"),
+    )
+}
+pub fn stall_until(deadline: u64) {
+    while nanos_since_boot() < deadline {
+        core::hint::spin_loop();
+    }
+}
+pub fn stall(microseconds: u64) {
+    stall_until(nanos_since_boot().saturating_add(microseconds.saturating_mul(1_000)));
+}
+// firmware only asks to sleep during boot, when no thread is running on this processor and so
+// there is nothing to park, threads sleep through the scheduler's sleeping queue instead
+pub fn sleep(milliseconds: u64) {
+    stall(milliseconds.saturating_mul(1_000));
+}
+fn invariant_tsc() -> bool {
+    let max_extended_leaf = __cpuid(CPUID_MAX_EXTENDED_LEAF).eax;
+    (max_extended_leaf >= CPUID_ADVANCED_POWER_LEAF)
+        & (__cpuid(CPUID_ADVANCED_POWER_LEAF).edx & CPUID_INVARIANT_TSC != 0)
+}
+fn tsc_frequency(reference: ClockSource) -> u64 {
+    let max_leaf = __cpuid(0).eax;
+    if max_leaf >= CPUID_TSC_LEAF {
+        let tsc_leaf = __cpuid(CPUID_TSC_LEAF);
+        if (tsc_leaf.eax != 0) & (tsc_leaf.ebx != 0) & (tsc_leaf.ecx != 0) {
+            println!("read tsc frequency from cpuid...");
+            return tsc_leaf.ecx as u64 * tsc_leaf.ebx as u64 / tsc_leaf.eax as u64;
+        }
+    }
+    let start_nanos = source_nanos(reference);
+    let start_tsc = unsafe { _rdtsc() };
+    while source_nanos(reference) < start_nanos + CALIBRATION_NANOS {
+        core::hint::spin_loop();
+    }
+    let elapsed_tsc = unsafe { _rdtsc() } - start_tsc;
+    let elapsed_nanos = source_nanos(reference) - start_nanos;
+    println!("calibrated tsc against reference clock...");
+    (elapsed_tsc as u128 * NANOS_PER_SECOND as u128 / elapsed_nanos as u128) as u64
+}
+fn hpet_source() -> Option<ClockSource> {
+    let hpet_info = {
+        let acpi_platform_guard = ACPI_PLATFORM.read();
+        acpi::HpetInfo::new(
+            &acpi_platform_guard
+                .as_ref()
+                .expect("ACPI platform not initialised before timekeeping initialisation!")
+                .tables,
+        )
+        .ok()?
+    };
+    let address = map_device_region(hpet_info.base_address as u64, HPET_REGION_SIZE);
+    let capabilities =
+        unsafe { ((address + HPET_CAPABILITIES_OFFSET) as *const u64).read_volatile() };
+    let period = capabilities >> 32;
+    if period == 0 {
+        return None;
+    }
+    let wide = capabilities & HPET_COUNT_SIZE_CAP != 0;
+    unsafe {
+        let configuration = (address + HPET_CONFIGURATION_OFFSET) as *mut u64;
+        configuration.write_volatile(configuration.read_volatile() | HPET_ENABLE);
+    }
+    println!(
+        "found {}-bit hpet at address 0x{:x} with period {}fs...",
+        if wide { 64 } else { 32 },
+        hpet_info.base_address,
+        period
+    );
+    if !wide {
+        *HPET_ACCUMULATOR.lock() = HpetAccumulator {
+            last_count: unsafe { ((address + HPET_COUNTER_OFFSET) as *const u32).read_volatile() },
+            ticks: 0,
+        };
+    }
+    Some(ClockSource::Hpet {
+        address,
+        period,
+        base: hpet_counter(address, wide),
+        wide,
+    })
+}
+fn pit_source() -> ClockSource {
+    unsafe {
+        port::write_u8(PIT_COMMAND_PORT, PIT_RATE_GENERATOR_COMMAND);
+        // a reload value of zero is treated as 65536, the longest possible period
+        port::write_u8(PIT_CHANNEL_0_PORT, 0);
+        port::write_u8(PIT_CHANNEL_0_PORT, 0);
+    }
+    *PIT_ACCUMULATOR.lock() = PitAccumulator {
+        last_count: 0,
+        ticks: 0,
+    };
+    pit_ticks();
+    PIT_ACCUMULATOR.lock().ticks = 0;
+    println!("programmed pit channel 0 as a free-running counter...");
+    ClockSource::Pit
+}
+pub fn initialise(_boot_info: &mut bootloader_api::BootInfo) {
+    let reference = hpet_source().unwrap_or_else(pit_source);
+    let source = if invariant_tsc() {
+        let frequency = tsc_frequency(reference);
+        println!("found invariant tsc running at {}hz...", frequency);
+        ClockSource::Tsc {
+            frequency,
+            base: unsafe { _rdtsc() },
+        }
+    } else {
+        println!("tsc is not invariant, leaving it unused...");
+        reference
+    };
+    println!(
+        "selected {} as monotonic clock source...",
+        match source {
+            ClockSource::Tsc { .. } => "tsc",
+            ClockSource::Hpet { .. } => "hpet",
+            ClockSource::Pit => "pit",
+        }
+    );
+    let _ = CLOCK_SOURCE.write().insert(source);
+}
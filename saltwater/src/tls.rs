@@ -0,0 +1,60 @@
+use crate::{
+    loader::TlsTemplate,
+    mapping::PAGE_SIZE,
+    page::{ManagedPageTable, USER_PAGE_FLAGS},
+    proc::ExecutionContext,
+};
+use alloc::vec;
+use x86_64::{
+    VirtAddr,
+    registers::model_specific::{FsBase, KernelGsBase},
+    structures::paging::PageTableFlags,
+};
+// the thread control block only holds its own address for now, as x86_64 code finds the thread
+// pointer by loading fs:0
+const TCB_SIZE: u64 = 8;
+// lays out a thread's block at the start of its area in the x86_64 variant ii layout, with the
+// thread-local data directly below the thread pointer and the control block at it. the linker
+// addresses each variable at a fixed negative offset from the thread pointer, so the data has to
+// end exactly at the aligned thread pointer
+pub fn create_block(
+    pages: &mut ManagedPageTable,
+    template: Option<&TlsTemplate>,
+    area: u64,
+) -> Option<u64> {
+    let (data_size, alignment) = template.map_or((0, 1), |template| {
+        (
+            template.memory_size.next_multiple_of(template.alignment),
+            template.alignment,
+        )
+    });
+    let thread_pointer = (area + data_size).next_multiple_of(alignment.max(TCB_SIZE));
+    let end = thread_pointer + TCB_SIZE;
+    pages.map(
+        area,
+        (end - area).div_ceil(PAGE_SIZE),
+        *USER_PAGE_FLAGS | PageTableFlags::NO_EXECUTE,
+    )?;
+    if let Some(template) = template {
+        // everything past the initialised data is the zeroed remainder, which fresh frames
+        // already are
+        let mut initialised = vec![0; template.file_size as usize];
+        pages.read(template.address, &mut initialised)?;
+        pages.write(thread_pointer - data_size, &initialised)?;
+    }
+    pages.write(thread_pointer, &thread_pointer.to_le_bytes())?;
+    Some(thread_pointer)
+}
+// called whenever a thread is switched in. the kernel itself never uses fs, and only reaches the
+// user's gs base through swapgs, so both can be installed as soon as the thread is chosen
+pub fn load_bases(context: &ExecutionContext) {
+    FsBase::write(VirtAddr::new(context.fs_base));
+    KernelGsBase::write(VirtAddr::new(context.gs_base));
+}
+// called whenever a thread is switched out. user code can still change its bases by loading a
+// segment selector, so they are read back rather than assumed unchanged. cr4.fsgsbase is left
+// clear, so this is the only way user code can change them
+pub fn save_bases(context: &mut ExecutionContext) {
+    context.fs_base = FsBase::read().as_u64();
+    context.gs_base = KernelGsBase::read().as_u64();
+}
@@ -0,0 +1,101 @@
+use crate::file::{FileError, Seek};
+use alloc::{boxed::Box, vec, vec::Vec};
+// the numbering is shared with tethys_lib through tethys_wire, this module builds and parses
+// messages as the kernel keeps them, in vectors of its own
+pub use tethys_wire::{
+    NO_MESSAGE_LIMIT, Names, ORDER_AFTER, ORDER_BEFORE, ORDER_REPLACE, Protocol,
+    REQUEST_HEADER_SIZE, RESPONSE_HEADER_SIZE, SELECTORS, STATUS_OK, Selector, WIRE_MAJOR,
+    WIRE_VERSION, major, state_from_word, state_word,
+};
+pub struct Request<'a> {
+    pub descriptor: u64,
+    pub selector: Selector,
+    pub body: &'a [u8],
+}
+pub fn parse_request(bytes: &[u8]) -> Result<Request<'_>, FileError> {
+    let descriptor = word(bytes, 0)?;
+    let selector = Selector::from_word(word(bytes, 1)?).ok_or(FileError::Invalid)?;
+    let length = word(bytes, 2)? as usize;
+    let body = bytes
+        .get(REQUEST_HEADER_SIZE..)
+        .and_then(|body| body.get(..length))
+        .ok_or(FileError::Invalid)?;
+    Ok(Request {
+        descriptor,
+        selector,
+        body,
+    })
+}
+pub fn request(descriptor: u64, selector: Selector, body: &[u8]) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(REQUEST_HEADER_SIZE + body.len());
+    bytes.extend(descriptor.to_le_bytes());
+    bytes.extend((selector as u64).to_le_bytes());
+    bytes.extend((body.len() as u64).to_le_bytes());
+    bytes.extend(body);
+    bytes
+}
+// the descriptor a request was sent through is replaced by the server's own handle for the file
+// before it is forwarded
+pub fn set_request_descriptor(bytes: &mut [u8], descriptor: u64) {
+    bytes[..size_of::<u64>()].copy_from_slice(&descriptor.to_le_bytes());
+}
+pub fn response(result: Result<Vec<u8>, FileError>) -> Vec<u8> {
+    let (status, body) = match result {
+        Ok(body) => (STATUS_OK, body),
+        Err(error) => (error.code(), Vec::new()),
+    };
+    let mut bytes = Vec::with_capacity(RESPONSE_HEADER_SIZE + body.len());
+    bytes.extend(status.to_le_bytes());
+    bytes.extend((body.len() as u64).to_le_bytes());
+    bytes.extend(body);
+    bytes
+}
+// a server from before version messages answers one as it does any selector it does not know,
+// with unsupported and an empty body
+pub fn predates_version(bytes: &[u8]) -> bool {
+    word(bytes, 0) == Ok(FileError::Unsupported.code()) && word(bytes, 1) == Ok(0)
+}
+pub fn parse_response(bytes: &[u8]) -> Result<&[u8], FileError> {
+    let status = word(bytes, 0)?;
+    if status != STATUS_OK {
+        return Err(FileError::from_status(status));
+    }
+    let length = word(bytes, 1)? as usize;
+    bytes
+        .get(RESPONSE_HEADER_SIZE..)
+        .and_then(|body| body.get(..length))
+        .ok_or(FileError::Invalid)
+}
+pub fn word(bytes: &[u8], index: usize) -> Result<u64, FileError> {
+    tethys_wire::word(bytes, index).ok_or(FileError::Invalid)
+}
+pub fn word_bytes(value: u64) -> Vec<u8> {
+    value.to_le_bytes().to_vec()
+}
+// what the kernel learnt asking a server what it speaks. whether the server predates version
+// messages is only kept by the kernel, and never sent
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Negotiated {
+    pub protocol: Protocol,
+    pub predates_version: bool,
+}
+pub fn seek(selector: Selector, offset: u64) -> Option<Seek> {
+    match selector {
+        Selector::ListSeekForward | Selector::SeekForward => Some(Seek::Forward(offset)),
+        Selector::ListSeekBackward | Selector::SeekBackward => Some(Seek::Backward(offset)),
+        Selector::ListSeekStart | Selector::SeekStart => Some(Seek::Start(offset)),
+        Selector::ListSeekEnd | Selector::SeekEnd => Some(Seek::End(offset)),
+        _ => None,
+    }
+}
+// the kernel keeps names boxed, and encodes them into a vector sized to fit
+pub fn names(names: &[Box<[u8]>]) -> Vec<u8> {
+    let names = || names.iter().map(|name| &**name);
+    let mut bytes = vec![0; Names::length(names())];
+    let length = Names::encode(names(), &mut bytes).map_or(0, |names| names.as_bytes().len());
+    bytes.truncate(length);
+    bytes
+}
+pub fn parse_names(bytes: &[u8]) -> Result<Vec<Box<[u8]>>, FileError> {
+    Ok(Names::parse(bytes)?.iter().map(Box::from).collect())
+}
@@ -0,0 +1,22 @@
+#![no_std]
+#![no_main]
+use core::{arch::asm, panic::PanicInfo};
+use tethys_lib::{syscall_receive, syscall_serve, thread};
+#[panic_handler]
+fn panic(_info: &PanicInfo) -> ! {
+    loop {
+        unsafe {
+            asm!("hlt")
+        }
+    }
+}
+// the process the kernel's teardown check runs and kills. it serves a server and then waits on it
+// for good, so that it is killed while holding a server, a descriptor and a thread blocked in
+// the kernel
+#[unsafe(no_mangle)]
+pub extern "C" fn _start() -> ! {
+    if let Ok((server, _)) = unsafe { syscall_serve() } {
+        let _ = unsafe { syscall_receive(server) };
+    }
+    thread::exit(1)
+}
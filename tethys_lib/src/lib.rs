@@ -1,11 +1,20 @@
-#![no_std]
+#![cfg_attr(not(test), no_std)]
+#![feature(allow_internal_unstable)]
+#![allow(internal_features)]
+pub mod namespace;
+pub mod thread;
+pub mod wire;
 use core::arch::asm;
+use core::mem;
+use namespace::Order;
+use wire::{FileError, Names, Protocol, Request, Response, WIRE_VERSION};
 use core::slice;
+use core::time::Duration;
 use core::sync::atomic::{AtomicUsize, Ordering};
 const PAGE_SIZE: usize = 4096;
-const HEAP_PAGE_INDEX: usize = 0x0000_4000_0000;
 const BUFFER_PAGE_INDEX: usize = 0x0000_8000_0000;
-static NEXT_HEAP_PAGE: AtomicUsize = AtomicUsize::new(HEAP_PAGE_INDEX);
+// timeouts are in nanoseconds, with all ones waiting forever and zero only polling
+pub const NO_TIMEOUT: usize = usize::MAX;
 static NEXT_BUFFER_PAGE: AtomicUsize = AtomicUsize::new(BUFFER_PAGE_INDEX);
 #[repr(usize)]
 pub enum Syscall {
@@ -19,18 +28,34 @@ pub enum Syscall {
     Respond,
     Check,
     Receive,
+    Spawn,
+    Join,
+    Detach,
+    Prioritise,
+    Exit,
+    Launch,
+    Kill,
+    Wait,
+    Serve,
+    Select,
+    Cancel,
+    Revoke,
+    Affinity,
 }
-pub unsafe fn syscall(Syscall: Syscall, arguments: &[usize]) -> Result<usize, ()> {
-    let mut length_args: [usize; 5] = [0; 5];
-    for i in 0..5 {
-        length_args[i] = arguments.get(i).unwrap_or(&0).clone();
-    }
+// the kernel says no more about a failed syscall than that it failed
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SyscallError;
+/// # Safety
+/// the arguments reach the kernel as they are, so any addresses among them must be valid for
+/// what the syscall does with them
+pub unsafe fn syscall(number: Syscall, arguments: &[usize]) -> Result<usize, SyscallError> {
+    let length_args: [usize; 5] = core::array::from_fn(|i| arguments.get(i).copied().unwrap_or(0));
     let mut error: usize;
     let mut result: usize;
     unsafe {
         asm!(
             "syscall",
-            inlateout("rax") Syscall as u64 => error,
+            inlateout("rax") number as u64 => error,
             inlateout("rdi") length_args[0] => result,
             in("rsi") length_args[1],
             in("rdx") length_args[2],
@@ -41,35 +66,95 @@ pub unsafe fn syscall(Syscall: Syscall, arguments: &[usize]) -> Result<usize, ()
             options(nostack, preserves_flags)
         );
     }
-    if error == 0 { Err(()) } else { Ok(result) }
+    if error == 0 { Err(SyscallError) } else { Ok(result) }
 }
+// ends the whole process, not only the calling thread
+/// # Safety
+/// every thread of the process ends where it is, without unwinding, so nothing may rely on
+/// destructors running
 pub unsafe fn syscall_abort() -> ! {
     unsafe {
         let _ = syscall(Syscall::Abort, &[]);
     };
-    panic!("thread did not abort!")
+    panic!("process did not abort!")
 }
-pub unsafe fn syscall_map(page_index: usize, page_count: usize) -> Result<(), ()> {
+/// # Safety
+/// the range is unmapped if it is wholly mapped, so nothing may still refer to pages it takes away
+pub unsafe fn syscall_map(page_index: usize, page_count: usize) -> Result<(), SyscallError> {
     unsafe { syscall(Syscall::Map, &[page_index, page_count]) }.map(|_| ())
 }
-pub unsafe fn syscall_length(message_tag: usize) -> Result<usize, ()> {
-    unsafe { syscall(Syscall::Length, &[message_tag]) }
+/// # Safety
+/// asks nothing of the caller, it is only as unsafe as any raw syscall
+pub unsafe fn syscall_switch() -> Result<(), SyscallError> {
+    unsafe { syscall(Syscall::Switch, &[]) }.map(|_| ())
+}
+// a length of zero means the timeout passed before the message was ready
+/// # Safety
+/// asks nothing of the caller, the kernel checks the tag it is given
+pub unsafe fn syscall_length(
+    message_tag: usize,
+    timeout_nanos: usize,
+) -> Result<usize, SyscallError> {
+    unsafe { syscall(Syscall::Length, &[message_tag, timeout_nanos]) }
 }
-pub unsafe fn syscall_send(page_index: usize, page_count: usize) -> Result<usize, ()> {
-    unsafe { syscall(Syscall::Send, &[page_index, page_count]) }
+// descriptors are passed as a count followed by an index and a state word for each, and come
+// back from a block as a count followed by their new indices
+pub const PASS_LIMIT: usize = 16;
+pub const NOTHING_PASSED: usize = 0;
+/// # Safety
+/// the pages are read as they are when the message is sent, and the passed address, unless
+/// nothing is passed, must point to a count and that many index and state pairs
+pub unsafe fn syscall_send(
+    page_index: usize,
+    page_count: usize,
+    passed_address: usize,
+) -> Result<usize, SyscallError> {
+    unsafe { syscall(Syscall::Send, &[page_index, page_count, passed_address]) }
 }
-pub unsafe fn syscall_query(message_tag: usize) -> Result<bool, ()> {
+/// # Safety
+/// asks nothing of the caller, the kernel checks the tag it is given
+pub unsafe fn syscall_query(message_tag: usize) -> Result<bool, SyscallError> {
     unsafe { syscall(Syscall::Query, &[message_tag]) }.map(|x| x != 0)
 }
-pub unsafe fn syscall_block(message_tag: usize, page_index: usize) -> Result<bool, ()> {
-    unsafe { syscall(Syscall::Block, &[message_tag, page_index]) }.map(|x| x != 0)
+// false means the timeout passed before the message was ready, and the tag is still held
+/// # Safety
+/// the message is mapped at the page index, which must be reserved and unmapped, and the
+/// passed indices are written to the passed address unless nothing is passed
+pub unsafe fn syscall_block(
+    message_tag: usize,
+    page_index: usize,
+    timeout_nanos: usize,
+    passed_address: usize,
+) -> Result<bool, SyscallError> {
+    unsafe {
+        syscall(
+            Syscall::Block,
+            &[message_tag, page_index, timeout_nanos, passed_address],
+        )
+    }
+    .map(|x| x != 0)
+}
+/// # Safety
+/// asks nothing of the caller, the kernel checks the tag it is given
+pub unsafe fn syscall_cancel(message_tag: usize) -> Result<(), SyscallError> {
+    unsafe { syscall(Syscall::Cancel, &[message_tag]) }.map(|_| ())
 }
+// takes back everything derived from the descriptor, which itself is left as it was
+/// # Safety
+/// descriptors derived from this one, including in this process, stop working once revoked
+pub unsafe fn syscall_revoke(descriptor_index: usize) -> Result<(), SyscallError> {
+    unsafe { syscall(Syscall::Revoke, &[descriptor_index]) }.map(|_| ())
+}
+/// # Safety
+/// the pages are read as they are when the response is sent, and the passed address, unless
+/// nothing is passed, must point to a count and that many index and state pairs
 pub unsafe fn syscall_respond(
     server_tag: u64,
     message_tag: u64,
     page_index: usize,
     page_count: usize,
-) -> Result<(), ()> {
+    passed_address: usize,
+) -> Result<(), SyscallError> {
     unsafe {
         syscall(
             Syscall::Respond,
@@ -78,29 +163,182 @@ pub unsafe fn syscall_respond(
                 message_tag as usize,
                 page_index,
                 page_count,
+                passed_address,
             ],
         )
         .map(|_| ())
     }
 }
-pub unsafe fn syscall_check(server_tag: usize) -> Result<bool, ()> {
+/// # Safety
+/// asks nothing of the caller, the kernel checks the tag it is given
+pub unsafe fn syscall_check(server_tag: usize) -> Result<bool, SyscallError> {
     unsafe { syscall(Syscall::Check, &[server_tag]) }.map(|x| x != 0)
 }
-pub unsafe fn syscall_receive(server_tag: usize) -> Result<usize, ()> {
+/// # Safety
+/// asks nothing of the caller, the kernel checks the tag it is given
+pub unsafe fn syscall_receive(server_tag: usize) -> Result<usize, SyscallError> {
     unsafe { syscall(Syscall::Receive, &[server_tag]) }
 }
+/// # Safety
+/// asks nothing of the caller, the descriptor index is written to a local of its own
+pub unsafe fn syscall_serve() -> Result<(usize, usize), SyscallError> {
+    let mut descriptor_index = 0usize;
+    unsafe { syscall(Syscall::Serve, &[&raw mut descriptor_index as usize]) }
+        .map(|server_tag| (server_tag, descriptor_index))
+}
+// laid out as the pair of words the kernel reads for each entry of a wait set
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct WaitEntry {
+    kind: usize,
+    tag: usize,
+}
+impl WaitEntry {
+    pub const fn response(message_tag: usize) -> WaitEntry {
+        WaitEntry {
+            kind: 0,
+            tag: message_tag,
+        }
+    }
+    pub const fn request(server_tag: usize) -> WaitEntry {
+        WaitEntry {
+            kind: 1,
+            tag: server_tag,
+        }
+    }
+    // ready once the client of a received request has given up on it
+    pub const fn cancellation(message_tag: usize) -> WaitEntry {
+        WaitEntry {
+            kind: 2,
+            tag: message_tag,
+        }
+    }
+}
+/// # Safety
+/// asks nothing of the caller, the entries are borrowed for as long as the syscall runs
+pub unsafe fn syscall_select(
+    entries: &[WaitEntry],
+    timeout_nanos: usize,
+) -> Result<usize, SyscallError> {
+    unsafe {
+        syscall(
+            Syscall::Select,
+            &[entries.as_ptr() as usize, entries.len(), timeout_nanos],
+        )
+    }
+}
+// blocks until a response or request in the set is ready and returns its index, or none once
+// the timeout has passed. no timeout waits forever and a zero timeout only polls
+pub fn select(
+    entries: &[WaitEntry],
+    timeout: Option<Duration>,
+) -> Result<Option<usize>, SyscallError> {
+    let timeout_nanos = timeout.map_or(NO_TIMEOUT, |timeout| {
+        timeout.as_nanos().min(usize::MAX as u128 - 1) as usize
+    });
+    let index = unsafe { syscall_select(entries, timeout_nanos) }?;
+    Ok((index < entries.len()).then_some(index))
+}
+// the kernel points fs at each thread's control block, whose first word is its own address
+pub fn thread_pointer() -> *const u8 {
+    let pointer: *const u8;
+    unsafe { asm!("mov {}, fs:[0]", out(reg) pointer, options(nostack, preserves_flags, readonly)) };
+    pointer
+}
+/// # Safety
+/// the new thread starts at entry with the given stack, both of which must stay valid for as
+/// long as it runs
+pub unsafe fn syscall_spawn(
+    entry: usize,
+    stack: usize,
+    argument: usize,
+) -> Result<usize, SyscallError> {
+    unsafe { syscall(Syscall::Spawn, &[entry, stack, argument]) }
+}
+/// # Safety
+/// asks nothing of the caller, the kernel checks the id it is given
+pub unsafe fn syscall_join(thread_id: usize) -> Result<usize, SyscallError> {
+    unsafe { syscall(Syscall::Join, &[thread_id]) }
+}
+/// # Safety
+/// the thread's stack must stay mapped for as long as it may still run
+pub unsafe fn syscall_detach(thread_id: usize) -> Result<(), SyscallError> {
+    unsafe { syscall(Syscall::Detach, &[thread_id]) }.map(|_| ())
+}
+/// # Safety
+/// asks nothing of the caller, the kernel checks the id it is given
+pub unsafe fn syscall_prioritise(thread_id: usize, priority: usize) -> Result<(), SyscallError> {
+    unsafe { syscall(Syscall::Prioritise, &[thread_id, priority]) }.map(|_| ())
+}
+// bit n of the mask allows processor n, and all ones allows every processor, including any past
+// the first 64. a mask allowing no online processor is refused
+pub const ANY_PROCESSOR: usize = usize::MAX;
+/// # Safety
+/// asks nothing of the caller, the kernel checks the id and mask it is given
+pub unsafe fn syscall_affinity(thread_id: usize, mask: usize) -> Result<(), SyscallError> {
+    unsafe { syscall(Syscall::Affinity, &[thread_id, mask]) }.map(|_| ())
+}
+/// # Safety
+/// the calling thread ends without unwinding, so nothing it borrowed out may outlive it
+pub unsafe fn syscall_exit(value: usize) -> ! {
+    unsafe {
+        let _ = syscall(Syscall::Exit, &[value]);
+    };
+    panic!("thread did not exit!")
+}
+// how a launched child's namespace is made from this process's
+#[repr(usize)]
+#[derive(Clone, Copy)]
+pub enum Inheritance {
+    Shared,
+    Copied,
+    Fresh,
+}
+// the workspace descriptor a child is launched with when it is given none
+pub const NO_DESCRIPTOR: usize = usize::MAX;
+/// # Safety
+/// asks nothing of the caller, the kernel checks the descriptors it is given
+pub unsafe fn syscall_launch(
+    descriptor_index: usize,
+    inheritance: Inheritance,
+    workspace_descriptor_index: usize,
+) -> Result<usize, SyscallError> {
+    unsafe {
+        syscall(
+            Syscall::Launch,
+            &[descriptor_index, inheritance as usize, workspace_descriptor_index],
+        )
+    }
+}
+/// # Safety
+/// asks nothing of the caller, the kernel checks the id it is given
+pub unsafe fn syscall_kill(child_id: usize, status: usize) -> Result<(), SyscallError> {
+    unsafe { syscall(Syscall::Kill, &[child_id, status]) }.map(|_| ())
+}
+/// # Safety
+/// asks nothing of the caller, the kernel checks the id it is given
+pub unsafe fn syscall_wait(child_id: usize) -> Result<usize, SyscallError> {
+    unsafe { syscall(Syscall::Wait, &[child_id]) }
+}
 pub struct Buffer {
     page_index: usize,
     page_length: usize,
 }
 impl Buffer {
-    unsafe fn syscall_map(self: &mut Self) -> Result<(), ()> {
+    // claims pages without mapping them, for a message to be mapped into
+    fn reserve(page_length: usize) -> Buffer {
+        Buffer {
+            page_index: NEXT_BUFFER_PAGE.fetch_add(page_length, Ordering::Relaxed),
+            page_length,
+        }
+    }
+    unsafe fn syscall_map(&mut self) -> Result<(), SyscallError> {
         unsafe { syscall_map(self.page_index, self.page_length) }
     }
     pub fn new(page_length: usize) -> Buffer {
         let mut buffer = Buffer {
             page_index: NEXT_BUFFER_PAGE.fetch_add(page_length, Ordering::Relaxed),
-            page_length: page_length,
+            page_length,
         };
         unsafe { buffer.syscall_map() }.unwrap_or_else(|_| {
             panic!(
@@ -110,10 +348,13 @@ impl Buffer {
         });
         buffer
     }
-    pub fn len(self: &Self) -> usize {
+    pub fn len(&self) -> usize {
         self.page_length * PAGE_SIZE
     }
-    pub fn as_slice(self: &Self) -> &[u8] {
+    pub fn is_empty(&self) -> bool {
+        self.page_length == 0
+    }
+    pub fn as_slice(&self) -> &[u8] {
         unsafe {
             slice::from_raw_parts(
                 (self.page_index * PAGE_SIZE) as *const u8,
@@ -121,7 +362,7 @@ impl Buffer {
             )
         }
     }
-    pub fn as_mut_slice(self: &mut Self) -> &mut [u8] {
+    pub fn as_mut_slice(&mut self) -> &mut [u8] {
         unsafe {
             slice::from_raw_parts_mut(
                 (self.page_index * PAGE_SIZE) as *mut u8,
@@ -130,7 +371,7 @@ impl Buffer {
         }
     }
     pub fn reconfigure(
-        mut self: Self,
+        mut self,
         first_page_length: usize,
         second_page_length: usize,
     ) -> (Buffer, Buffer) {
@@ -159,7 +400,6 @@ impl Buffer {
                 });
                 self = Buffer::new(first_page_length + second_page_length);
             })
-        } else {
         }
         (
             Buffer {
@@ -174,7 +414,7 @@ impl Buffer {
     }
 }
 impl Drop for Buffer {
-    fn drop(self: &mut Self) {
+    fn drop(&mut self) {
         unsafe {
             self.syscall_map().unwrap_or_else(|_| {
                 panic!(
@@ -185,138 +425,216 @@ impl Drop for Buffer {
         }
     }
 }
-#[repr(usize)]
-enum MsgSelector {
-    ReadState,
-    WriteState,
-    Drop,
-    Walk,
-    List,
-    ListPeek,
-    ListSeekForward,
-    ListSeekBackward,
-    ListSeekStart,
-    ListSeekEnd,
-    ListTell,
-    Make,
-    Remove,
-    Rename,
-    Read,
-    Peek,
-    Insert,
-    Overwrite,
-    Truncate,
-    SeekForward,
-    SeekBackward,
-    SeekStart,
-    SeekEnd,
-    Bind,
-    Unmap,
-}
-pub struct State {
-    walk: bool,
-    rename: bool,
-    make: bool,
-    remove: bool,
-    read: bool,
-    insert: bool,
-    overwrite: bool,
-    truncate: bool,
-    seek_forward: bool,
-    seek_backward: bool,
-    seek_start: bool,
-    seek_end: bool,
-    tell: bool,
-    lock: bool,
-}
-macro_rules! state_chain {
-    ($field:ident) => {
-        const fn $field(mut self: Self, value: bool) -> State {
-            self.$field = value;
-            self
-        }
-    };
+// numbered as they are on the wire
+pub use tethys_wire::Selector as MsgSelector;
+// shared with the kernel through tethys_wire, along with its encoding as a word
+pub use tethys_wire::State;
+pub struct Descriptor {
+    index: usize,
 }
-impl State {
-    const fn new() -> State {
-        State {
-            walk: false,
-            rename: false,
-            make: false,
-            remove: false,
-            read: false,
-            insert: false,
-            overwrite: false,
-            truncate: false,
-            seek_forward: false,
-            seek_backward: false,
-            seek_start: false,
-            seek_end: false,
-            tell: false,
-            lock: false,
+impl Descriptor {
+    // takes over a descriptor index the kernel handed this process, such as its workspace's
+    pub fn from_index(index: usize) -> Descriptor {
+        Descriptor { index }
+    }
+    // sends a request through the descriptor and blocks until it is answered, with the response
+    // mapped into a buffer of its own
+    fn transact(&self, request: &Request) -> Result<Buffer, FileError> {
+        let mut message = Buffer::new(request.length().div_ceil(PAGE_SIZE));
+        request.encode(self.index as u64, message.as_mut_slice())?;
+        let tag = unsafe { syscall_send(message.page_index, message.page_length, NOTHING_PASSED) }
+            .map_err(|_| FileError::Invalid)?;
+        drop(message);
+        let page_length =
+            unsafe { syscall_length(tag, NO_TIMEOUT) }.map_err(|_| FileError::Invalid)?;
+        let response = Buffer::reserve(page_length);
+        if unsafe { syscall_block(tag, response.page_index, NO_TIMEOUT, NOTHING_PASSED) }.is_err() {
+            // nothing was mapped, so there is nothing for the buffer to unmap
+            mem::forget(response);
+            return Err(FileError::Invalid);
+        }
+        Ok(response)
+    }
+    // the helpers below check a response carries what its selector promises
+    fn empty(&self, request: &Request) -> Result<(), FileError> {
+        let response = self.transact(request)?;
+        Response::decode(request.selector(), response.as_slice()).map(|_| ())
+    }
+    fn word(&self, request: &Request) -> Result<u64, FileError> {
+        let response = self.transact(request)?;
+        match Response::decode(request.selector(), response.as_slice())? {
+            Response::Word(value) => Ok(value),
+            _ => Err(FileError::Invalid),
+        }
+    }
+    fn child(&self, request: &Request) -> Result<Descriptor, FileError> {
+        let response = self.transact(request)?;
+        match Response::decode(request.selector(), response.as_slice())? {
+            Response::Descriptor(index) => Ok(Descriptor::from_index(index as usize)),
+            _ => Err(FileError::Invalid),
+        }
+    }
+    // keeps the response mapped, so what it carries can be borrowed without copying
+    fn body(&self, request: &Request) -> Result<Body, FileError> {
+        let buffer = self.transact(request)?;
+        Response::decode(request.selector(), buffer.as_slice())?;
+        Ok(Body {
+            selector: request.selector(),
+            buffer,
+        })
+    }
+    // walking nowhere gives a second descriptor on the same file, which is dropped on its own
+    pub fn try_clone(&self) -> Result<Descriptor, FileError> {
+        self.child(&Request::Walk(&[]))
+    }
+    pub fn walk(&mut self, path: &str) -> Result<Descriptor, FileError> {
+        self.child(&Request::Walk(path.as_bytes()))
+    }
+    pub fn read_state(&mut self) -> Result<State, FileError> {
+        let response = self.transact(&Request::ReadState)?;
+        match Response::decode(MsgSelector::ReadState, response.as_slice())? {
+            Response::State(state) => Ok(state),
+            _ => Err(FileError::Invalid),
+        }
+    }
+    pub fn write_state(&mut self, state: State) -> Result<(), FileError> {
+        self.empty(&Request::WriteState(state))
+    }
+    pub fn list(&mut self, count: usize) -> Result<Body, FileError> {
+        self.body(&Request::List(count as u64))
+    }
+    pub fn list_peek(&mut self, count: usize) -> Result<Body, FileError> {
+        self.body(&Request::ListPeek(count as u64))
+    }
+    pub fn list_seek_forward(&mut self, offset: u64) -> Result<u64, FileError> {
+        self.word(&Request::ListSeekForward(offset))
+    }
+    pub fn list_seek_backward(&mut self, offset: u64) -> Result<u64, FileError> {
+        self.word(&Request::ListSeekBackward(offset))
+    }
+    pub fn list_seek_start(&mut self, offset: u64) -> Result<u64, FileError> {
+        self.word(&Request::ListSeekStart(offset))
+    }
+    pub fn list_seek_end(&mut self, offset: u64) -> Result<u64, FileError> {
+        self.word(&Request::ListSeekEnd(offset))
+    }
+    pub fn list_tell(&mut self) -> Result<u64, FileError> {
+        self.word(&Request::ListTell)
+    }
+    pub fn make(&mut self, child_state: State, child_name: &str) -> Result<Descriptor, FileError> {
+        self.child(&Request::Make(child_state, child_name.as_bytes()))
+    }
+    pub fn remove(&mut self, child_name: &str) -> Result<(), FileError> {
+        self.empty(&Request::Remove(child_name.as_bytes()))
+    }
+    pub fn rename(&mut self, new_name: &str) -> Result<(), FileError> {
+        self.empty(&Request::Rename(new_name.as_bytes()))
+    }
+    pub fn read(&mut self, length: usize) -> Result<Body, FileError> {
+        self.body(&Request::Read(length as u64))
+    }
+    pub fn peek(&mut self, length: usize) -> Result<Body, FileError> {
+        self.body(&Request::Peek(length as u64))
+    }
+    // returns how many bytes the server took
+    pub fn insert(&mut self, content: &[u8]) -> Result<usize, FileError> {
+        self.word(&Request::Insert(content)).map(|length| length as usize)
+    }
+    pub fn overwrite(&mut self, content: &[u8]) -> Result<usize, FileError> {
+        self.word(&Request::Overwrite(content)).map(|length| length as usize)
+    }
+    pub fn truncate(&mut self, length: u64) -> Result<u64, FileError> {
+        self.word(&Request::Truncate(length))
+    }
+    // seeks return the position the head ends up at
+    pub fn seek_forward(&mut self, offset: u64) -> Result<u64, FileError> {
+        self.word(&Request::SeekForward(offset))
+    }
+    pub fn seek_backward(&mut self, offset: u64) -> Result<u64, FileError> {
+        self.word(&Request::SeekBackward(offset))
+    }
+    pub fn seek_start(&mut self, offset: u64) -> Result<u64, FileError> {
+        self.word(&Request::SeekStart(offset))
+    }
+    pub fn seek_end(&mut self, offset: u64) -> Result<u64, FileError> {
+        self.word(&Request::SeekEnd(offset))
+    }
+    pub fn tell(&mut self) -> Result<u64, FileError> {
+        self.word(&Request::Tell)
+    }
+    pub fn lock(&mut self) -> Result<(), FileError> {
+        self.empty(&Request::Lock)
+    }
+    // makes from available at name under this descriptor, as the kernel's namespace multiplexer
+    // answers bind messages itself
+    pub fn bind(
+        &mut self,
+        from: &Descriptor,
+        state: State,
+        order: Order,
+        name: &str,
+    ) -> Result<(), FileError> {
+        self.empty(&Request::Bind {
+            from: from.index as u64,
+            state,
+            order,
+            name: name.as_bytes(),
+        })
+    }
+    // takes away whatever from was bound onto name, leaving anything else bound there
+    pub fn unmap(&mut self, from: &Descriptor, name: &str) -> Result<(), FileError> {
+        self.empty(&Request::Unmap {
+            from: from.index as u64,
+            name: name.as_bytes(),
+        })
+    }
+    // asks the server which protocol it speaks, offering the version this library speaks
+    pub fn version(&mut self) -> Result<Protocol, FileError> {
+        let response = self.transact(&Request::Version(WIRE_VERSION))?;
+        match Response::decode(MsgSelector::Version, response.as_slice())? {
+            Response::Protocol(protocol) => Ok(protocol),
+            _ => Err(FileError::Invalid),
         }
     }
-    state_chain!(walk);
-    state_chain!(rename);
-    state_chain!(make);
-    state_chain!(remove);
-    state_chain!(read);
-    state_chain!(insert);
-    state_chain!(overwrite);
-    state_chain!(truncate);
-    state_chain!(seek_forward);
-    state_chain!(seek_backward);
-    state_chain!(seek_start);
-    state_chain!(seek_end);
 }
-pub struct Descriptor {
-    index: usize,
-}/*
-impl Descriptor {
-    pub fn read_state(self: &mut Self) -> Result<State, ()> {
-        let mut buffer = Buffer::new(1);
-        buffer.as_mut_slice()[8..16].copy_from_slice(&(MsgSelector::ReadState as usize).to_le_bytes());
-        let mut state = State::new()
-    }
-    pub fn write_state(self: &mut Self, state: &State) -> Result<(), ()> {}
-    pub fn walk(self: &mut Self, path: &str) -> Result<Descriptor, ()> {}
-    pub fn list(self: &mut Self, count: usize) -> Result<&mut [&mut str], ()> {}
-    pub fn list_peek(self: &mut Self, count: usize) -> Result<&mut [&mut str], ()> {}
-    pub fn list_seek_forward(self: &mut Self, offset: usize) -> Result<(), ()> {}
-    pub fn list_seek_backward(self: &mut Self, offset: usize) -> Result<(), ()> {}
-    pub fn list_seek_start(self: &mut Self, offset: usize) -> Result<(), ()> {}
-    pub fn list_seek_end(self: &mut Self, offset: usize) -> Result<(), ()> {}
-    pub fn list_tell(self: &mut Self) -> usize {}
-    pub fn make(self: &mut Self, child_state: &State, child_name: &str) -> Result<Descriptor, ()> {}
-    pub fn remove(self: &mut Self, child_name: &str) -> Result<(), ()> {}
-    pub fn rename(self: &mut Self, new_name: &str) -> Result<(), ()> {}
-    pub fn read(self: &mut Self, length: usize) -> Result<Buffer, ()> {}
-    pub fn peek(self: &mut Self, length: usize) -> Result<Buffer, ()> {}
-    pub fn insert(self: &mut Self, content: Buffer, length: usize) -> Result<usize, ()> {}
-    pub fn overwrite(self: &mut Self, content: Buffer, length: usize) -> Result<usize, ()> {}
-    pub fn truncate(self: &mut Self, length: usize) -> Result<usize, ()> {}
-    pub fn seek_forward(self: &mut Self, offset: usize) -> Result<usize, ()> {}
-    pub fn seek_backward(self: &mut Self, offset: usize) -> Result<usize, ()> {}
-    pub fn seek_start(self: &mut Self, offset: usize) -> Result<usize, ()> {}
-    pub fn seek_end(self: &mut Self, offset: usize) -> Result<usize, ()> {}
-}*/
+// the kernel answers a drop itself, and nothing can be done here if it fails
 impl Drop for Descriptor {
     fn drop(&mut self) {
-        todo!()
+        let _ = self.transact(&Request::Drop);
     }
 }
-impl Clone for Descriptor {
-    fn clone(&self) -> Self {
-        Self {
-            index: self.index.clone(),
+// a response left where it was mapped, for reads and listings that carry more than a word
+pub struct Body {
+    selector: MsgSelector,
+    buffer: Buffer,
+}
+impl Body {
+    // the bytes a read or peek carries
+    pub fn as_slice(&self) -> &[u8] {
+        match Response::decode(self.selector, self.buffer.as_slice()) {
+            Ok(Response::Bytes(bytes)) => bytes,
+            _ => &[],
+        }
+    }
+    // the names a list or list peek carries
+    pub fn names(&self) -> Names<'_> {
+        match Response::decode(self.selector, self.buffer.as_slice()) {
+            Ok(Response::Names(names)) => names,
+            _ => Names::empty(),
         }
     }
 }
+// the words every request and response starts with, laid out as they are on the wire
 #[repr(C, packed)]
 pub struct MessageHeader {
+    pub descriptor: u64,
+    pub selector: u64,
+    pub length: u64,
+}
+#[repr(C, packed)]
+pub struct ResponseHeader {
+    pub status: u64,
     pub length: u64,
-    pub tag: u64,
-    pub offset: u64,
 }
 #[macro_export]
 macro_rules! entry {
@@ -331,3 +649,17 @@ macro_rules! entry {
         }
     };
 }
+// declares statics with one instance per thread, placed in the thread-local block the kernel
+// builds for every new thread from the program's tls segment. only statics with constant
+// initialisers and no destructors are supported, as nothing runs when a thread starts or exits
+#[macro_export]
+#[allow_internal_unstable(thread_local)]
+macro_rules! thread_local {
+    ($($(#[$attribute:meta])* $visibility:vis static $name:ident: $type:ty = $value:expr;)*) => {
+        $(
+            $(#[$attribute])*
+            #[thread_local]
+            $visibility static $name: $type = $value;
+        )*
+    };
+}
@@ -0,0 +1,244 @@
+use crate::State;
+use core::{fmt, iter::Enumerate, str::Lines};
+// a namespace file holds one directive per line, with everything after a '#' ignored:
+//     bind FROM TO            binds FROM onto TO, replacing whatever was there
+//     union before FROM TO    binds FROM onto TO, searched before what is already there
+//     union after FROM TO     binds FROM onto TO, searched after what is already there
+//     mask STATE...           masks every binding after it to the named state bits, or all
+// paths are absolute, from the root of the descriptor the bindings are made through
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Order {
+    Replace,
+    Before,
+    After,
+}
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Binding<'a> {
+    pub from: &'a str,
+    pub to: &'a str,
+    pub order: Order,
+    pub mask: State,
+}
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ParseErrorKind<'a> {
+    UnknownDirective(&'a str),
+    UnknownOrder(&'a str),
+    UnknownState(&'a str),
+    MissingArgument(&'static str),
+    ExtraArgument(&'a str),
+    RelativePath(&'a str),
+}
+// lines are counted from one, as an editor shows them
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ParseError<'a> {
+    pub line: usize,
+    pub kind: ParseErrorKind<'a>,
+}
+impl fmt::Display for ParseError<'_> {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        write!(formatter, "line {}: ", self.line)?;
+        match self.kind {
+            ParseErrorKind::UnknownDirective(directive) => write!(
+                formatter,
+                "unknown directive \"{}\", expected bind, union or mask",
+                directive
+            ),
+            ParseErrorKind::UnknownOrder(order) => write!(
+                formatter,
+                "unknown union order \"{}\", expected before or after",
+                order
+            ),
+            ParseErrorKind::UnknownState(state) => {
+                write!(formatter, "unknown state bit \"{}\"", state)
+            }
+            ParseErrorKind::MissingArgument(argument) => {
+                write!(formatter, "missing {}", argument)
+            }
+            ParseErrorKind::ExtraArgument(argument) => {
+                write!(formatter, "unexpected \"{}\" after the last argument", argument)
+            }
+            ParseErrorKind::RelativePath(path) => {
+                write!(formatter, "path \"{}\" does not start with '/'", path)
+            }
+        }
+    }
+}
+// yields the bindings in the order they are to be made. a bad line is reported without stopping
+// the lines after it from being parsed, so every mistake in a file can be shown at once
+pub struct Parser<'a> {
+    lines: Enumerate<Lines<'a>>,
+    mask: State,
+}
+pub fn parse(text: &str) -> Parser<'_> {
+    Parser {
+        lines: text.lines().enumerate(),
+        mask: State::all(),
+    }
+}
+fn state_bits(name: &str) -> Option<State> {
+    let none = State::new();
+    Some(match name {
+        "all" => State::all(),
+        "walk" => none.walk(true),
+        "rename" => none.rename(true),
+        "make" => none.make(true),
+        "remove" => none.remove(true),
+        "read" => none.read(true),
+        "insert" => none.insert(true),
+        "overwrite" => none.overwrite(true),
+        "truncate" => none.truncate(true),
+        "seek" => none.seek(true),
+        "seek_forward" => none.seek_forward(true),
+        "seek_backward" => none.seek_backward(true),
+        "seek_start" => none.seek_start(true),
+        "seek_end" => none.seek_end(true),
+        "tell" => none.tell(true),
+        "lock" => none.lock(true),
+        _ => return None,
+    })
+}
+fn path<'a>(argument: Option<&'a str>, name: &'static str) -> Result<&'a str, ParseErrorKind<'a>> {
+    let path = argument.ok_or(ParseErrorKind::MissingArgument(name))?;
+    if !path.starts_with('/') {
+        return Err(ParseErrorKind::RelativePath(path));
+    }
+    Ok(path)
+}
+impl<'a> Parser<'a> {
+    // a mask line yields nothing, and an empty line is skipped
+    fn line(&mut self, line: &'a str) -> Result<Option<Binding<'a>>, ParseErrorKind<'a>> {
+        let line = line.split('#').next().unwrap_or("");
+        let mut arguments = line.split_whitespace();
+        let Some(directive) = arguments.next() else {
+            return Ok(None);
+        };
+        let order = match directive {
+            "bind" => Order::Replace,
+            "union" => match arguments.next() {
+                Some("before") => Order::Before,
+                Some("after") => Order::After,
+                Some(order) => return Err(ParseErrorKind::UnknownOrder(order)),
+                None => return Err(ParseErrorKind::MissingArgument("union order")),
+            },
+            "mask" => {
+                let mut mask = State::new();
+                let mut named = false;
+                for name in arguments {
+                    mask = mask | state_bits(name).ok_or(ParseErrorKind::UnknownState(name))?;
+                    named = true;
+                }
+                if !named {
+                    return Err(ParseErrorKind::MissingArgument("state bits"));
+                }
+                self.mask = mask;
+                return Ok(None);
+            }
+            _ => return Err(ParseErrorKind::UnknownDirective(directive)),
+        };
+        let from = path(arguments.next(), "path to bind")?;
+        let to = path(arguments.next(), "path to bind onto")?;
+        if let Some(extra) = arguments.next() {
+            return Err(ParseErrorKind::ExtraArgument(extra));
+        }
+        Ok(Some(Binding {
+            from,
+            to,
+            order,
+            mask: self.mask,
+        }))
+    }
+}
+impl<'a> Iterator for Parser<'a> {
+    type Item = Result<Binding<'a>, ParseError<'a>>;
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let (index, line) = self.lines.next()?;
+            match self.line(line) {
+                Ok(Some(binding)) => return Some(Ok(binding)),
+                Ok(None) => {}
+                Err(kind) => {
+                    return Some(Err(ParseError {
+                        line: index + 1,
+                        kind,
+                    }));
+                }
+            }
+        }
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    fn only(text: &str) -> Result<Binding<'_>, ParseError<'_>> {
+        let mut bindings = parse(text);
+        let binding = bindings.next().expect("no binding parsed");
+        assert_eq!(bindings.next(), None);
+        binding
+    }
+    fn error(text: &str) -> ParseErrorKind<'_> {
+        only(text).expect_err("bad line parsed").kind
+    }
+    #[test]
+    fn directives_give_their_order() {
+        for (text, order) in [
+            ("bind /a /b", Order::Replace),
+            ("union before /a /b", Order::Before),
+            ("union after /a /b", Order::After),
+        ] {
+            assert_eq!(
+                only(text),
+                Ok(Binding {
+                    from: "/a",
+                    to: "/b",
+                    order,
+                    mask: State::all(),
+                })
+            );
+        }
+    }
+    #[test]
+    fn masks_apply_to_the_bindings_after_them() {
+        let text = "bind /a /b\nmask read seek_start\nbind /c /d\nmask all\nbind /e /f";
+        let masks = parse(text)
+            .map(|binding| binding.expect("good line failed").mask)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            masks,
+            [
+                State::all(),
+                State::new().read(true).seek_start(true),
+                State::all(),
+            ]
+        );
+        let seek = only("mask seek\nbind /a /b").expect("good line failed").mask;
+        assert_eq!(seek, State::new().seek(true));
+    }
+    #[test]
+    fn comments_and_blank_lines_are_skipped() {
+        let text = "# a comment\n\n   \nbind /a /b # trailing\n";
+        assert_eq!(only(text).map(|binding| (binding.from, binding.to)), Ok(("/a", "/b")));
+    }
+    #[test]
+    fn each_bad_line_is_reported() {
+        assert_eq!(error("mount /a /b"), ParseErrorKind::UnknownDirective("mount"));
+        assert_eq!(error("union beside /a /b"), ParseErrorKind::UnknownOrder("beside"));
+        assert_eq!(error("mask read write"), ParseErrorKind::UnknownState("write"));
+        assert_eq!(error("union"), ParseErrorKind::MissingArgument("union order"));
+        assert_eq!(error("mask"), ParseErrorKind::MissingArgument("state bits"));
+        assert_eq!(error("bind"), ParseErrorKind::MissingArgument("path to bind"));
+        assert_eq!(error("bind /a"), ParseErrorKind::MissingArgument("path to bind onto"));
+        assert_eq!(error("bind /a /b /c"), ParseErrorKind::ExtraArgument("/c"));
+        assert_eq!(error("bind a /b"), ParseErrorKind::RelativePath("a"));
+        assert_eq!(error("union after /a b"), ParseErrorKind::RelativePath("b"));
+    }
+    #[test]
+    fn errors_carry_their_line_and_parsing_carries_on() {
+        let results = parse("bind /a /b\nbad\n\nbind /c\nbind /d /e").collect::<Vec<_>>();
+        assert_eq!(results.len(), 4);
+        assert_eq!(results[1].map_err(|error| error.line), Err(2));
+        assert_eq!(results[2].map_err(|error| error.line), Err(4));
+        assert_eq!(results[3].map(|binding| binding.from), Ok("/d"));
+        let message = format!("{}", results[1].expect_err("bad line parsed"));
+        assert_eq!(message, "line 2: unknown directive \"bad\", expected bind, union or mask");
+    }
+}
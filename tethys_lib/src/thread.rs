@@ -0,0 +1,73 @@
+use crate::{
+    Buffer, SyscallError, syscall_affinity, syscall_detach, syscall_exit, syscall_join,
+    syscall_prioritise, syscall_spawn,
+};
+use core::mem;
+const STACK_PAGES: usize = 16;
+// placed at the top of a new thread's stack, as the kernel only passes a single argument
+#[repr(C)]
+struct Start {
+    entry: fn(usize) -> usize,
+    argument: usize,
+}
+extern "C" fn start(start: *const Start) -> ! {
+    let Start { entry, argument } = unsafe { start.read() };
+    unsafe { syscall_exit(entry(argument)) }
+}
+// the stack stays mapped for as long as the thread might be using it, so a handle that is
+// dropped without being joined detaches the thread and leaks its stack
+pub struct JoinHandle {
+    id: usize,
+    stack: Option<Buffer>,
+}
+pub fn spawn(entry: fn(usize) -> usize, argument: usize) -> Result<JoinHandle, SyscallError> {
+    let mut stack = Buffer::new(STACK_PAGES);
+    let stack_slice = stack.as_mut_slice();
+    let start_address = (stack_slice.as_mut_ptr() as usize + stack_slice.len()
+        - size_of::<Start>())
+        & !0xf;
+    unsafe { (start_address as *mut Start).write(Start { entry, argument }) };
+    // the entry point is entered as if it had just been called, one return address below a
+    // 16-byte boundary
+    let id = unsafe {
+        syscall_spawn(
+            start as *const () as usize,
+            start_address - size_of::<usize>(),
+            start_address,
+        )
+    }?;
+    Ok(JoinHandle {
+        id,
+        stack: Some(stack),
+    })
+}
+impl JoinHandle {
+    pub fn id(&self) -> usize {
+        self.id
+    }
+    pub fn set_priority(&self, priority: usize) -> Result<(), SyscallError> {
+        unsafe { syscall_prioritise(self.id, priority) }
+    }
+    pub fn set_affinity(&self, mask: usize) -> Result<(), SyscallError> {
+        unsafe { syscall_affinity(self.id, mask) }
+    }
+    pub fn join(mut self) -> Result<usize, SyscallError> {
+        let value = unsafe { syscall_join(self.id) };
+        if value.is_ok() {
+            drop(self.stack.take());
+        }
+        value
+    }
+    pub fn detach(self) {}
+}
+impl Drop for JoinHandle {
+    fn drop(&mut self) {
+        if let Some(stack) = self.stack.take() {
+            let _ = unsafe { syscall_detach(self.id) };
+            mem::forget(stack);
+        }
+    }
+}
+pub fn exit(value: usize) -> ! {
+    unsafe { syscall_exit(value) }
+}
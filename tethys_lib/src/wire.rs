@@ -0,0 +1,658 @@
+use crate::{MessageHeader, MsgSelector, ResponseHeader, State, namespace::Order};
+// the encoding the kernel and every server speak, numbered through tethys_wire as the kernel's
+// own is. every word is little-endian, a request starts with a message header and a response
+// with a response header, each followed by its body
+// versions with the same major number understand each other's encoding, and a new minor version
+// only ever adds selectors, which a server lists in the protocol it answers a version message with
+pub use tethys_wire::{
+    FileError, NO_MESSAGE_LIMIT, Names, NamesIter, Protocol, REQUEST_HEADER_SIZE,
+    RESPONSE_HEADER_SIZE, WIRE_MAJOR, WIRE_MINOR, WIRE_VERSION, major, state_from_word,
+    state_word,
+};
+use tethys_wire::{ORDER_AFTER, ORDER_BEFORE, ORDER_REPLACE, STATUS_OK};
+// paths and names are bytes, as servers are free to name files however they like
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Request<'a> {
+    ReadState,
+    WriteState(State),
+    Drop,
+    Walk(&'a [u8]),
+    List(u64),
+    ListPeek(u64),
+    ListSeekForward(u64),
+    ListSeekBackward(u64),
+    ListSeekStart(u64),
+    ListSeekEnd(u64),
+    ListTell,
+    Make(State, &'a [u8]),
+    Remove(&'a [u8]),
+    Rename(&'a [u8]),
+    Read(u64),
+    Peek(u64),
+    Insert(&'a [u8]),
+    Overwrite(&'a [u8]),
+    Truncate(u64),
+    SeekForward(u64),
+    SeekBackward(u64),
+    SeekStart(u64),
+    SeekEnd(u64),
+    Bind {
+        from: u64,
+        state: State,
+        order: Order,
+        name: &'a [u8],
+    },
+    Unmap {
+        from: u64,
+        name: &'a [u8],
+    },
+    Tell,
+    Lock,
+    Version(u64),
+}
+// what a response carries depends on the selector of the request it answers
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Response<'a> {
+    Empty,
+    State(State),
+    Descriptor(u64),
+    Word(u64),
+    Names(Names<'a>),
+    Bytes(&'a [u8]),
+    Protocol(Protocol),
+}
+struct Writer<'a> {
+    out: &'a mut [u8],
+    position: usize,
+}
+impl<'a> Writer<'a> {
+    fn new(out: &'a mut [u8], position: usize) -> Writer<'a> {
+        Writer { out, position }
+    }
+    fn bytes(&mut self, bytes: &[u8]) -> Result<(), FileError> {
+        let end = self
+            .position
+            .checked_add(bytes.len())
+            .filter(|end| *end <= self.out.len())
+            .ok_or(FileError::Invalid)?;
+        self.out[self.position..end].copy_from_slice(bytes);
+        self.position = end;
+        Ok(())
+    }
+    fn word(&mut self, value: u64) -> Result<(), FileError> {
+        self.bytes(&value.to_le_bytes())
+    }
+}
+pub fn word(bytes: &[u8], index: usize) -> Result<u64, FileError> {
+    tethys_wire::word(bytes, index).ok_or(FileError::Invalid)
+}
+fn order_word(order: Order) -> u64 {
+    match order {
+        Order::Replace => ORDER_REPLACE,
+        Order::Before => ORDER_BEFORE,
+        Order::After => ORDER_AFTER,
+    }
+}
+impl MessageHeader {
+    pub fn parse(bytes: &[u8]) -> Result<MessageHeader, FileError> {
+        Ok(MessageHeader {
+            descriptor: word(bytes, 0)?,
+            selector: word(bytes, 1)?,
+            length: word(bytes, 2)?,
+        })
+    }
+}
+impl ResponseHeader {
+    pub fn parse(bytes: &[u8]) -> Result<ResponseHeader, FileError> {
+        Ok(ResponseHeader {
+            status: word(bytes, 0)?,
+            length: word(bytes, 1)?,
+        })
+    }
+}
+// the body following a header, which must fit within the bytes given
+fn body(bytes: &[u8], header_size: usize, length: u64) -> Result<&[u8], FileError> {
+    bytes
+        .get(header_size..)
+        .and_then(|body| body.get(..usize::try_from(length).ok()?))
+        .ok_or(FileError::Invalid)
+}
+impl<'a> Request<'a> {
+    // the length the request encodes to, header included
+    pub fn length(&self) -> usize {
+        REQUEST_HEADER_SIZE
+            + match *self {
+                Request::ReadState
+                | Request::Drop
+                | Request::ListTell
+                | Request::Tell
+                | Request::Lock => 0,
+                Request::WriteState(..)
+                | Request::List(..)
+                | Request::ListPeek(..)
+                | Request::ListSeekForward(..)
+                | Request::ListSeekBackward(..)
+                | Request::ListSeekStart(..)
+                | Request::ListSeekEnd(..)
+                | Request::Read(..)
+                | Request::Peek(..)
+                | Request::Truncate(..)
+                | Request::SeekForward(..)
+                | Request::SeekBackward(..)
+                | Request::SeekStart(..)
+                | Request::SeekEnd(..)
+                | Request::Version(..) => size_of::<u64>(),
+                Request::Walk(bytes)
+                | Request::Remove(bytes)
+                | Request::Rename(bytes)
+                | Request::Insert(bytes)
+                | Request::Overwrite(bytes) => bytes.len(),
+                Request::Make(_, name) | Request::Unmap { name, .. } => {
+                    size_of::<u64>() + name.len()
+                }
+                Request::Bind { name, .. } => 3 * size_of::<u64>() + name.len(),
+            }
+    }
+    pub fn selector(&self) -> MsgSelector {
+        match self {
+            Request::ReadState => MsgSelector::ReadState,
+            Request::WriteState(..) => MsgSelector::WriteState,
+            Request::Drop => MsgSelector::Drop,
+            Request::Walk(..) => MsgSelector::Walk,
+            Request::List(..) => MsgSelector::List,
+            Request::ListPeek(..) => MsgSelector::ListPeek,
+            Request::ListSeekForward(..) => MsgSelector::ListSeekForward,
+            Request::ListSeekBackward(..) => MsgSelector::ListSeekBackward,
+            Request::ListSeekStart(..) => MsgSelector::ListSeekStart,
+            Request::ListSeekEnd(..) => MsgSelector::ListSeekEnd,
+            Request::ListTell => MsgSelector::ListTell,
+            Request::Make(..) => MsgSelector::Make,
+            Request::Remove(..) => MsgSelector::Remove,
+            Request::Rename(..) => MsgSelector::Rename,
+            Request::Read(..) => MsgSelector::Read,
+            Request::Peek(..) => MsgSelector::Peek,
+            Request::Insert(..) => MsgSelector::Insert,
+            Request::Overwrite(..) => MsgSelector::Overwrite,
+            Request::Truncate(..) => MsgSelector::Truncate,
+            Request::SeekForward(..) => MsgSelector::SeekForward,
+            Request::SeekBackward(..) => MsgSelector::SeekBackward,
+            Request::SeekStart(..) => MsgSelector::SeekStart,
+            Request::SeekEnd(..) => MsgSelector::SeekEnd,
+            Request::Bind { .. } => MsgSelector::Bind,
+            Request::Unmap { .. } => MsgSelector::Unmap,
+            Request::Tell => MsgSelector::Tell,
+            Request::Lock => MsgSelector::Lock,
+            Request::Version(..) => MsgSelector::Version,
+        }
+    }
+    // writes the request into the start of a buffer's pages, returning its length in bytes
+    pub fn encode(&self, descriptor: u64, out: &mut [u8]) -> Result<usize, FileError> {
+        let mut writer = Writer::new(out, REQUEST_HEADER_SIZE);
+        match *self {
+            Request::ReadState
+            | Request::Drop
+            | Request::ListTell
+            | Request::Tell
+            | Request::Lock => {}
+            Request::WriteState(state) => writer.word(state_word(state))?,
+            Request::List(value)
+            | Request::ListPeek(value)
+            | Request::ListSeekForward(value)
+            | Request::ListSeekBackward(value)
+            | Request::ListSeekStart(value)
+            | Request::ListSeekEnd(value)
+            | Request::Read(value)
+            | Request::Peek(value)
+            | Request::Truncate(value)
+            | Request::SeekForward(value)
+            | Request::SeekBackward(value)
+            | Request::SeekStart(value)
+            | Request::SeekEnd(value)
+            | Request::Version(value) => writer.word(value)?,
+            Request::Walk(bytes)
+            | Request::Remove(bytes)
+            | Request::Rename(bytes)
+            | Request::Insert(bytes)
+            | Request::Overwrite(bytes) => writer.bytes(bytes)?,
+            Request::Make(state, name) => {
+                writer.word(state_word(state))?;
+                writer.bytes(name)?;
+            }
+            Request::Bind {
+                from,
+                state,
+                order,
+                name,
+            } => {
+                writer.word(from)?;
+                writer.word(state_word(state))?;
+                writer.word(order_word(order))?;
+                writer.bytes(name)?;
+            }
+            Request::Unmap { from, name } => {
+                writer.word(from)?;
+                writer.bytes(name)?;
+            }
+        }
+        let length = writer.position;
+        let mut header = Writer::new(writer.out, 0);
+        header.word(descriptor)?;
+        header.word(self.selector() as u64)?;
+        header.word((length - REQUEST_HEADER_SIZE) as u64)?;
+        Ok(length)
+    }
+    // reads a request out of a buffer's pages, along with the descriptor it was sent through
+    pub fn decode(bytes: &'a [u8]) -> Result<(u64, Request<'a>), FileError> {
+        let header = MessageHeader::parse(bytes)?;
+        let body = body(bytes, REQUEST_HEADER_SIZE, header.length)?;
+        let argument = || word(body, 0);
+        let request = match MsgSelector::from_word(header.selector).ok_or(FileError::Invalid)? {
+            MsgSelector::ReadState => Request::ReadState,
+            MsgSelector::WriteState => Request::WriteState(state_from_word(argument()?)),
+            MsgSelector::Drop => Request::Drop,
+            MsgSelector::Walk => Request::Walk(body),
+            MsgSelector::List => Request::List(argument()?),
+            MsgSelector::ListPeek => Request::ListPeek(argument()?),
+            MsgSelector::ListSeekForward => Request::ListSeekForward(argument()?),
+            MsgSelector::ListSeekBackward => Request::ListSeekBackward(argument()?),
+            MsgSelector::ListSeekStart => Request::ListSeekStart(argument()?),
+            MsgSelector::ListSeekEnd => Request::ListSeekEnd(argument()?),
+            MsgSelector::ListTell => Request::ListTell,
+            MsgSelector::Make => {
+                Request::Make(state_from_word(argument()?), &body[size_of::<u64>()..])
+            }
+            MsgSelector::Remove => Request::Remove(body),
+            MsgSelector::Rename => Request::Rename(body),
+            MsgSelector::Read => Request::Read(argument()?),
+            MsgSelector::Peek => Request::Peek(argument()?),
+            MsgSelector::Insert => Request::Insert(body),
+            MsgSelector::Overwrite => Request::Overwrite(body),
+            MsgSelector::Truncate => Request::Truncate(argument()?),
+            MsgSelector::SeekForward => Request::SeekForward(argument()?),
+            MsgSelector::SeekBackward => Request::SeekBackward(argument()?),
+            MsgSelector::SeekStart => Request::SeekStart(argument()?),
+            MsgSelector::SeekEnd => Request::SeekEnd(argument()?),
+            MsgSelector::Bind => Request::Bind {
+                from: argument()?,
+                state: state_from_word(word(body, 1)?),
+                order: match word(body, 2)? {
+                    ORDER_REPLACE => Order::Replace,
+                    ORDER_BEFORE => Order::Before,
+                    ORDER_AFTER => Order::After,
+                    _ => return Err(FileError::Invalid),
+                },
+                name: &body[3 * size_of::<u64>()..],
+            },
+            MsgSelector::Unmap => Request::Unmap {
+                from: argument()?,
+                name: &body[size_of::<u64>()..],
+            },
+            MsgSelector::Tell => Request::Tell,
+            MsgSelector::Lock => Request::Lock,
+            MsgSelector::Version => Request::Version(argument()?),
+        };
+        Ok((header.descriptor, request))
+    }
+}
+impl<'a> Response<'a> {
+    // writes a response, or the error it carries instead, into the start of a buffer's pages,
+    // returning its length in bytes
+    pub fn encode(result: Result<Response, FileError>, out: &mut [u8]) -> Result<usize, FileError> {
+        let mut writer = Writer::new(out, RESPONSE_HEADER_SIZE);
+        let status = match result {
+            Ok(response) => {
+                match response {
+                    Response::Empty => {}
+                    Response::State(state) => writer.word(state_word(state))?,
+                    Response::Descriptor(value) | Response::Word(value) => writer.word(value)?,
+                    Response::Names(names) => writer.bytes(names.as_bytes())?,
+                    Response::Bytes(bytes) => writer.bytes(bytes)?,
+                    Response::Protocol(protocol) => writer.bytes(&protocol.encode())?,
+                }
+                STATUS_OK
+            }
+            Err(error) => error.code(),
+        };
+        let length = writer.position;
+        let mut header = Writer::new(writer.out, 0);
+        header.word(status)?;
+        header.word((length - RESPONSE_HEADER_SIZE) as u64)?;
+        Ok(length)
+    }
+    // reads the response to a request with the given selector, which decides what it carries
+    pub fn decode(selector: MsgSelector, bytes: &'a [u8]) -> Result<Response<'a>, FileError> {
+        let header = ResponseHeader::parse(bytes)?;
+        if header.status != STATUS_OK {
+            return Err(FileError::from_status(header.status));
+        }
+        let body = body(bytes, RESPONSE_HEADER_SIZE, header.length)?;
+        Ok(match selector {
+            MsgSelector::WriteState
+            | MsgSelector::Drop
+            | MsgSelector::Remove
+            | MsgSelector::Rename
+            | MsgSelector::Bind
+            | MsgSelector::Unmap
+            | MsgSelector::Lock => Response::Empty,
+            MsgSelector::ReadState => Response::State(state_from_word(word(body, 0)?)),
+            MsgSelector::Walk | MsgSelector::Make => Response::Descriptor(word(body, 0)?),
+            MsgSelector::List | MsgSelector::ListPeek => Response::Names(Names::parse(body)?),
+            MsgSelector::Read | MsgSelector::Peek => Response::Bytes(body),
+            MsgSelector::Version => Response::Protocol(Protocol::parse(body)?),
+            MsgSelector::ListSeekForward
+            | MsgSelector::ListSeekBackward
+            | MsgSelector::ListSeekStart
+            | MsgSelector::ListSeekEnd
+            | MsgSelector::ListTell
+            | MsgSelector::Insert
+            | MsgSelector::Overwrite
+            | MsgSelector::Truncate
+            | MsgSelector::SeekForward
+            | MsgSelector::SeekBackward
+            | MsgSelector::SeekStart
+            | MsgSelector::SeekEnd
+            | MsgSelector::Tell => Response::Word(word(body, 0)?),
+        })
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::PAGE_SIZE;
+    use tethys_wire::SELECTORS;
+    const DESCRIPTOR: u64 = 7;
+    const NAME: &[u8] = b"name";
+    fn state() -> State {
+        State::new().walk(true).read(true).lock(true)
+    }
+    fn requests() -> Vec<Request<'static>> {
+        vec![
+            Request::ReadState,
+            Request::WriteState(state()),
+            Request::Drop,
+            Request::Walk(b"a/b"),
+            Request::Walk(b""),
+            Request::List(1),
+            Request::ListPeek(2),
+            Request::ListSeekForward(3),
+            Request::ListSeekBackward(4),
+            Request::ListSeekStart(5),
+            Request::ListSeekEnd(6),
+            Request::ListTell,
+            Request::Make(state(), NAME),
+            Request::Remove(NAME),
+            Request::Rename(NAME),
+            Request::Read(7),
+            Request::Peek(8),
+            Request::Insert(b"content"),
+            Request::Overwrite(b"content"),
+            Request::Truncate(9),
+            Request::SeekForward(10),
+            Request::SeekBackward(11),
+            Request::SeekStart(12),
+            Request::SeekEnd(u64::MAX),
+            Request::Bind {
+                from: 3,
+                state: state(),
+                order: Order::Replace,
+                name: NAME,
+            },
+            Request::Bind {
+                from: 3,
+                state: State::all(),
+                order: Order::Before,
+                name: NAME,
+            },
+            Request::Bind {
+                from: 3,
+                state: State::new(),
+                order: Order::After,
+                name: b"",
+            },
+            Request::Unmap {
+                from: 4,
+                name: NAME,
+            },
+            Request::Tell,
+            Request::Lock,
+            Request::Version(WIRE_VERSION),
+        ]
+    }
+    #[test]
+    fn every_request_round_trips() {
+        let requests = requests();
+        for selector in SELECTORS {
+            assert!(
+                requests.iter().any(|request| request.selector() == selector),
+                "{:?} has no request to round trip",
+                selector
+            );
+        }
+        for request in requests {
+            let mut out = [0; 64];
+            let length = request.encode(DESCRIPTOR, &mut out).expect("request failed to encode");
+            assert_eq!(length, request.length());
+            assert_eq!(Request::decode(&out[..length]), Ok((DESCRIPTOR, request)));
+        }
+    }
+    #[test]
+    fn requests_too_long_for_the_buffer_fail() {
+        let mut out = [0; REQUEST_HEADER_SIZE + 2];
+        assert_eq!(Request::Walk(b"abc").encode(DESCRIPTOR, &mut out), Err(FileError::Invalid));
+        assert_eq!(Request::decode(&out[..REQUEST_HEADER_SIZE - 1]), Err(FileError::Invalid));
+    }
+    #[test]
+    fn every_response_round_trips() {
+        let mut names = [0; 64];
+        let listed: [&[u8]; 3] = [b"one", b"", b"three"];
+        let names = Names::encode(listed, &mut names).expect("names failed");
+        let protocol = Protocol::current();
+        for selector in SELECTORS {
+            let response = match selector {
+                MsgSelector::WriteState
+                | MsgSelector::Drop
+                | MsgSelector::Remove
+                | MsgSelector::Rename
+                | MsgSelector::Bind
+                | MsgSelector::Unmap
+                | MsgSelector::Lock => Response::Empty,
+                MsgSelector::ReadState => Response::State(state()),
+                MsgSelector::Walk | MsgSelector::Make => Response::Descriptor(DESCRIPTOR),
+                MsgSelector::List | MsgSelector::ListPeek => Response::Names(names),
+                MsgSelector::Read | MsgSelector::Peek => Response::Bytes(b"content"),
+                MsgSelector::Version => Response::Protocol(protocol),
+                _ => Response::Word(selector as u64),
+            };
+            let mut out = [0; 64];
+            let length = Response::encode(Ok(response), &mut out).expect("response failed");
+            assert_eq!(Response::decode(selector, &out[..length]), Ok(response));
+        }
+        assert_eq!(names.iter().collect::<Vec<_>>(), listed);
+        assert!(Names::empty().is_empty());
+    }
+    #[test]
+    fn errors_round_trip_for_every_selector() {
+        for code in 1.. {
+            let Some(error) = FileError::from_code(code) else {
+                break;
+            };
+            for selector in SELECTORS {
+                let mut out = [0; RESPONSE_HEADER_SIZE];
+                let length = Response::encode(Err(error), &mut out).expect("error failed");
+                assert_eq!(Response::decode(selector, &out[..length]), Err(error));
+            }
+        }
+    }
+    // cases are generated from a fixed seed, so that a failing case fails on every run
+    const SEED: u64 = 0x7e74_a5c0_de00_0001;
+    const CASES: usize = 1024;
+    // payloads run up to two pages, so that messages cross the end of a buffer's first page
+    const PAYLOAD_LIMIT: usize = 2 * PAGE_SIZE;
+    struct Generator(u64);
+    impl Generator {
+        fn next(&mut self) -> u64 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            self.0
+        }
+        fn below(&mut self, bound: usize) -> usize {
+            (self.next() % bound as u64) as usize
+        }
+        fn pool(&mut self) -> Vec<u8> {
+            (0..PAYLOAD_LIMIT).map(|_| self.next() as u8).collect()
+        }
+        // a run of arbitrary bytes, short as often as not
+        fn bytes<'p>(&mut self, pool: &'p [u8]) -> &'p [u8] {
+            let limit = if self.next() & 1 == 0 { 16 } else { pool.len() };
+            let length = self.below(limit + 1);
+            let start = self.below(pool.len() - length + 1);
+            &pool[start..start + length]
+        }
+        fn state(&mut self) -> State {
+            state_from_word(self.next())
+        }
+        fn selector(&mut self) -> MsgSelector {
+            SELECTORS[self.below(SELECTORS.len())]
+        }
+        fn request<'p>(&mut self, pool: &'p [u8]) -> Request<'p> {
+            let value = self.next();
+            match self.selector() {
+                MsgSelector::ReadState => Request::ReadState,
+                MsgSelector::WriteState => Request::WriteState(self.state()),
+                MsgSelector::Drop => Request::Drop,
+                MsgSelector::Walk => Request::Walk(self.bytes(pool)),
+                MsgSelector::List => Request::List(value),
+                MsgSelector::ListPeek => Request::ListPeek(value),
+                MsgSelector::ListSeekForward => Request::ListSeekForward(value),
+                MsgSelector::ListSeekBackward => Request::ListSeekBackward(value),
+                MsgSelector::ListSeekStart => Request::ListSeekStart(value),
+                MsgSelector::ListSeekEnd => Request::ListSeekEnd(value),
+                MsgSelector::ListTell => Request::ListTell,
+                MsgSelector::Make => Request::Make(self.state(), self.bytes(pool)),
+                MsgSelector::Remove => Request::Remove(self.bytes(pool)),
+                MsgSelector::Rename => Request::Rename(self.bytes(pool)),
+                MsgSelector::Read => Request::Read(value),
+                MsgSelector::Peek => Request::Peek(value),
+                MsgSelector::Insert => Request::Insert(self.bytes(pool)),
+                MsgSelector::Overwrite => Request::Overwrite(self.bytes(pool)),
+                MsgSelector::Truncate => Request::Truncate(value),
+                MsgSelector::SeekForward => Request::SeekForward(value),
+                MsgSelector::SeekBackward => Request::SeekBackward(value),
+                MsgSelector::SeekStart => Request::SeekStart(value),
+                MsgSelector::SeekEnd => Request::SeekEnd(value),
+                MsgSelector::Bind => Request::Bind {
+                    from: value,
+                    state: self.state(),
+                    order: [Order::Replace, Order::Before, Order::After][self.below(3)],
+                    name: self.bytes(pool),
+                },
+                MsgSelector::Unmap => Request::Unmap {
+                    from: value,
+                    name: self.bytes(pool),
+                },
+                MsgSelector::Tell => Request::Tell,
+                MsgSelector::Lock => Request::Lock,
+                MsgSelector::Version => Request::Version(value),
+            }
+        }
+    }
+    // every cut short of the whole message fails, as does a length word claiming more than is
+    // there, without either panicking
+    fn assert_truncations_fail(
+        bytes: &[u8],
+        length_index: usize,
+        error: impl Fn(&[u8]) -> Option<FileError>,
+    ) {
+        for cut in 0..bytes.len() {
+            assert!(error(&bytes[..cut]).is_some(), "{} of {} bytes decoded", cut, bytes.len());
+        }
+        let length = word(bytes, length_index).expect("message had no length");
+        for oversized in [length + 1, u64::MAX] {
+            let mut bytes = bytes.to_vec();
+            let at = length_index * size_of::<u64>();
+            bytes[at..at + size_of::<u64>()].copy_from_slice(&oversized.to_le_bytes());
+            assert_eq!(error(&bytes), Some(FileError::Invalid));
+        }
+    }
+    #[test]
+    fn generated_requests_round_trip() {
+        let mut generator = Generator(SEED);
+        let pool = generator.pool();
+        for _ in 0..CASES {
+            let descriptor = generator.next();
+            let request = generator.request(&pool);
+            // sized as a buffer of whole pages would be, which is longer than the request
+            let mut out = vec![0; request.length().div_ceil(PAGE_SIZE) * PAGE_SIZE];
+            let length = request.encode(descriptor, &mut out).expect("request failed to encode");
+            assert_eq!(length, request.length());
+            assert_eq!(Request::decode(&out), Ok((descriptor, request)));
+            assert_eq!(
+                request.encode(descriptor, &mut out[..length - 1]),
+                Err(FileError::Invalid)
+            );
+            let mut exact = vec![0; length];
+            request.encode(descriptor, &mut exact).expect("request failed to encode");
+            assert_truncations_fail(&exact, 2, |bytes| Request::decode(bytes).err());
+        }
+    }
+    #[test]
+    fn generated_responses_round_trip() {
+        let mut generator = Generator(SEED);
+        let pool = generator.pool();
+        for _ in 0..CASES {
+            let listed = (0..generator.below(16))
+                .map(|_| generator.bytes(&pool[..256]))
+                .collect::<Vec<_>>();
+            let mut names = vec![0; Names::length(listed.iter().copied())];
+            let names = Names::encode(listed.iter().copied(), &mut names).expect("names failed");
+            let selector = generator.selector();
+            let response = match selector {
+                MsgSelector::WriteState
+                | MsgSelector::Drop
+                | MsgSelector::Remove
+                | MsgSelector::Rename
+                | MsgSelector::Bind
+                | MsgSelector::Unmap
+                | MsgSelector::Lock => Response::Empty,
+                MsgSelector::ReadState => Response::State(generator.state()),
+                MsgSelector::Walk | MsgSelector::Make => Response::Descriptor(generator.next()),
+                MsgSelector::List | MsgSelector::ListPeek => Response::Names(names),
+                MsgSelector::Read | MsgSelector::Peek => Response::Bytes(generator.bytes(&pool)),
+                MsgSelector::Version => Response::Protocol(Protocol {
+                    version: generator.next(),
+                    message_limit: generator.next(),
+                    selectors: generator.next(),
+                }),
+                _ => Response::Word(generator.next()),
+            };
+            let mut out = vec![0; RESPONSE_HEADER_SIZE + PAYLOAD_LIMIT];
+            let length = Response::encode(Ok(response), &mut out).expect("response failed");
+            assert_eq!(Response::decode(selector, &out), Ok(response));
+            if let Response::Names(names) = response {
+                assert_eq!(names.iter().collect::<Vec<_>>(), listed);
+            }
+            assert_truncations_fail(&out[..length], 1, |bytes| {
+                Response::decode(selector, bytes).err()
+            });
+        }
+    }
+    #[test]
+    fn arbitrary_bytes_never_panic() {
+        let mut generator = Generator(SEED);
+        for _ in 0..CASES {
+            // headers that are mostly plausible, so that decoding gets as far as the body
+            let body = (0..generator.below(64)).map(|_| generator.next() as u8).collect::<Vec<_>>();
+            let mut bytes = Vec::new();
+            bytes.extend(generator.next().to_le_bytes());
+            bytes.extend((generator.below(SELECTORS.len() + 2) as u64).to_le_bytes());
+            bytes.extend((generator.below(body.len() + 16) as u64).to_le_bytes());
+            bytes.extend(&body);
+            let _ = Request::decode(&bytes);
+            for selector in SELECTORS {
+                let _ = Response::decode(selector, &bytes[size_of::<u64>()..]);
+                let _ = Response::decode(selector, &bytes[2 * size_of::<u64>()..]);
+            }
+        }
+    }
+}
@@ -0,0 +1,621 @@
+#![cfg_attr(not(test), no_std)]
+use core::ops::{BitAnd, BitOr};
+// the numbering of the encoding the kernel and every server speak, shared so that neither side
+// can drift from the other. every word is little-endian, a request starts with the descriptor it
+// is sent through, its selector and the length of the body that follows, and a response with its
+// status and the length of its body
+pub const REQUEST_HEADER_SIZE: usize = 3 * size_of::<u64>();
+pub const RESPONSE_HEADER_SIZE: usize = 2 * size_of::<u64>();
+pub const STATUS_OK: u64 = 0;
+// a version is its major number in the upper half of a word and its minor number in the lower.
+// versions with the same major number understand each other's encoding, and a new minor version
+// only ever adds selectors, which servers advertise
+pub const WIRE_MAJOR: u64 = 1;
+pub const WIRE_MINOR: u64 = 0;
+pub const WIRE_VERSION: u64 = (WIRE_MAJOR << 32) | WIRE_MINOR;
+pub const NO_MESSAGE_LIMIT: u64 = u64::MAX;
+pub const ORDER_REPLACE: u64 = 0;
+pub const ORDER_BEFORE: u64 = 1;
+pub const ORDER_AFTER: u64 = 2;
+// the bits of a state word follow the order of the state's fields
+pub const STATE_BITS: u32 = 14;
+pub const STATE_WALK: u32 = 0;
+pub const STATE_RENAME: u32 = 1;
+pub const STATE_MAKE: u32 = 2;
+pub const STATE_REMOVE: u32 = 3;
+pub const STATE_READ: u32 = 4;
+pub const STATE_INSERT: u32 = 5;
+pub const STATE_OVERWRITE: u32 = 6;
+pub const STATE_TRUNCATE: u32 = 7;
+pub const STATE_SEEK_FORWARD: u32 = 8;
+pub const STATE_SEEK_BACKWARD: u32 = 9;
+pub const STATE_SEEK_START: u32 = 10;
+pub const STATE_SEEK_END: u32 = 11;
+pub const STATE_TELL: u32 = 12;
+pub const STATE_LOCK: u32 = 13;
+// what can be done with a file, each field carried in the bit of a state word named after it
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct State {
+    pub walk: bool,
+    pub rename: bool,
+    pub make: bool,
+    pub remove: bool,
+    pub read: bool,
+    pub insert: bool,
+    pub overwrite: bool,
+    pub truncate: bool,
+    pub seek_forward: bool,
+    pub seek_backward: bool,
+    pub seek_start: bool,
+    pub seek_end: bool,
+    pub tell: bool,
+    pub lock: bool,
+}
+macro_rules! state_chain {
+    ($field:ident) => {
+        pub const fn $field(mut self, value: bool) -> State {
+            self.$field = value;
+            self
+        }
+    };
+}
+impl State {
+    pub const fn new() -> State {
+        State {
+            walk: false,
+            rename: false,
+            make: false,
+            remove: false,
+            read: false,
+            insert: false,
+            overwrite: false,
+            truncate: false,
+            seek_forward: false,
+            seek_backward: false,
+            seek_start: false,
+            seek_end: false,
+            tell: false,
+            lock: false,
+        }
+    }
+    pub const fn all() -> State {
+        State::new()
+            .walk(true)
+            .rename(true)
+            .make(true)
+            .remove(true)
+            .read(true)
+            .insert(true)
+            .overwrite(true)
+            .truncate(true)
+            .seek(true)
+            .tell(true)
+            .lock(true)
+    }
+    pub const fn seek(self, value: bool) -> State {
+        self.seek_forward(value)
+            .seek_backward(value)
+            .seek_start(value)
+            .seek_end(value)
+    }
+    state_chain!(walk);
+    state_chain!(rename);
+    state_chain!(make);
+    state_chain!(remove);
+    state_chain!(read);
+    state_chain!(insert);
+    state_chain!(overwrite);
+    state_chain!(truncate);
+    state_chain!(seek_forward);
+    state_chain!(seek_backward);
+    state_chain!(seek_start);
+    state_chain!(seek_end);
+    state_chain!(tell);
+    state_chain!(lock);
+    fn bits(self) -> [(bool, u32); STATE_BITS as usize] {
+        [
+            (self.walk, STATE_WALK),
+            (self.rename, STATE_RENAME),
+            (self.make, STATE_MAKE),
+            (self.remove, STATE_REMOVE),
+            (self.read, STATE_READ),
+            (self.insert, STATE_INSERT),
+            (self.overwrite, STATE_OVERWRITE),
+            (self.truncate, STATE_TRUNCATE),
+            (self.seek_forward, STATE_SEEK_FORWARD),
+            (self.seek_backward, STATE_SEEK_BACKWARD),
+            (self.seek_start, STATE_SEEK_START),
+            (self.seek_end, STATE_SEEK_END),
+            (self.tell, STATE_TELL),
+            (self.lock, STATE_LOCK),
+        ]
+    }
+}
+impl BitAnd for State {
+    type Output = State;
+    fn bitand(self, other: State) -> State {
+        state_from_word(state_word(self) & state_word(other))
+    }
+}
+impl BitOr for State {
+    type Output = State;
+    fn bitor(self, other: State) -> State {
+        state_from_word(state_word(self) | state_word(other))
+    }
+}
+pub fn state_word(state: State) -> u64 {
+    state
+        .bits()
+        .into_iter()
+        .fold(0, |word, (enabled, bit)| word | ((enabled as u64) << bit))
+}
+// bits past the last state bit are ignored
+pub fn state_from_word(word: u64) -> State {
+    let bit = |index: u32| word & (1 << index) != 0;
+    State::new()
+        .walk(bit(STATE_WALK))
+        .rename(bit(STATE_RENAME))
+        .make(bit(STATE_MAKE))
+        .remove(bit(STATE_REMOVE))
+        .read(bit(STATE_READ))
+        .insert(bit(STATE_INSERT))
+        .overwrite(bit(STATE_OVERWRITE))
+        .truncate(bit(STATE_TRUNCATE))
+        .seek_forward(bit(STATE_SEEK_FORWARD))
+        .seek_backward(bit(STATE_SEEK_BACKWARD))
+        .seek_start(bit(STATE_SEEK_START))
+        .seek_end(bit(STATE_SEEK_END))
+        .tell(bit(STATE_TELL))
+        .lock(bit(STATE_LOCK))
+}
+// numbered as they are on the wire
+#[repr(usize)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Selector {
+    ReadState,
+    WriteState,
+    Drop,
+    Walk,
+    List,
+    ListPeek,
+    ListSeekForward,
+    ListSeekBackward,
+    ListSeekStart,
+    ListSeekEnd,
+    ListTell,
+    Make,
+    Remove,
+    Rename,
+    Read,
+    Peek,
+    Insert,
+    Overwrite,
+    Truncate,
+    SeekForward,
+    SeekBackward,
+    SeekStart,
+    SeekEnd,
+    Bind,
+    Unmap,
+    Tell,
+    Lock,
+    Version,
+}
+pub const SELECTORS: [Selector; 28] = [
+    Selector::ReadState,
+    Selector::WriteState,
+    Selector::Drop,
+    Selector::Walk,
+    Selector::List,
+    Selector::ListPeek,
+    Selector::ListSeekForward,
+    Selector::ListSeekBackward,
+    Selector::ListSeekStart,
+    Selector::ListSeekEnd,
+    Selector::ListTell,
+    Selector::Make,
+    Selector::Remove,
+    Selector::Rename,
+    Selector::Read,
+    Selector::Peek,
+    Selector::Insert,
+    Selector::Overwrite,
+    Selector::Truncate,
+    Selector::SeekForward,
+    Selector::SeekBackward,
+    Selector::SeekStart,
+    Selector::SeekEnd,
+    Selector::Bind,
+    Selector::Unmap,
+    Selector::Tell,
+    Selector::Lock,
+    Selector::Version,
+];
+impl Selector {
+    pub fn from_word(word: u64) -> Option<Selector> {
+        SELECTORS.get(usize::try_from(word).ok()?).copied()
+    }
+    // the bit of a state a message needs on the descriptor it is sent through, with none needed
+    // by the messages that are always allowed
+    pub fn required_bit(self) -> Option<u32> {
+        match self {
+            Selector::Walk
+            | Selector::List
+            | Selector::ListPeek
+            | Selector::ListSeekForward
+            | Selector::ListSeekBackward
+            | Selector::ListSeekStart
+            | Selector::ListSeekEnd
+            | Selector::ListTell => Some(STATE_WALK),
+            Selector::Make | Selector::Bind => Some(STATE_MAKE),
+            Selector::Remove | Selector::Unmap => Some(STATE_REMOVE),
+            Selector::Rename => Some(STATE_RENAME),
+            Selector::Read | Selector::Peek => Some(STATE_READ),
+            Selector::Insert => Some(STATE_INSERT),
+            Selector::Overwrite => Some(STATE_OVERWRITE),
+            Selector::Truncate => Some(STATE_TRUNCATE),
+            Selector::SeekForward => Some(STATE_SEEK_FORWARD),
+            Selector::SeekBackward => Some(STATE_SEEK_BACKWARD),
+            Selector::SeekStart => Some(STATE_SEEK_START),
+            Selector::SeekEnd => Some(STATE_SEEK_END),
+            Selector::Tell => Some(STATE_TELL),
+            Selector::Lock => Some(STATE_LOCK),
+            Selector::ReadState | Selector::WriteState | Selector::Drop | Selector::Version => None,
+        }
+    }
+    pub fn permitted(self, mask: u64) -> bool {
+        self.required_bit().is_none_or(|bit| mask & (1 << bit) != 0)
+    }
+}
+// a response's error status. anything malformed, including a message too large for the pages it
+// is written into, is invalid
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FileError {
+    Unsupported,
+    NotFound,
+    Denied,
+    Busy,
+    Invalid,
+    // waiting would leave processes waiting on each other for good
+    Deadlock,
+    // the descriptor, or a binding it was walked through, has had its grant taken back
+    Revoked,
+    // the server speaks a version of the encoding the other side does not, or cannot take the
+    // message
+    Incompatible,
+}
+impl FileError {
+    pub fn code(self) -> u64 {
+        match self {
+            FileError::Unsupported => 1,
+            FileError::NotFound => 2,
+            FileError::Denied => 3,
+            FileError::Busy => 4,
+            FileError::Invalid => 5,
+            FileError::Deadlock => 6,
+            FileError::Revoked => 7,
+            FileError::Incompatible => 8,
+        }
+    }
+    // none for the success status and for any code this side does not know, which the decoder
+    // decides how to report rather than having it pass for some other error
+    pub fn from_code(code: u64) -> Option<FileError> {
+        match code {
+            1 => Some(FileError::Unsupported),
+            2 => Some(FileError::NotFound),
+            3 => Some(FileError::Denied),
+            4 => Some(FileError::Busy),
+            5 => Some(FileError::Invalid),
+            6 => Some(FileError::Deadlock),
+            7 => Some(FileError::Revoked),
+            8 => Some(FileError::Incompatible),
+            _ => None,
+        }
+    }
+    // a status that is neither success nor a known error comes from a server speaking an
+    // encoding this side does not, so it is reported as incompatible
+    pub fn from_status(status: u64) -> FileError {
+        FileError::from_code(status).unwrap_or(FileError::Incompatible)
+    }
+}
+// what a server speaks, as it answers a version message: its version, the largest request it
+// takes in bytes and a bit for each selector it supports, numbered as selectors are
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Protocol {
+    pub version: u64,
+    pub message_limit: u64,
+    pub selectors: u64,
+}
+pub const PROTOCOL_SIZE: usize = 3 * size_of::<u64>();
+impl Protocol {
+    // anything built against this crate takes every selector it knows of, at any size
+    pub const fn current() -> Protocol {
+        Protocol {
+            version: WIRE_VERSION,
+            message_limit: NO_MESSAGE_LIMIT,
+            selectors: (1 << SELECTORS.len()) - 1,
+        }
+    }
+    // a server from before version messages is taken to speak 1.0, with every selector before
+    // version
+    pub const fn legacy() -> Protocol {
+        Protocol {
+            version: 1 << 32,
+            message_limit: NO_MESSAGE_LIMIT,
+            selectors: (1 << Selector::Version as u64) - 1,
+        }
+    }
+    pub fn compatible(&self) -> bool {
+        major(self.version) == WIRE_MAJOR
+    }
+    pub fn supports(&self, selector: Selector) -> bool {
+        self.selectors & (1 << selector as u64) != 0
+    }
+    // fields appended by later minor versions are ignored
+    pub fn parse(bytes: &[u8]) -> Result<Protocol, FileError> {
+        let field = |index| word(bytes, index).ok_or(FileError::Invalid);
+        Ok(Protocol {
+            version: field(0)?,
+            message_limit: field(1)?,
+            selectors: field(2)?,
+        })
+    }
+    pub fn encode(&self) -> [u8; PROTOCOL_SIZE] {
+        let mut bytes = [0; PROTOCOL_SIZE];
+        for (chunk, field) in bytes
+            .chunks_exact_mut(size_of::<u64>())
+            .zip([self.version, self.message_limit, self.selectors])
+        {
+            chunk.copy_from_slice(&field.to_le_bytes());
+        }
+        bytes
+    }
+}
+// a list of names still in its encoded form, the number of names followed by each name's length
+// and bytes, which is checked once and then read without copying
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Names<'a> {
+    bytes: &'a [u8],
+}
+impl<'a> Names<'a> {
+    pub fn parse(bytes: &'a [u8]) -> Result<Names<'a>, FileError> {
+        let count = word(bytes, 0).ok_or(FileError::Invalid)?;
+        let mut offset = size_of::<u64>();
+        for _ in 0..count {
+            let length = bytes
+                .get(offset..)
+                .and_then(|rest| word(rest, 0))
+                .and_then(|length| usize::try_from(length).ok())
+                .ok_or(FileError::Invalid)?;
+            offset = offset
+                .checked_add(size_of::<u64>())
+                .and_then(|offset| offset.checked_add(length))
+                .filter(|end| *end <= bytes.len())
+                .ok_or(FileError::Invalid)?;
+        }
+        Ok(Names {
+            bytes: &bytes[..offset],
+        })
+    }
+    // encodes names into the start of the given bytes, failing as invalid if they do not fit
+    pub fn encode<'n>(
+        names: impl IntoIterator<Item = &'n [u8]>,
+        out: &'a mut [u8],
+    ) -> Result<Names<'a>, FileError> {
+        let mut count = 0u64;
+        let mut offset = size_of::<u64>();
+        for name in names {
+            let end = offset
+                .checked_add(size_of::<u64>() + name.len())
+                .filter(|end| *end <= out.len())
+                .ok_or(FileError::Invalid)?;
+            out[offset..offset + size_of::<u64>()]
+                .copy_from_slice(&(name.len() as u64).to_le_bytes());
+            out[offset + size_of::<u64>()..end].copy_from_slice(name);
+            offset = end;
+            count += 1;
+        }
+        out.get_mut(..size_of::<u64>())
+            .ok_or(FileError::Invalid)?
+            .copy_from_slice(&count.to_le_bytes());
+        Ok(Names {
+            bytes: &out[..offset],
+        })
+    }
+    // the length names encode to, for sizing the bytes to encode them into
+    pub fn length<'n>(names: impl IntoIterator<Item = &'n [u8]>) -> usize {
+        names
+            .into_iter()
+            .fold(size_of::<u64>(), |length, name| length + size_of::<u64>() + name.len())
+    }
+    // a count of zero and nothing after it
+    pub fn empty() -> Names<'static> {
+        Names { bytes: &[0; 8] }
+    }
+    pub fn as_bytes(&self) -> &'a [u8] {
+        self.bytes
+    }
+    pub fn len(&self) -> usize {
+        word(self.bytes, 0).unwrap_or(0) as usize
+    }
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+    pub fn iter(&self) -> NamesIter<'a> {
+        NamesIter {
+            bytes: self.bytes,
+            offset: size_of::<u64>(),
+            remaining: self.len(),
+        }
+    }
+}
+pub struct NamesIter<'a> {
+    bytes: &'a [u8],
+    offset: usize,
+    remaining: usize,
+}
+impl<'a> Iterator for NamesIter<'a> {
+    type Item = &'a [u8];
+    fn next(&mut self) -> Option<&'a [u8]> {
+        if self.remaining == 0 {
+            return None;
+        }
+        self.remaining -= 1;
+        let length = word(&self.bytes[self.offset..], 0)? as usize;
+        let start = self.offset + size_of::<u64>();
+        self.offset = start + length;
+        Some(&self.bytes[start..self.offset])
+    }
+}
+pub fn word(bytes: &[u8], index: usize) -> Option<u64> {
+    bytes
+        .get(index.checked_mul(size_of::<u64>())?..)?
+        .get(..size_of::<u64>())
+        .map(|word| u64::from_le_bytes(word.try_into().unwrap()))
+}
+pub fn major(version: u64) -> u64 {
+    version >> 32
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    const ALL_STATES: u64 = (1 << STATE_BITS) - 1;
+    // each selector with the bit of a state word it needs, if any, as listed in ABI.md
+    const SELECTOR_BITS: [(Selector, Option<u32>); 28] = [
+        (Selector::ReadState, None),
+        (Selector::WriteState, None),
+        (Selector::Drop, None),
+        (Selector::Walk, Some(0)),
+        (Selector::List, Some(0)),
+        (Selector::ListPeek, Some(0)),
+        (Selector::ListSeekForward, Some(0)),
+        (Selector::ListSeekBackward, Some(0)),
+        (Selector::ListSeekStart, Some(0)),
+        (Selector::ListSeekEnd, Some(0)),
+        (Selector::ListTell, Some(0)),
+        (Selector::Make, Some(2)),
+        (Selector::Remove, Some(3)),
+        (Selector::Rename, Some(1)),
+        (Selector::Read, Some(4)),
+        (Selector::Peek, Some(4)),
+        (Selector::Insert, Some(5)),
+        (Selector::Overwrite, Some(6)),
+        (Selector::Truncate, Some(7)),
+        (Selector::SeekForward, Some(8)),
+        (Selector::SeekBackward, Some(9)),
+        (Selector::SeekStart, Some(10)),
+        (Selector::SeekEnd, Some(11)),
+        (Selector::Bind, Some(2)),
+        (Selector::Unmap, Some(3)),
+        (Selector::Tell, Some(12)),
+        (Selector::Lock, Some(13)),
+        (Selector::Version, None),
+    ];
+    // every selector against every bit, both alone and with every other bit set
+    #[test]
+    fn selectors_are_masked_by_their_bit() {
+        for (selector, needed) in SELECTOR_BITS {
+            for bit in 0..STATE_BITS {
+                let alone = 1 << bit;
+                let without = ALL_STATES & !alone;
+                assert_eq!(
+                    selector.permitted(alone),
+                    needed.is_none_or(|needed| needed == bit),
+                    "{:?} with only bit {}",
+                    selector,
+                    bit
+                );
+                assert_eq!(
+                    selector.permitted(without),
+                    needed != Some(bit),
+                    "{:?} without bit {}",
+                    selector,
+                    bit
+                );
+            }
+            assert!(selector.permitted(ALL_STATES), "{:?} with every bit", selector);
+            assert_eq!(selector.permitted(0), needed.is_none(), "{:?} with no bits", selector);
+        }
+    }
+    #[test]
+    fn selectors_round_trip_through_words() {
+        for (index, (selector, _)) in SELECTOR_BITS.into_iter().enumerate() {
+            assert_eq!(selector as u64, index as u64);
+            assert_eq!(Selector::from_word(index as u64), Some(selector));
+        }
+        assert_eq!(Selector::from_word(SELECTORS.len() as u64), None);
+        assert_eq!(Selector::from_word(u64::MAX), None);
+    }
+    // every word made of state bits, and each field alone in the bit named after it
+    #[test]
+    fn state_words_round_trip() {
+        for word in 0..=ALL_STATES {
+            assert_eq!(state_word(state_from_word(word)), word);
+        }
+        assert_eq!(state_from_word(u64::MAX), State::all());
+        assert_eq!(state_word(State::all()), ALL_STATES);
+        let fields = [
+            (State::new().walk(true), STATE_WALK),
+            (State::new().rename(true), STATE_RENAME),
+            (State::new().make(true), STATE_MAKE),
+            (State::new().remove(true), STATE_REMOVE),
+            (State::new().read(true), STATE_READ),
+            (State::new().insert(true), STATE_INSERT),
+            (State::new().overwrite(true), STATE_OVERWRITE),
+            (State::new().truncate(true), STATE_TRUNCATE),
+            (State::new().seek_forward(true), STATE_SEEK_FORWARD),
+            (State::new().seek_backward(true), STATE_SEEK_BACKWARD),
+            (State::new().seek_start(true), STATE_SEEK_START),
+            (State::new().seek_end(true), STATE_SEEK_END),
+            (State::new().tell(true), STATE_TELL),
+            (State::new().lock(true), STATE_LOCK),
+        ];
+        for (state, bit) in fields {
+            assert_eq!(state_word(state), 1 << bit, "{:?}", state);
+        }
+    }
+    #[test]
+    fn state_masks_combine_bit_by_bit() {
+        let read = State::new().read(true).seek(true);
+        let write = State::new().overwrite(true).seek(true);
+        assert_eq!(read & write, State::new().seek(true));
+        assert_eq!(state_word(read | write), state_word(read) | state_word(write));
+    }
+    #[test]
+    fn protocols_round_trip_and_ignore_appended_fields() {
+        for protocol in [Protocol::current(), Protocol::legacy()] {
+            let bytes = protocol.encode();
+            assert_eq!(Protocol::parse(&bytes), Ok(protocol));
+            let mut longer = bytes.to_vec();
+            longer.extend(u64::MAX.to_le_bytes());
+            assert_eq!(Protocol::parse(&longer), Ok(protocol));
+            assert_eq!(Protocol::parse(&bytes[..PROTOCOL_SIZE - 1]), Err(FileError::Invalid));
+        }
+        assert!(Protocol::current().supports(Selector::Version));
+        assert!(!Protocol::legacy().supports(Selector::Version));
+        assert!(Protocol::legacy().supports(Selector::Lock));
+    }
+    #[test]
+    fn names_round_trip_and_must_fit() {
+        let names: [&[u8]; 3] = [b"one", b"", b"three"];
+        let mut out = vec![0; Names::length(names)];
+        let encoded = Names::encode(names, &mut out).expect("names did not fit their length");
+        assert_eq!(encoded.iter().collect::<Vec<_>>(), names);
+        let bytes = encoded.as_bytes().to_vec();
+        assert_eq!(Names::parse(&bytes).map(|names| names.len()), Ok(3));
+        assert_eq!(Names::parse(&bytes[..bytes.len() - 1]), Err(FileError::Invalid));
+        let mut short = vec![0; Names::length(names) - 1];
+        assert_eq!(Names::encode(names, &mut short), Err(FileError::Invalid));
+        assert!(Names::empty().is_empty());
+    }
+    #[test]
+    fn error_codes_round_trip_and_unknown_codes_are_incompatible() {
+        for code in 1..=8 {
+            let error = FileError::from_code(code).expect("known error code did not decode");
+            assert_eq!(error.code(), code);
+        }
+        assert_eq!(FileError::from_code(STATUS_OK), None);
+        assert_eq!(FileError::from_code(9), None);
+        assert_eq!(FileError::from_status(9), FileError::Incompatible);
+    }
+}